@@ -0,0 +1,811 @@
+// Helpers for working with .bapple files outside of playback.
+// A .bapple file is just a tar archive with:
+//   - one zstd-compressed entry per frame, played in archive order
+//   - an optional `poster.zst` entry, a frame to show while the rest loads
+//   - an optional `audio.mp3` entry
+//   - an optional `metadata.ron` entry
+//   - optional `subs.<lang>.srt` entries, one per subtitle track
+//   - an optional `dictionary` entry, a zstd dictionary the frames were
+//     compressed against, before any of them
+//   - an optional `manifest.ron` entry, the hashes of everything else,
+//     last (see `manifest`)
+//   - an optional `manifest.sig` entry after it, an ed25519 signature
+//     over the manifest (see `signature`)
+//   - an optional `index.ron` entry, where all of the others are in the
+//     file, at the very end (see `remote`)
+// Encrypted ones are different altogether, see `encryption`.
+// Some packers sort entries into directories instead, which works too:
+// anything under `frames/` is a frame, the file under `audio/` (whatever
+// it's called) is the audio, and `subs/<lang>.srt` are subtitle tracks.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use tar::{Archive, Builder, Entry, EntryType, Header};
+
+use crate::{
+    Res,
+    encryption::is_encrypted,
+    frames::{Dictionary, decompress},
+    manifest::{Hashed, Hashing, Manifest},
+    metadata::Metadata,
+    remote::{Index, Indexed},
+    subtitles::Subtitles,
+    timeline::Timeline,
+};
+
+/// Where reading a whole archive gives up, so a hostile (or just
+/// enormous) file fails right away instead of running out of memory.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub entries: usize,
+    /// Compressed, adding up every entry.
+    pub bytes: u64,
+}
+
+impl Default for Limits {
+    /// About 4 hours at 60 FPS, in up to 4 GB.
+    fn default() -> Self {
+        Self { entries: 1_000_000, bytes: 4 * 1024 * 1024 * 1024 }
+    }
+}
+
+impl Limits {
+    /// Counts one more entry of `size` bytes, along with the `read` so
+    /// far, failing once either adds up to too much.
+    pub fn check(&self, read: &mut (usize, u64), size: u64) -> Res<()> {
+        read.0 += 1;
+        read.1 = read.1.saturating_add(size);
+        if read.0 > self.entries {
+            let entries = self.entries;
+            return Err(format!(
+                "The archive has more than {entries} entries \
+                 (--max-entries raises the limit)"
+            )
+            .into());
+        }
+        if read.1 > self.bytes {
+            return Err(self.too_big().into());
+        }
+        Ok(())
+    }
+
+    /// Why an archive bigger than `bytes` gets refused.
+    pub fn too_big(&self) -> String {
+        let megabytes = self.bytes.div_ceil(1024 * 1024);
+        format!(
+            "The archive holds more than {megabytes} MB \
+             (--max-archive-size raises the limit)"
+        )
+    }
+}
+
+/// Everything in an archive but its frames, which only get counted, for
+/// tools that take archives apart and put them back together.
+pub struct Contents {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    /// Played, so repeats count.
+    pub frames: usize,
+    /// Which stored frame each played one is, if any are repeated.
+    map: Option<Vec<usize>>,
+    /// How long each frame lasts at normal speed, in microseconds.
+    pub frametimes: Vec<u64>,
+    /// How long it plays for, speed regions and all.
+    pub duration: Duration,
+    pub audio: Option<Vec<u8>>,
+    pub subtitles: Vec<(String, Subtitles)>,
+    pub poster: Option<Vec<u8>>,
+    /// What the frames were compressed against, if anything.
+    pub dictionary: Option<Vec<u8>>,
+}
+
+impl Contents {
+    pub fn read(path: &Path) -> Res<Self> {
+        let mut archive = Archive::new(File::open(path)?);
+        let mut metadata = Metadata::default();
+        let mut frames = 0;
+        let mut audio = None;
+        let mut subtitles = Vec::new();
+        let mut poster = None;
+        let mut dictionary = None;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let Some(name) = entry_name(&entry)? else {
+                continue;
+            };
+            if is_frame(&name) {
+                frames += 1;
+                continue;
+            }
+            if is_encryption(&name) {
+                return Err(format!(
+                    "{}: is encrypted, which this can't be done to",
+                    path.display()
+                )
+                .into());
+            }
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            if is_metadata(&name) {
+                metadata = Metadata::parse(&name, &content).map_err(|e| {
+                    format!("{}: invalid metadata: {e}", path.display())
+                })?;
+            } else if is_poster(&name) {
+                poster = Some(content);
+            } else if is_dictionary(&name) {
+                dictionary = Some(content);
+            } else if let Some(language) = subtitle_language(&name) {
+                let track =
+                    Subtitles::parse(&String::from_utf8_lossy(&content));
+                subtitles.push((language.to_string(), track));
+            } else if is_audio(&name) {
+                audio = Some(content);
+            }
+        }
+
+        // Fields this version doesn't know would get dropped.
+        if metadata.is_newer() {
+            return Err(format!(
+                "{}: made by a newer version of bplay, which this one can't \
+                 rewrite without losing things",
+                path.display()
+            )
+            .into());
+        }
+        if frames == 0 {
+            return Err(format!("{}: has no frames", path.display()).into());
+        }
+        let map = metadata.frame_map(frames, Limits::default())?;
+        let frames = map.as_ref().map_or(frames, Vec::len);
+        let frametimes = if metadata.frametimes.len() == frames {
+            metadata.frametimes.clone()
+        } else if metadata.effective_frametime() != 0 {
+            vec![metadata.effective_frametime(); frames]
+        } else {
+            return Err(format!(
+                "{}: doesn't say how long its frames last, which can be set \
+                 with `bplay meta set frametime`",
+                path.display()
+            )
+            .into());
+        };
+        let duration = Self::timeline_of(&metadata, &frametimes).duration();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            metadata,
+            frames,
+            map,
+            frametimes,
+            duration,
+            audio,
+            subtitles,
+            poster,
+            dictionary,
+        })
+    }
+
+    pub fn timeline(&self) -> Timeline {
+        Self::timeline_of(&self.metadata, &self.frametimes)
+    }
+
+    fn timeline_of(metadata: &Metadata, frametimes: &[u64]) -> Timeline {
+        Timeline::new(0, frametimes, &metadata.speeds, frametimes.len())
+    }
+
+    /// Its title, or failing that, its file name.
+    pub fn title(&self) -> String {
+        self.metadata.title.clone().unwrap_or_else(|| {
+            let stem = self.path.file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        })
+    }
+
+    /// Goes through every compressed frame, in order, repeats and all.
+    pub fn for_each_frame(
+        &self,
+        mut f: impl FnMut(usize, &[u8]) -> Res<()>,
+    ) -> Res<()> {
+        let mut archive = Archive::new(File::open(&self.path)?);
+        let (mut stored, mut index) = (0, 0);
+        let mut frame = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry_name(&entry)?.is_some_and(|name| is_frame(&name)) {
+                frame.clear();
+                entry.read_to_end(&mut frame)?;
+                loop {
+                    f(index, &frame)?;
+                    index += 1;
+                    let next = self.map.as_ref().and_then(|map| map.get(index));
+                    if next != Some(&stored) {
+                        break;
+                    }
+                }
+                stored += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ArchiveWriter<W: Write> {
+    builder: Builder<Counting<W>>,
+    frames: usize,
+    /// Everything written so far, for the manifest.
+    hashes: Vec<Hashed>,
+    /// And where it all went, for the index.
+    index: Vec<Indexed>,
+}
+
+/// A writer that counts what goes through it.
+struct Counting<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl ArchiveWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            builder: Builder::new(Counting { inner, written: 0 }),
+            frames: 0,
+            hashes: Vec::new(),
+            index: Vec::new(),
+        }
+    }
+
+    /// Appends an already compressed frame.
+    pub fn append_frame(&mut self, compressed: &[u8]) -> io::Result<()> {
+        let name = format!("{:06}.zst", self.frames);
+        self.append(&name, compressed)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Goes first, so players find it without reading everything else.
+    pub fn append_poster(&mut self, compressed: &[u8]) -> io::Result<()> {
+        self.append("poster.zst", compressed)
+    }
+
+    /// Has to go before the frames compressed against it.
+    pub fn append_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.append("dictionary", dictionary)
+    }
+
+    pub fn append_audio(&mut self, mp3: &[u8]) -> io::Result<()> {
+        self.append("audio.mp3", mp3)
+    }
+
+    /// `language` is whatever tells the tracks apart, like `en`.
+    pub fn append_subtitles(
+        &mut self,
+        language: &str,
+        srt: &[u8],
+    ) -> io::Result<()> {
+        self.append(&format!("subs.{language}.srt"), srt)
+    }
+
+    pub fn append_metadata(&mut self, metadata: &Metadata) -> Res<()> {
+        let serialized = ron::to_string(metadata)?;
+        self.append("metadata.ron", serialized.as_bytes())?;
+        Ok(())
+    }
+
+    /// The hashes of everything written before it, so it goes last.
+    pub fn append_manifest(&mut self) -> Res<()> {
+        let manifest = Manifest::new(std::mem::take(&mut self.hashes));
+        let serialized = ron::to_string(&manifest)?;
+        self.append("manifest.ron", serialized.as_bytes())?;
+        Ok(())
+    }
+
+    /// Has to go after the manifest it's over.
+    pub fn append_signature(&mut self, signature: &[u8]) -> io::Result<()> {
+        self.append("manifest.sig", signature)
+    }
+
+    /// Copies an entry over as-is, without counting it as a frame.
+    pub fn append_raw(
+        &mut self,
+        name: &Path,
+        size: u64,
+        data: impl Read,
+    ) -> io::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        let mut hashing = Hashing::new(data);
+        self.builder.append_data(&mut header, name, &mut hashing)?;
+        self.hashes.push(hashing.finish(name));
+        // The data's padded out to a whole block.
+        let end = self.builder.get_ref().written;
+        self.index.push(Indexed {
+            name: name.to_string_lossy().into_owned(),
+            offset: end - size.next_multiple_of(512),
+            size,
+        });
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Writes the index, last so it's easy to find, and the end of the
+    /// archive.
+    pub fn finish(mut self) -> io::Result<()> {
+        let index = Index { entries: std::mem::take(&mut self.index) };
+        let serialized = ron::to_string(&index).map_err(io::Error::other)?;
+        self.append("index.ron", serialized.as_bytes())?;
+        self.builder.into_inner()?.flush()
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.append_raw(Path::new(name), data.len() as u64, data)
+    }
+}
+
+pub fn is_metadata(name: &Path) -> bool {
+    name.file_stem().is_some_and(|stem| stem == "metadata")
+}
+
+/// The language of a `subs.<lang>.srt` (or `subs/<lang>.srt`) entry,
+/// `None` for anything else.
+pub fn subtitle_language(name: &Path) -> Option<&str> {
+    let file_name = name.file_name()?.to_str()?;
+    let language = if directory(name) == Some(OsStr::new("subs")) {
+        file_name.strip_suffix(".srt")?
+    } else {
+        file_name.strip_prefix("subs.")?.strip_suffix(".srt")?
+    };
+    (!language.is_empty()).then_some(language)
+}
+
+pub fn is_audio(name: &Path) -> bool {
+    directory(name) == Some(OsStr::new("audio"))
+        || name.file_stem().is_some_and(|stem| stem == "audio")
+}
+
+/// The directory an entry is in, if it's in one.
+fn directory(name: &Path) -> Option<&OsStr> {
+    name.parent()?.file_name()
+}
+
+pub fn is_poster(name: &Path) -> bool {
+    name.file_stem().is_some_and(|stem| stem == "poster")
+}
+
+pub fn is_dictionary(name: &Path) -> bool {
+    name.file_stem().is_some_and(|stem| stem == "dictionary")
+}
+
+/// Only right at the top, where it gets written, like the signature and
+/// the index. They're the entries left out of the manifest, and anything
+/// else named like them (`frames/index.zst`) gets played, so it has to be
+/// hashed.
+pub fn is_manifest(name: &Path) -> bool {
+    name == Path::new("manifest.ron")
+}
+
+/// The header and payload of an encrypted file.
+pub fn is_encryption(name: &Path) -> bool {
+    name.file_stem().is_some_and(|stem| stem == "encryption")
+}
+
+pub fn is_index(name: &Path) -> bool {
+    name == Path::new("index.ron")
+}
+
+pub fn is_signature(name: &Path) -> bool {
+    name == Path::new("manifest.sig")
+}
+
+/// An entry's name, or `None` for directories (and the headers some tar
+/// tools put in), which there's nothing to read from. Nothing gets written
+/// out from an archive, but an entry that would land outside of it if it
+/// were (an absolute path or one with `..` in it), or that's a link or a
+/// device, has no business being in one, so the whole archive is refused.
+pub fn entry_name<R: Read>(entry: &Entry<'_, R>) -> Res<Option<PathBuf>> {
+    let name = entry.path()?.into_owned();
+    let kind = entry.header().entry_type();
+    if kind.is_dir() || kind.is_pax_global_extensions() {
+        return Ok(None);
+    }
+    check_inside(&name)?;
+    if !matches!(kind, EntryType::Regular | EntryType::Continuous) {
+        return Err(format!(
+            "The archive has an entry that isn't a regular file: {}",
+            name.display()
+        )
+        .into());
+    }
+    Ok(Some(name))
+}
+
+/// Refuses a name that would land outside of the archive, if it got
+/// written out: an absolute path, or one with `..` in it.
+pub fn check_inside(name: &Path) -> Res<()> {
+    let escapes = name.components().any(|component| {
+        matches!(
+            component,
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir
+        )
+    });
+    if escapes {
+        return Err(format!(
+            "The archive has an entry outside of it: {}",
+            name.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Anything under `frames/`, or anywhere else if it isn't audio,
+/// metadata, subtitles, the poster, the dictionary, the manifest, its
+/// signature or what an encrypted file has instead.
+pub fn is_frame(name: &Path) -> bool {
+    directory(name) == Some(OsStr::new("frames"))
+        || !is_metadata(name)
+            && !is_poster(name)
+            && !is_audio(name)
+            && !is_dictionary(name)
+            && !is_manifest(name)
+            && !is_signature(name)
+            && !is_encryption(name)
+            && !is_index(name)
+            && subtitle_language(name).is_none()
+}
+
+/// The decompressed poster, skipping over everything else to find it,
+/// up to `max_size` bytes of it.
+pub fn read_poster(path: &Path, max_size: usize) -> Res<Option<Vec<u8>>> {
+    let mut archive = Archive::new(File::open(path)?);
+
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        if entry_name(&entry)?.is_some_and(|name| is_poster(&name)) {
+            // Never compressed against the dictionary, to be there first.
+            let none = Dictionary::default();
+            return Ok(Some(decompress(entry, &none, max_size)?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The dictionary the frames were compressed against, if there is one.
+pub fn read_dictionary(path: &Path) -> Res<Dictionary> {
+    let mut archive = Archive::new(File::open(path)?);
+
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        if entry_name(&entry)?.is_some_and(|name| is_dictionary(&name)) {
+            let mut dictionary = Vec::new();
+            entry.read_to_end(&mut dictionary)?;
+            return Ok(Dictionary::new(&dictionary));
+        }
+    }
+
+    Ok(Dictionary::default())
+}
+
+/// The audio entry as it's stored, skipping over everything else to find
+/// it.
+pub fn read_audio(path: &Path) -> Res<Option<Vec<u8>>> {
+    let mut archive = Archive::new(File::open(path)?);
+
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        if entry_name(&entry)?.is_some_and(|name| is_audio(&name)) {
+            let mut audio = Vec::new();
+            entry.read_to_end(&mut audio)?;
+            return Ok(Some(audio));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Errors out on encrypted files, since there's nothing in them to get at
+/// without the password.
+pub fn refuse_encrypted(path: &Path) -> Res<()> {
+    if is_encrypted(path)? {
+        return Err(format!(
+            "{}: is encrypted, which this can't be done to",
+            path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads only the metadata entry. `None` if the file doesn't have one.
+pub fn read_metadata(path: &Path) -> Res<Option<Metadata>> {
+    let mut archive = Archive::new(File::open(path)?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        if !is_metadata(&name) {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        let metadata = Metadata::parse(&name, &content).map_err(|e| {
+            format!("{}: invalid metadata: {e}", path.display())
+        })?;
+        return Ok(Some(metadata));
+    }
+
+    Ok(None)
+}
+
+/// How long the frames play for, skipping over them instead of reading
+/// them. `None` without any frames, or a framerate to go by.
+pub fn duration(path: &Path) -> Res<Option<Duration>> {
+    let mut archive = Archive::new(File::open(path)?);
+    let mut metadata = Metadata::default();
+    let mut frames = 0;
+
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        if is_metadata(&name) {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            metadata = Metadata::parse(&name, &content).unwrap_or_default();
+        } else if is_frame(&name) {
+            frames += 1;
+        }
+    }
+
+    let framerate =
+        metadata.effective_frametime() != 0 || !metadata.frametimes.is_empty();
+    if frames == 0 || !framerate {
+        return Ok(None);
+    }
+    let timeline = Timeline::new(
+        metadata.effective_frametime(),
+        &metadata.frametimes,
+        &metadata.speeds,
+        metadata.played(frames, Limits::default())?,
+    );
+    Ok(Some(timeline.duration()))
+}
+
+/// The first frame, decompressed, if it's no more than `max_size` bytes.
+/// `None` if there aren't any frames.
+pub fn first_frame(path: &Path, max_size: usize) -> Res<Option<Vec<u8>>> {
+    let mut archive = Archive::new(File::open(path)?);
+    let mut dictionary = Dictionary::default();
+
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        if is_dictionary(&name) {
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            dictionary = Dictionary::new(&bytes);
+        } else if is_frame(&name) {
+            return Ok(Some(decompress(entry, &dictionary, max_size)?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Replaces the metadata entry (or adds one), copying everything else
+/// over untouched. Goes through a temporary file, so a failure halfway
+/// through doesn't leave a broken archive behind.
+pub fn write_metadata(path: &Path, metadata: &Metadata) -> Res<()> {
+    let replaced = Replaced { metadata: Some(metadata), ..Default::default() };
+    replace(path, path, &replaced)
+}
+
+/// The same for the audio entry, and the metadata too if there's any,
+/// writing to `output`, which can be `path` itself.
+pub fn write_audio(
+    path: &Path,
+    output: &Path,
+    mp3: &[u8],
+    metadata: Option<&Metadata>,
+) -> Res<()> {
+    let replaced = Replaced { metadata, audio: Some(mp3), signature: None };
+    replace(path, output, &replaced)
+}
+
+/// Signs the manifest with `signature`, writing to `output`, which can be
+/// `path` itself.
+pub fn write_signature(
+    path: &Path,
+    output: &Path,
+    signature: &[u8],
+) -> Res<()> {
+    let replaced =
+        Replaced { signature: Some(signature), ..Default::default() };
+    replace(path, output, &replaced)
+}
+
+/// What gets swapped for something new when rewriting.
+#[derive(Default)]
+struct Replaced<'a> {
+    metadata: Option<&'a Metadata>,
+    audio: Option<&'a [u8]>,
+    signature: Option<&'a [u8]>,
+}
+
+fn replace(path: &Path, output: &Path, replaced: &Replaced) -> Res<()> {
+    let temp = temp_path(output);
+    let result = rewrite(path, &temp, replaced);
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+
+    fs::rename(&temp, output)?;
+    Ok(())
+}
+
+fn rewrite(path: &Path, temp: &Path, replaced: &Replaced) -> Res<()> {
+    refuse_encrypted(path)?;
+    let mut archive = Archive::new(File::open(path)?);
+    let mut writer = ArchiveWriter::create(temp)?;
+    let mut manifest = false;
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        // Written again at the end.
+        if is_index(&name) {
+            continue;
+        }
+        // Once anything changes, the manifest gets made again at the end
+        // and nobody signed that one.
+        if is_signature(&name)
+            || is_manifest(&name) && replaced.signature.is_none()
+        {
+            manifest |= is_manifest(&name);
+            continue;
+        }
+        let skipped = (replaced.metadata.is_some() && is_metadata(&name))
+            || (replaced.audio.is_some() && is_audio(&name));
+        if !skipped {
+            writer.append_raw(&name, entry.size(), entry)?;
+        }
+    }
+
+    if let Some(mp3) = replaced.audio {
+        writer.append_audio(mp3)?;
+    }
+    if let Some(metadata) = replaced.metadata {
+        writer.append_metadata(metadata)?;
+    }
+    if manifest {
+        writer.append_manifest()?;
+    }
+    if let Some(signature) = replaced.signature {
+        writer.append_signature(signature)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = OsString::from(path.as_os_str());
+    temp.push(".tmp");
+    temp.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_entries_and_bytes() {
+        let limits = Limits { entries: 2, bytes: 100 };
+        let mut read = (0, 0);
+        limits.check(&mut read, 60).unwrap();
+        limits.check(&mut read, 40).unwrap();
+        assert_eq!(read, (2, 100));
+        assert!(limits.check(&mut read, 0).is_err());
+
+        let mut read = (0, 0);
+        limits.check(&mut read, 60).unwrap();
+        let error = limits.check(&mut read, 41).unwrap_err();
+        assert!(error.to_string().contains("more than 1 MB"));
+    }
+
+    #[test]
+    fn adding_up_bytes_cant_overflow() {
+        let limits = Limits::default();
+        let mut read = (0, u64::MAX - 1);
+        assert!(limits.check(&mut read, u64::MAX).is_err());
+        assert_eq!(read.1, u64::MAX);
+    }
+
+    /// Entries as is, since `tar` won't write names like `../evil`.
+    fn archive(entries: &[(&[u8], EntryType)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for &(name, kind) in entries {
+            let mut header = Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name);
+            header.set_entry_type(kind);
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, io::empty()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn names(archive: &[u8]) -> Res<Vec<PathBuf>> {
+        let mut archive = Archive::new(archive);
+        let mut names = Vec::new();
+        for entry in archive.entries()? {
+            names.extend(entry_name(&entry?)?);
+        }
+        Ok(names)
+    }
+
+    #[test]
+    fn skips_directories() {
+        let archive = archive(&[
+            (b"frames/", EntryType::Directory),
+            (b"frames/000000.zst", EntryType::Regular),
+        ]);
+        assert_eq!(names(&archive).unwrap(), [Path::new("frames/000000.zst")]);
+    }
+
+    #[test]
+    fn refuses_entries_outside_of_it() {
+        for name in [&b"../evil"[..], b"frames/../../evil", b"/etc/evil"] {
+            let archive = archive(&[(name, EntryType::Regular)]);
+            let error = names(&archive).unwrap_err().to_string();
+            assert!(error.contains("outside of it"), "{error}");
+        }
+    }
+
+    #[test]
+    fn refuses_links_and_devices() {
+        for kind in [EntryType::Symlink, EntryType::Link, EntryType::Char] {
+            let archive = archive(&[(b"000000.zst", kind)]);
+            let error = names(&archive).unwrap_err().to_string();
+            assert!(error.contains("isn't a regular file"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// `bplay audio`, for getting at the audio of a .bapple file on its own.
+
+use std::{fs, path::PathBuf};
+
+use clap::Subcommand;
+
+use crate::{Res, archive::read_audio};
+
+#[derive(Subcommand, Debug)]
+pub enum AudioCommand {
+    /// Copy the audio out, byte for byte, as the MP3 it's stored as
+    Extract {
+        /// Path to a .bapple file
+        file: PathBuf,
+        /// Where to write it
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+pub fn run(command: &AudioCommand) -> Res<()> {
+    match command {
+        AudioCommand::Extract { file, output } => {
+            let audio = read_audio(file)?
+                .ok_or_else(|| format!("{} has no audio", file.display()))?;
+            fs::write(output, audio)
+                .map_err(|e| format!("{}: {e}", output.display()))?;
+        }
+    }
+    Ok(())
+}
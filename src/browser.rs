@@ -0,0 +1,321 @@
+// `bplay` without a file, or with `--browse`: a list of the .bapple files
+// in a directory, with how long each one is, to pick one to play. The
+// highlighted one gets its first frame shown next to the list, read the
+// first time it's highlighted and kept around after that.
+
+use std::{
+    cell::OnceCell,
+    fs,
+    io::{self, stdout},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor::{Hide, Show},
+    event::{KeyCode, KeyModifiers},
+    execute,
+    terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    Frame, Terminal,
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, List, ListState, Paragraph},
+};
+
+use crate::{
+    Res, archive,
+    controls::Keyboard,
+    history::{self, History},
+    render::{strip_escapes, to_ascii},
+    terminal::{Capabilities, ColorDepth},
+    timeline::format_time,
+    tui::parse_frame,
+};
+
+/// How far Page Up and Page Down move.
+const PAGE: u16 = 10;
+
+/// How many of the latest files in the history get shown.
+const RECENT: usize = 9;
+
+const HINT: &str = " Up/Down to choose, Enter to play, q to quit";
+const RECENT_HINT: &str =
+    " Up/Down to choose, Enter to play, 1-9 for a recent one, q to quit";
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    /// `None` if it couldn't be worked out.
+    duration: Option<Duration>,
+    /// `None` inside if there's nothing to show.
+    preview: OnceCell<Option<Preview>>,
+}
+
+/// A frame as a grid of styled characters, so it can be shrunk to fit.
+struct Preview {
+    rows: Vec<Vec<(char, Style)>>,
+}
+
+impl Preview {
+    /// Gets the first frame ready the same way playback would.
+    fn load(
+        path: &Path,
+        capabilities: Capabilities,
+        max_frame_size: usize,
+    ) -> Option<Self> {
+        let mut frame = archive::first_frame(path, max_frame_size).ok()??;
+        if capabilities.color == ColorDepth::None {
+            frame = strip_escapes(&frame);
+        }
+        if !capabilities.unicode {
+            frame = to_ascii(&frame);
+        }
+
+        let rows = parse_frame(&frame)
+            .lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .flat_map(|span| {
+                        span.content.chars().map(|c| (c, span.style))
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(Self { rows })
+    }
+
+    /// Skips rows and columns until the whole frame fits in `area`. Both
+    /// get skipped at the same rate, so it keeps its shape.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn fit(&self, area: Rect) -> Text<'static> {
+        let width = self.rows.iter().map(Vec::len).max().unwrap_or_default();
+        let step = (width as f64 / f64::from(area.width))
+            .max(self.rows.len() as f64 / f64::from(area.height))
+            .max(1.0);
+        let sample = move |len: usize| {
+            (0..)
+                .map(move |i| (f64::from(i) * step) as usize)
+                .take_while(move |&index| index < len)
+        };
+
+        sample(self.rows.len())
+            .map(|row| {
+                let row = &self.rows[row];
+                sample(row.len())
+                    .map(|column| {
+                        let (c, style) = row[column];
+                        Span::styled(c.to_string(), style)
+                    })
+                    .collect::<Line>()
+            })
+            .collect()
+    }
+}
+
+/// What got picked: a file, and where to start it.
+pub struct Picked {
+    pub path: PathBuf,
+    /// Where it was left off, for files picked from the history.
+    pub start: Duration,
+}
+
+struct Browser {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    /// The latest few from the history, picked with the number keys.
+    recent: Vec<history::Entry>,
+    capabilities: Capabilities,
+    max_frame_size: usize,
+}
+
+/// Shows the picker, `None` if nothing got picked. Previews are refused
+/// past `max_frame_size` bytes, the same as playback.
+pub fn pick(
+    dir: &Path,
+    capabilities: Capabilities,
+    max_frame_size: usize,
+) -> Res<Option<Picked>> {
+    let entries = list(dir)?;
+    let recent: Vec<_> = History::load()
+        .entries
+        .into_iter()
+        .filter(|entry| entry.path.is_file())
+        .take(RECENT)
+        .collect();
+    if entries.is_empty() && recent.is_empty() {
+        return Err(format!("No .bapple files in {}", dir.display()).into());
+    }
+    let keyboard =
+        Keyboard::new().ok_or("Picking a file needs a terminal to do it in")?;
+
+    let browser = Browser {
+        // The full path makes a better title than `.` does.
+        dir: fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()),
+        entries,
+        recent,
+        capabilities,
+        max_frame_size,
+    };
+    execute!(stdout(), EnterAlternateScreen, Clear(ClearType::All), Hide)?;
+    let picked = browser.run(&keyboard);
+    execute!(stdout(), Show, LeaveAlternateScreen)?;
+    Ok(picked?)
+}
+
+/// Every .bapple file in `dir`, by name.
+fn list(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file()
+            || path.extension().is_none_or(|extension| extension != "bapple")
+        {
+            continue;
+        }
+
+        let name = file_name(&path);
+        // A broken file still shows up, it fails once it's picked.
+        let duration = archive::duration(&path).ok().flatten();
+        entries.push(Entry { path, name, duration, preview: OnceCell::new() });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+impl Browser {
+    fn run(&self, keyboard: &Keyboard) -> io::Result<Option<Picked>> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        let mut state = ListState::default().with_selected(Some(0));
+
+        loop {
+            terminal.draw(|screen| self.draw(screen, &mut state))?;
+            let Some(key) = keyboard.read_key() else {
+                return Ok(None);
+            };
+
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                // Windows raw mode turns Ctrl+C into a key press.
+                KeyCode::Char('c') if control => return Ok(None),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+                KeyCode::Home | KeyCode::Char('g') => state.select_first(),
+                KeyCode::End | KeyCode::Char('G') => state.select_last(),
+                KeyCode::PageUp => state.scroll_up_by(PAGE),
+                KeyCode::PageDown => state.scroll_down_by(PAGE),
+                KeyCode::Enter => {
+                    // Drawing keeps the selection in range.
+                    let entry =
+                        state.selected().and_then(|i| self.entries.get(i));
+                    if let Some(entry) = entry {
+                        return Ok(Some(Picked {
+                            path: entry.path.clone(),
+                            start: Duration::ZERO,
+                        }));
+                    }
+                }
+                KeyCode::Char(c @ '1'..='9') => {
+                    let index = usize::from(c as u8 - b'1');
+                    if let Some(entry) = self.recent.get(index) {
+                        return Ok(Some(Picked {
+                            path: entry.path.clone(),
+                            start: entry.position,
+                        }));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&self, screen: &mut Frame, state: &mut ListState) {
+        let [main, hint] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)])
+                .areas(screen.area());
+        let [left, preview_area] = Layout::horizontal([
+            Constraint::Percentage(40),
+            Constraint::Percentage(60),
+        ])
+        .areas(main);
+
+        let recent_height = match self.recent.len() {
+            0 => 0,
+            len => u16::try_from(len).unwrap_or(u16::MAX) + 2,
+        };
+        let [recent_area, list_area] = Layout::vertical([
+            Constraint::Length(recent_height),
+            Constraint::Min(3),
+        ])
+        .areas(left);
+
+        if !self.recent.is_empty() {
+            let recent = (1..).zip(&self.recent).map(|(number, entry)| {
+                let name = file_name(&entry.path);
+                if entry.position.is_zero() {
+                    format!("{number}  {name}")
+                } else {
+                    format!(
+                        "{number}  {name}  at {}",
+                        format_time(entry.position)
+                    )
+                }
+            });
+            screen.render_widget(
+                List::new(recent).block(Block::bordered().title("Recent")),
+                recent_area,
+            );
+        }
+
+        let width = self.entries.iter().map(|e| e.name.chars().count()).max();
+        let width = width.unwrap_or_default();
+        let items = self.entries.iter().map(|entry| {
+            let duration =
+                entry.duration.map_or("--:--".to_string(), format_time);
+            format!("{:<width$}  {duration:>8}", entry.name)
+        });
+        let list = List::new(items)
+            .block(Block::bordered().title(self.dir.display().to_string()))
+            .highlight_style(Modifier::REVERSED);
+        // Drawing the list first keeps the selection in range.
+        screen.render_stateful_widget(list, list_area, state);
+
+        let block = Block::bordered().title("Preview");
+        let inner = block.inner(preview_area);
+        screen.render_widget(block, preview_area);
+        let preview =
+            state.selected().and_then(|index| self.entries.get(index));
+        let preview = preview.and_then(|entry| {
+            entry
+                .preview
+                .get_or_init(|| {
+                    Preview::load(
+                        &entry.path,
+                        self.capabilities,
+                        self.max_frame_size,
+                    )
+                })
+                .as_ref()
+        });
+        let text = preview
+            .map_or_else(|| Text::from("No frames to show"), |p| p.fit(inner));
+        screen.render_widget(Paragraph::new(text), inner);
+
+        let keys = if self.recent.is_empty() { HINT } else { RECENT_HINT };
+        screen.render_widget(Paragraph::new(keys), hint);
+    }
+}
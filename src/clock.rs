@@ -0,0 +1,266 @@
+// Where playback gets its time from. Normally that's the real thing,
+// but anything driving the loop without a terminal (tests, offline
+// rendering) can swap in a clock that doesn't actually wait. The time of
+// day, for showing it and for starting along with other machines, is down
+// at the bottom.
+
+use std::{
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub trait Clock {
+    /// Time since some fixed point, which one doesn't matter.
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+/// How much of each sleep gets spun away when timing precisely,
+/// about as coarse as the OS scheduler gets.
+const SPIN: Duration = Duration::from_millis(1);
+/// How many frames it takes the playhead to make up a difference from
+/// the audio, so it gets there without a visible jump.
+const SLEW_FRAMES: f64 = 8.0;
+/// Differences bigger than this get made up all at once, since they'd
+/// take too long to get rid of smoothly, like after the audio stalled.
+const SNAP: Duration = Duration::from_secs(1);
+
+pub struct SystemClock {
+    origin: Instant,
+    /// Spin through the end of each sleep, since the OS tends to
+    /// oversleep. Burns a bit of CPU for smoother pacing.
+    precise: bool,
+    #[cfg(windows)]
+    _resolution: TimerResolution,
+}
+
+impl SystemClock {
+    pub fn new(precise: bool) -> Self {
+        Self {
+            origin: Instant::now(),
+            precise,
+            #[cfg(windows)]
+            _resolution: TimerResolution::new(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        if !self.precise {
+            thread::sleep(duration);
+            return;
+        }
+
+        let deadline = Instant::now() + duration;
+        thread::sleep(duration.saturating_sub(SPIN));
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Only moves when it's told to, sleeping included, so tests can play a
+/// file as fast as it decodes and still know when everything happened.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeClock {
+    now: std::cell::Cell<Duration>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Where in the file playback is, which frames get scheduled against.
+/// It runs on a `Clock`, but when there's audio that's the master, and
+/// the playhead gets nudged towards it a bit every frame.
+pub struct Playhead {
+    /// Where in the file it was at `since`.
+    position: Duration,
+    since: Duration,
+}
+
+impl Playhead {
+    pub fn new(position: Duration, now: Duration) -> Self {
+        Self { position, since: now }
+    }
+
+    pub fn at(&self, now: Duration) -> Duration {
+        self.position + now.saturating_sub(self.since)
+    }
+
+    /// Goes straight to `position`, for seeking.
+    pub fn set(&mut self, position: Duration, now: Duration) {
+        *self = Self::new(position, now);
+    }
+
+    /// Leaves out time spent paused.
+    pub fn pause(&mut self, paused: Duration) {
+        self.since += paused;
+    }
+
+    /// Makes up part of the difference from where the audio is, or all of
+    /// it if it's too big. `true` when it had to jump.
+    pub fn follow(&mut self, audio: Duration, now: Duration) -> bool {
+        let at = self.at(now);
+        let difference = at.abs_diff(audio);
+        let snap = difference > SNAP;
+        let step = difference.div_f64(SLEW_FRAMES);
+        self.position = if snap {
+            audio
+        } else if audio > at {
+            at + step
+        } else {
+            // Never past the audio, so it can't go below zero.
+            at.saturating_sub(step)
+        };
+        self.since = now;
+        snap
+    }
+}
+
+/// Windows wakes sleeping threads every 15.6ms by default, which makes
+/// 30-60fps stutter. This asks for 1ms for as long as it's alive.
+#[cfg(windows)]
+struct TimerResolution {
+    granted: bool,
+}
+
+#[cfg(windows)]
+impl TimerResolution {
+    fn new() -> Self {
+        use winapi::um::{mmsystem::TIMERR_NOERROR, timeapi::timeBeginPeriod};
+
+        Self { granted: unsafe { timeBeginPeriod(1) } == TIMERR_NOERROR }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
+        use winapi::um::timeapi::timeEndPeriod;
+
+        // Every successful begin needs a matching end.
+        if self.granted {
+            unsafe { timeEndPeriod(1) };
+        }
+    }
+}
+
+/// The time of day as the system has it, which NTP keeps in line with
+/// everyone else's, as time since 1970 UTC.
+pub fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// How long until it's `at` past midnight, local time, tomorrow if that's
+/// already gone by today.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+pub fn until(at: Duration) -> Duration {
+    const DAY: i64 = 86_400;
+    let since_epoch = since_epoch();
+    let (hours, minutes, seconds) = time_of_day();
+    let local = i64::from(hours * 3600 + minutes * 60 + seconds);
+    let utc = (since_epoch.as_secs() % 86_400) as i64;
+    // Time zones are whole minutes off UTC, which keeps the two reads a
+    // second apart from throwing it off.
+    let offset = ((local - utc + 30).rem_euclid(DAY) / 60 * 60) as u64;
+    let now = Duration::new(
+        (since_epoch.as_secs() + offset) % 86_400,
+        since_epoch.subsec_nanos(),
+    );
+    match at.checked_sub(now) {
+        Some(wait) => wait,
+        None => (at + Duration::from_secs(86_400)).saturating_sub(now),
+    }
+}
+
+/// The local time of day, as hours, minutes and seconds.
+#[cfg(unix)]
+pub fn time_of_day() -> (u32, u32, u32) {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    if unsafe { libc::localtime_r(&raw const now, &raw mut tm) }.is_null() {
+        return utc_time_of_day();
+    }
+    let field = |value: libc::c_int| u32::try_from(value).unwrap_or(0);
+    (field(tm.tm_hour), field(tm.tm_min), field(tm.tm_sec))
+}
+
+/// The local time of day, as hours, minutes and seconds.
+#[cfg(windows)]
+pub fn time_of_day() -> (u32, u32, u32) {
+    use winapi::um::{minwinbase::SYSTEMTIME, sysinfoapi::GetLocalTime};
+
+    let mut time: SYSTEMTIME = unsafe { std::mem::zeroed() };
+    unsafe { GetLocalTime(&raw mut time) };
+    (time.wHour.into(), time.wMinute.into(), time.wSecond.into())
+}
+
+/// Anywhere else, UTC will have to do.
+#[cfg(not(any(unix, windows)))]
+pub fn time_of_day() -> (u32, u32, u32) {
+    utc_time_of_day()
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn utc_time_of_day() -> (u32, u32, u32) {
+    let seconds = since_epoch().as_secs() % 86_400;
+    let seconds = u32::try_from(seconds).unwrap_or(0);
+    (seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn runs_with_the_clock() {
+        let mut playhead = Playhead::new(SECOND, SECOND * 10);
+        assert_eq!(playhead.at(SECOND * 12), SECOND * 3);
+        playhead.pause(SECOND);
+        assert_eq!(playhead.at(SECOND * 12), SECOND * 2);
+        playhead.set(Duration::ZERO, SECOND * 12);
+        assert_eq!(playhead.at(SECOND * 13), SECOND);
+    }
+
+    #[test]
+    fn follows_the_audio_a_bit_at_a_time() {
+        let now = SECOND * 10;
+        let mut playhead = Playhead::new(Duration::ZERO, now);
+        let ms = Duration::from_millis;
+        assert!(!playhead.follow(ms(80), now));
+        assert_eq!(playhead.at(now), ms(10));
+        assert!(!playhead.follow(Duration::ZERO, now));
+        // An eighth of the way back.
+        assert_eq!(playhead.at(now), Duration::from_micros(8750));
+    }
+
+    #[test]
+    fn jumps_to_audio_far_away() {
+        let mut playhead = Playhead::new(SECOND * 5, Duration::ZERO);
+        assert!(playhead.follow(SECOND, Duration::ZERO));
+        assert_eq!(playhead.at(Duration::ZERO), SECOND);
+    }
+}
@@ -0,0 +1,88 @@
+// `bplay completions <shell>`. Clap writes the scripts from the arguments
+// it already knows about, but all it can say about a file is that it's a
+// path. So for the shells that can do it, the file to play gets narrowed
+// down to .bapple files and playlists afterwards, and the file the other
+// subcommands take to .bapple files.
+
+use std::io::{Write, stdout};
+
+use clap::CommandFactory;
+use clap_complete::{Shell, generate};
+
+use crate::{Res, primitives::Args};
+
+/// Only files left to the shell's default get narrowed down, which also
+/// leaves option values alone.
+const BASH: &str = r#"
+_bplay_files() {
+    _bplay "$@"
+    if [[ ${#COMPREPLY[@]} -eq 0 && "$2" != -* && "$3" != -* ]]; then
+        compopt -o filenames 2>/dev/null
+        COMPREPLY=(
+            $(compgen -d -- "$2")
+            $(compgen -f -X '!*.bapple' -- "$2")
+            $(compgen -f -X '!*.m3u' -- "$2")
+            $(compgen -f -X '!*.txt' -- "$2")
+        )
+    fi
+}
+complete -F _bplay_files -o bashdefault -o default bplay
+"#;
+
+const FISH: &str = r#"
+complete -c bplay -n "__fish_bplay_needs_command" -f -a "(__fish_complete_suffix .bapple; __fish_complete_suffix .m3u; __fish_complete_suffix .txt)"
+complete -c bplay -n "__fish_bplay_using_subcommand info" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand concat" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand split" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand trim" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand remux" -F
+complete -c bplay -n "__fish_bplay_using_subcommand diff" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand stats" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand optimize" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand validate" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand sign" -F
+complete -c bplay -n "__fish_bplay_using_subcommand serve" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand audio; and __fish_seen_subcommand_from extract" -f -a "(__fish_complete_suffix .bapple)"
+complete -c bplay -n "__fish_bplay_using_subcommand meta; and __fish_seen_subcommand_from list get set" -f -a "(__fish_complete_suffix .bapple)"
+"#;
+
+pub fn run(shell: Shell) -> Res<()> {
+    let mut script = Vec::new();
+    generate(shell, &mut Args::command(), "bplay", &mut script);
+    let mut script = String::from_utf8(script)?;
+
+    match shell {
+        Shell::Bash => script.push_str(BASH),
+        Shell::Fish => script.push_str(FISH),
+        Shell::Zsh => script = zsh(&script),
+        _ => {}
+    }
+
+    stdout().write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// Gives `_files` a pattern on the lines for the file arguments. The one
+/// to play is the only optional one.
+fn zsh(script: &str) -> String {
+    let mut patched = String::new();
+    for line in script.lines() {
+        let pattern = if line.starts_with("'::file -- ") {
+            Some("*.(bapple|m3u|txt)")
+        } else if line.starts_with("':file -- ") {
+            Some("*.bapple")
+        } else {
+            None
+        };
+        match pattern {
+            Some(pattern) => patched.push_str(&line.replacen(
+                ":_files'",
+                &format!(":_files -g \"{pattern}\"'"),
+                1,
+            )),
+            None => patched.push_str(line),
+        }
+        patched.push('\n');
+    }
+    patched
+}
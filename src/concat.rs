@@ -0,0 +1,242 @@
+// `bplay concat`, joining .bapple files end to end into one. Frames get
+// renumbered one after the other, and everything that goes by time or by
+// frame (subtitles, speed regions, episodes) moves along with them. Each
+// file becomes an episode, unless it already had some. The audio is
+// joined too, each file's padded or cut to how long its frames last so it
+// all stays in sync, unless the files can't share a track. Frames
+// compressed against a dictionary get compressed again by themselves,
+// since each file's would be different.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Cursor,
+    iter::repeat,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rodio::{Decoder, Source, source::UniformSourceIterator};
+
+use crate::{
+    Res,
+    archive::{ArchiveWriter, Contents},
+    encode::audio::to_mp3,
+    frames::{Dictionary, decompress, max_frame_size},
+    messages::{AUDIO_MISMATCH, AUDIO_MISSING},
+    metadata::{Episode, Metadata, SpeedRegion, TrackLabel, today},
+    subtitles::Subtitles,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ConcatArgs {
+    /// The .bapple files to join, in the order they play
+    #[arg(required = true, num_args = 2..)]
+    pub files: Vec<PathBuf>,
+    /// Where to write the joined file
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// MP3 bitrate in kbps for the joined audio
+    #[arg(
+        long,
+        default_value = "192",
+        value_parser = ["96", "128", "160", "192", "256", "320"]
+    )]
+    pub audio_bitrate: String,
+}
+
+pub fn run(args: &ConcatArgs) -> Res<()> {
+    for file in &args.files {
+        if same_file(file, &args.output) {
+            return Err(format!(
+                "{} is one of the files being joined",
+                args.output.display()
+            )
+            .into());
+        }
+    }
+    let parts = args
+        .files
+        .iter()
+        .map(|path| Contents::read(path))
+        .collect::<Res<Vec<_>>>()?;
+
+    let mut writer = ArchiveWriter::create(&args.output)?;
+    if let Some(poster) = parts.iter().find_map(|part| part.poster.as_ref()) {
+        writer.append_poster(poster)?;
+    }
+    let limit = max_frame_size();
+    for part in &parts {
+        let Some(dictionary) = &part.dictionary else {
+            part.for_each_frame(|_, frame| Ok(writer.append_frame(frame)?))?;
+            continue;
+        };
+        let dictionary = Dictionary::new(dictionary);
+        part.for_each_frame(|_, frame| {
+            let frame = decompress(frame, &dictionary, limit)?;
+            let level = zstd::DEFAULT_COMPRESSION_LEVEL;
+            Ok(writer.append_frame(&zstd::encode_all(&frame[..], level)?)?)
+        })?;
+    }
+    let bitrate = args.audio_bitrate.parse().unwrap_or(192);
+    let audio = join_audio(&parts, bitrate)?;
+    if let Some(mp3) = &audio {
+        writer.append_audio(mp3)?;
+    }
+    for (language, subtitles) in join_subtitles(&parts) {
+        writer.append_subtitles(&language, subtitles.to_srt().as_bytes())?;
+    }
+    writer.append_metadata(&join_metadata(&parts, audio.is_some()))?;
+    let frames = writer.frames();
+    writer.finish()?;
+
+    println!(
+        "Joined {} files ({frames} frames) into {}",
+        parts.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Whether both paths lead to the same file, if the second exists at all.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// One track for the whole thing, `None` (with a warning) if some of the
+/// files don't have audio or it's at different sample rates.
+fn join_audio(parts: &[Contents], bitrate: u16) -> Res<Option<Vec<u8>>> {
+    if parts.iter().all(|part| part.audio.is_none()) {
+        return Ok(None);
+    }
+    if parts.iter().any(|part| part.audio.is_none()) {
+        eprintln!("{AUDIO_MISSING}");
+        return Ok(None);
+    }
+
+    let mut decoders = Vec::new();
+    for part in parts {
+        let audio = part.audio.clone().unwrap_or_default();
+        let decoder = Decoder::try_from(Cursor::new(audio))
+            .map_err(|e| format!("{}: {e}", part.path.display()))?;
+        decoders.push((decoder, part.duration));
+    }
+    let sample_rate = decoders[0].0.sample_rate();
+    if decoders.iter().any(|(decoder, _)| decoder.sample_rate() != sample_rate)
+    {
+        eprintln!("{AUDIO_MISMATCH}");
+        return Ok(None);
+    }
+
+    let mut samples = Vec::new();
+    for (decoder, duration) in decoders {
+        // Stereo, so two samples at a time.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let length =
+            (duration.as_secs_f64() * f64::from(sample_rate)) as usize * 2;
+        let decoded = UniformSourceIterator::new(decoder, 2, sample_rate);
+        samples.extend(decoded.chain(repeat(0.0)).take(length));
+    }
+    println!("Encoding the joined audio...");
+    to_mp3(samples, sample_rate, bitrate).map(Some)
+}
+
+/// Every track, by language, with the cues from later files moved back by
+/// however long the files before them play for.
+fn join_subtitles(parts: &[Contents]) -> BTreeMap<String, Subtitles> {
+    let mut tracks = BTreeMap::<String, Subtitles>::new();
+    let mut start = Duration::ZERO;
+    for part in parts {
+        for (language, track) in &part.subtitles {
+            tracks
+                .entry(language.clone())
+                .or_default()
+                .append_shifted(track, start);
+        }
+        start += part.duration;
+    }
+    tracks
+}
+
+fn join_metadata(parts: &[Contents], audio: bool) -> Metadata {
+    let frametimes: Vec<u64> =
+        parts.iter().flat_map(|part| part.frametimes.clone()).collect();
+    let frametime = frametimes[0];
+    let constant = frametimes.iter().all(|&time| time == frametime);
+
+    let mut speeds = Vec::new();
+    let mut episodes = Vec::new();
+    let mut frame = 0;
+    // Speed regions go by time at normal speed.
+    let mut unscaled = 0;
+    for part in parts {
+        speeds.extend(part.metadata.speeds.iter().map(|region| SpeedRegion {
+            start: region.start + unscaled,
+            end: region.end + unscaled,
+            ..*region
+        }));
+        if part.metadata.episodes.is_empty() {
+            episodes.push(Episode {
+                title: part.title(),
+                start: frame,
+                end: frame + part.frames,
+            });
+        } else {
+            episodes.extend(part.metadata.episodes.iter().map(|episode| {
+                Episode {
+                    title: episode.title.clone(),
+                    start: episode.start + frame,
+                    end: episode.end + frame,
+                }
+            }));
+        }
+        frame += part.frames;
+        unscaled += part.frametimes.iter().sum::<u64>();
+    }
+
+    let mut subtitle_tracks: Vec<TrackLabel> = Vec::new();
+    for label in parts.iter().flat_map(|part| &part.metadata.subtitle_tracks) {
+        if subtitle_tracks.iter().all(|l| l.language != label.language) {
+            subtitle_tracks.push(label.clone());
+        }
+    }
+
+    Metadata {
+        version: Metadata::VERSION,
+        frametime,
+        fps: 1_000_000_u64.checked_div(frametime).unwrap_or(0),
+        frametimes: if constant { Vec::new() } else { frametimes },
+        // Whatever every file agrees on.
+        title: common(parts, |metadata| &metadata.title),
+        author: common(parts, |metadata| &metadata.author),
+        description: common(parts, |metadata| &metadata.description),
+        source: common(parts, |metadata| &metadata.source),
+        created: Some(today()),
+        speeds,
+        charset: common(parts, |metadata| &metadata.charset),
+        audio_track: if audio {
+            parts[0].metadata.audio_track.clone()
+        } else {
+            None
+        },
+        subtitle_tracks,
+        episodes,
+        ..Default::default()
+    }
+}
+
+/// A field's value, if it's the same in every file.
+fn common<T: Clone + PartialEq>(
+    parts: &[Contents],
+    field: impl Fn(&Metadata) -> &Option<T>,
+) -> Option<T> {
+    let first = field(&parts[0].metadata);
+    parts
+        .iter()
+        .all(|part| field(&part.metadata) == first)
+        .then(|| first.clone())
+        .flatten()
+}
@@ -0,0 +1,65 @@
+// Settings that stick around between runs, in config.ron under the
+// config directory. A missing or broken file just means defaults.
+// `BAPPLE_*` variables go on top of the file, and flags on top of those,
+// most of them through clap. The ones without a flag are handled here.
+
+use std::{env, fs, path::PathBuf};
+
+use clap::ValueEnum;
+
+use ron::ser::{PrettyConfig, to_string_pretty};
+use serde::{Deserialize, Serialize};
+
+use crate::{Res, controls::Osd, paths};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// What's drawn on top of the video, changed with the `o` key.
+    pub osd: Osd,
+}
+
+impl Config {
+    pub fn load() -> Self {
+        path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|content| ron::de::from_bytes(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Res<()> {
+        let path = path().ok_or("Couldn't find a config directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, to_string_pretty(self, PrettyConfig::default())?)?;
+        Ok(())
+    }
+
+    /// Whatever's set in the environment, on top of the file. Kept apart
+    /// from [`Self::load`], so it never gets saved.
+    #[must_use]
+    pub fn with_env(mut self) -> Self {
+        if let Some(osd) = var("BAPPLE_OSD") {
+            self.osd = osd;
+        }
+        self
+    }
+
+    /// Loads the config, changes it and saves it right away, so nothing
+    /// else in the file gets lost.
+    pub fn update(change: impl FnOnce(&mut Self)) -> Res<()> {
+        let mut config = Self::load();
+        change(&mut config);
+        config.save()
+    }
+}
+
+/// Values that don't parse get ignored, like a broken config file.
+fn var<T: ValueEnum>(name: &str) -> Option<T> {
+    T::from_str(&env::var(name).ok()?, true).ok()
+}
+
+fn path() -> Option<PathBuf> {
+    paths::config_dir().map(|dir| dir.join("config.ron"))
+}
@@ -0,0 +1,325 @@
+// For Windows consoles that can't do virtual terminal processing (old
+// conhost, some CI consoles). Everything written here gets parsed and
+// drawn cell by cell with the console API, instead of showing up as
+// escape sequence garbage. Only understands what the player itself
+// writes: moving the cursor, clearing, colors and the title.
+
+use std::io::{self, Write};
+
+use winapi::um::{
+    handleapi::INVALID_HANDLE_VALUE,
+    processenv::GetStdHandle,
+    winbase::STD_OUTPUT_HANDLE,
+    wincon::{
+        COMMON_LVB_REVERSE_VIDEO, CONSOLE_CURSOR_INFO,
+        CONSOLE_SCREEN_BUFFER_INFO, FOREGROUND_INTENSITY, GetConsoleCursorInfo,
+        GetConsoleScreenBufferInfo, SetConsoleCursorInfo, SetConsoleTitleW,
+        WriteConsoleOutputW,
+    },
+    wincontypes::{CHAR_INFO, COORD, SMALL_RECT},
+    winnt::HANDLE,
+};
+
+/// The 6 levels of each channel in the 256 color cube.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+pub struct ConsoleWriter {
+    handle: HANDLE,
+    pending: Vec<u8>,
+    /// Whatever the console was using before, for `\x1b[0m`.
+    default_attributes: u16,
+    attributes: u16,
+    window: SMALL_RECT,
+    cells: Vec<CHAR_INFO>,
+    row: usize,
+    column: usize,
+}
+
+impl ConsoleWriter {
+    pub fn new() -> io::Result<Self> {
+        let handle = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut info: CONSOLE_SCREEN_BUFFER_INFO =
+            unsafe { std::mem::zeroed() };
+        if unsafe { GetConsoleScreenBufferInfo(handle, &raw mut info) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut writer = Self {
+            handle,
+            pending: Vec::new(),
+            default_attributes: info.wAttributes,
+            attributes: info.wAttributes,
+            window: info.srWindow,
+            cells: Vec::new(),
+            row: 0,
+            column: 0,
+        };
+        writer.clear();
+        Ok(writer)
+    }
+
+    fn width(&self) -> usize {
+        usize::try_from(self.window.Right - self.window.Left + 1).unwrap_or(0)
+    }
+
+    fn height(&self) -> usize {
+        usize::try_from(self.window.Bottom - self.window.Top + 1).unwrap_or(0)
+    }
+
+    fn clear(&mut self) {
+        let blank = cell(' ', self.default_attributes);
+        self.cells = vec![blank; self.width() * self.height()];
+    }
+
+    fn clear_row(&mut self) {
+        let width = self.width();
+        let blank = cell(' ', self.attributes);
+        if let Some(row) =
+            self.cells.get_mut(self.row * width..(self.row + 1) * width)
+        {
+            row.fill(blank);
+        }
+    }
+
+    fn clear_rest_of_row(&mut self) {
+        let width = self.width();
+        let blank = cell(' ', self.attributes);
+        if self.column < width
+            && let Some(rest) = self
+                .cells
+                .get_mut(self.row * width + self.column..(self.row + 1) * width)
+        {
+            rest.fill(blank);
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        let (width, height) = (self.width(), self.height());
+        if self.row < height && self.column < width {
+            let c = if u32::from(c) > 0xffff { '?' } else { c };
+            self.cells[self.row * width + self.column] =
+                cell(c, self.attributes);
+        }
+        self.column += 1;
+    }
+
+    /// Interprets everything written since the last flush.
+    fn process(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let text = String::from_utf8_lossy(&pending);
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => {
+                        let mut parameters = String::new();
+                        for c in chars.by_ref() {
+                            if ('@'..='~').contains(&c) {
+                                self.csi(&parameters, c);
+                                break;
+                            }
+                            parameters.push(c);
+                        }
+                    }
+                    Some(']') => {
+                        let title: String = chars
+                            .by_ref()
+                            .take_while(|&c| c != '\x07')
+                            .collect();
+                        if let Some(title) = title.strip_prefix("0;") {
+                            set_title(title);
+                        }
+                    }
+                    _ => {}
+                },
+                '\n' => {
+                    self.row += 1;
+                    self.column = 0;
+                }
+                '\r' => self.column = 0,
+                _ => self.put(c),
+            }
+        }
+    }
+
+    fn csi(&mut self, parameters: &str, command: char) {
+        match (parameters, command) {
+            (_, 'H') => {
+                // 1-based, and either one can be left out.
+                let mut position = parameters
+                    .split(';')
+                    .map(|p| p.parse::<usize>().unwrap_or(1).saturating_sub(1));
+                self.row = position.next().unwrap_or(0);
+                self.column = position.next().unwrap_or(0);
+            }
+            ("2", 'J') => self.clear(),
+            ("2", 'K') => self.clear_row(),
+            ("" | "0", 'K') => self.clear_rest_of_row(),
+            ("?25", 'h') => self.set_cursor_visible(true),
+            ("?25", 'l') => self.set_cursor_visible(false),
+            (_, 'm') => self.sgr(parameters),
+            // Titles, alternate screens and synchronized output don't
+            // mean anything here.
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, parameters: &str) {
+        let mut parameters =
+            parameters.split(';').map(|p| p.parse().unwrap_or(0));
+
+        while let Some(parameter) = parameters.next() {
+            let (foreground, color) = match parameter {
+                0 => {
+                    self.attributes = self.default_attributes;
+                    continue;
+                }
+                7 => {
+                    self.attributes |= COMMON_LVB_REVERSE_VIDEO;
+                    continue;
+                }
+                27 => {
+                    self.attributes &= !COMMON_LVB_REVERSE_VIDEO;
+                    continue;
+                }
+                30..=37 => (true, basic(parameter - 30)),
+                90..=97 => (true, basic(parameter - 90) | FOREGROUND_INTENSITY),
+                40..=47 => (false, basic(parameter - 40)),
+                100..=107 => {
+                    (false, basic(parameter - 100) | FOREGROUND_INTENSITY)
+                }
+                39 => (true, self.default_attributes & 0x0f),
+                49 => (false, (self.default_attributes >> 4) & 0x0f),
+                38 | 48 => {
+                    let color = match parameters.next() {
+                        Some(5) => indexed(parameters.next().unwrap_or(0)),
+                        Some(2) => {
+                            let mut channel = || {
+                                u8::try_from(parameters.next().unwrap_or(0))
+                                    .unwrap_or(u8::MAX)
+                            };
+                            nearest(channel(), channel(), channel())
+                        }
+                        _ => continue,
+                    };
+                    (parameter == 38, color)
+                }
+                _ => continue,
+            };
+
+            self.attributes = if foreground {
+                (self.attributes & !0x0f) | color
+            } else {
+                (self.attributes & !0xf0) | (color << 4)
+            };
+        }
+    }
+
+    fn set_cursor_visible(&self, visible: bool) {
+        let mut info: CONSOLE_CURSOR_INFO = unsafe { std::mem::zeroed() };
+        unsafe {
+            if GetConsoleCursorInfo(self.handle, &raw mut info) != 0 {
+                info.bVisible = i32::from(visible);
+                SetConsoleCursorInfo(self.handle, &raw const info);
+            }
+        }
+    }
+
+    /// Puts the whole grid on screen in one go.
+    fn draw(&mut self) -> io::Result<()> {
+        let size = COORD {
+            X: self.window.Right - self.window.Left + 1,
+            Y: self.window.Bottom - self.window.Top + 1,
+        };
+        let mut region = self.window;
+        let written = unsafe {
+            WriteConsoleOutputW(
+                self.handle,
+                self.cells.as_ptr(),
+                size,
+                COORD { X: 0, Y: 0 },
+                &raw mut region,
+            )
+        };
+        if written == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.process();
+        self.draw()
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        // Whatever came after the last frame, like showing the cursor.
+        self.process();
+    }
+}
+
+fn cell(c: char, attributes: u16) -> CHAR_INFO {
+    let mut cell: CHAR_INFO = unsafe { std::mem::zeroed() };
+    #[allow(clippy::cast_possible_truncation)]
+    unsafe {
+        *cell.Char.UnicodeChar_mut() = u32::from(c) as u16;
+    }
+    cell.Attributes = attributes;
+    cell
+}
+
+fn set_title(title: &str) {
+    let title: Vec<u16> = title.encode_utf16().chain([0]).collect();
+    unsafe { SetConsoleTitleW(title.as_ptr()) };
+}
+
+/// ANSI numbers colors red, green, blue, the console blue, green, red.
+fn basic(ansi: u16) -> u16 {
+    ((ansi & 1) << 2) | (ansi & 2) | ((ansi & 4) >> 2)
+}
+
+fn indexed(index: u16) -> u16 {
+    match index {
+        0..=7 => basic(index),
+        8..=15 => basic(index - 8) | FOREGROUND_INTENSITY,
+        16..=231 => {
+            let index = usize::from(index - 16);
+            nearest(CUBE[index / 36], CUBE[index / 6 % 6], CUBE[index % 6])
+        }
+        _ => {
+            let gray =
+                u8::try_from(8 + 10 * (index.min(255) - 232)).unwrap_or(255);
+            nearest(gray, gray, gray)
+        }
+    }
+}
+
+/// Rough match against the 16 console colors.
+fn nearest(red: u8, green: u8, blue: u8) -> u16 {
+    let brightest = red.max(green).max(blue);
+    let bright = brightest > 170;
+    let threshold = if bright { 128 } else { 64 };
+
+    let mut color = 0;
+    for (ansi, channel) in [(1, red), (2, green), (4, blue)] {
+        if channel > threshold {
+            color |= ansi;
+        }
+    }
+    let color = basic(color);
+    if bright { color | FOREGROUND_INTENSITY } else { color }
+}
@@ -0,0 +1,313 @@
+// What `bplay serve --control` takes over HTTP, for scripts and home
+// automation to drive playback with:
+//
+//   GET  /status            what's playing, and where
+//   POST /play              carries on after a pause
+//   POST /pause
+//   POST /seek?to=1:30      goes there, as [[h:]mm:]ss
+//   GET  /playlist          what's in it, and which one's playing
+//   POST /playlist?item=2   plays that one instead, counting from 0
+//
+// Everything answers in JSON, with the status unless it says otherwise:
+//
+//   {"playing":true,"item":0,"title":"Bad Apple!!","frame":12,
+//    "time":0.4,"duration":219.0}
+//
+// The player picks up what it's asked to do before the next frame. Pages
+// from other sites don't get to, nor do requests for any other host than
+// this one, and with `--control-token`, only requests with
+// `Authorization: Bearer <token>` do. Listening anywhere but on this
+// machine takes one.
+
+use std::{net::SocketAddr, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+use crate::{serve::lock, timeline::parse_time, websocket::Request};
+
+const ENDPOINTS: [&str; 5] =
+    ["/status", "/play", "/pause", "/seek", "/playlist"];
+
+/// Where playback is, and what it's been asked to do.
+#[derive(Default)]
+pub struct State {
+    pub paused: bool,
+    /// Where to go, for the player to pick up.
+    pub seek: Option<Duration>,
+    /// Which item to play instead, for the player to pick up.
+    pub skip: Option<usize>,
+    pub item: usize,
+    pub title: String,
+    pub frame: usize,
+    pub time: Duration,
+    pub duration: Duration,
+}
+
+/// What `/status` and the rest answer with.
+#[derive(Serialize)]
+struct Status<'a> {
+    playing: bool,
+    item: usize,
+    title: &'a str,
+    frame: usize,
+    /// In seconds, like `duration`.
+    time: f64,
+    duration: f64,
+}
+
+#[derive(Serialize)]
+struct Playlist<'a> {
+    item: usize,
+    items: &'a [String],
+}
+
+#[derive(Serialize)]
+struct Error<'a> {
+    error: &'a str,
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub body: String,
+}
+
+/// Answers a request for one of the endpoints, `None` for anything else.
+/// `address` is where it's listening, and `items` are the names of what's
+/// in the playlist.
+pub fn handle(
+    request: &Request,
+    address: SocketAddr,
+    token: Option<&str>,
+    state: &Mutex<State>,
+    items: &[String],
+) -> Option<Response> {
+    let (method, target) = (request.method.as_str(), request.path.as_str());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if !ENDPOINTS.contains(&path) {
+        return None;
+    }
+    if !request.to_here(address) || !request.same_origin() {
+        let why = "requests from other sites aren't taken";
+        return Some(error("403 Forbidden", why));
+    }
+    if let Some(token) = token
+        && !authorized(request, token)
+    {
+        let why = "expected Authorization: Bearer with the --control-token";
+        return Some(error("401 Unauthorized", why));
+    }
+    let mut state = lock(state);
+    let done = match (path, method) {
+        ("/status", "GET") => Ok(()),
+        ("/play", "POST") => {
+            state.paused = false;
+            Ok(())
+        }
+        ("/pause", "POST") => {
+            state.paused = true;
+            Ok(())
+        }
+        ("/seek", "POST") => param(query, "to")
+            .and_then(|to| parse_time(&to))
+            .map(|to| state.seek = Some(to)),
+        ("/playlist", "GET") => {
+            return Some(playlist(&state, items));
+        }
+        ("/playlist", "POST") => {
+            let item = param(query, "item").and_then(|item| {
+                item.parse::<usize>().map_err(|e| format!("item: {e}"))
+            });
+            match item {
+                Ok(item) if item >= items.len() => {
+                    let why = format!("there's no item {item}");
+                    return Some(error("404 Not Found", &why));
+                }
+                Ok(item) => {
+                    state.skip = Some(item);
+                    return Some(playlist(&state, items));
+                }
+                Err(e) => Err(e),
+            }
+        }
+        _ => {
+            let why = format!("{path} doesn't take {method}");
+            return Some(error("405 Method Not Allowed", &why));
+        }
+    };
+    Some(match done {
+        Ok(()) => status(&state),
+        Err(why) => error("400 Bad Request", &why),
+    })
+}
+
+/// Whether the request comes with `token`, compared without giving away
+/// how much of it was right by how long that took.
+fn authorized(request: &Request, token: &str) -> bool {
+    let Some(given) = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+fn status(state: &State) -> Response {
+    ok(&Status {
+        playing: !state.paused,
+        item: state.item,
+        title: &state.title,
+        frame: state.frame,
+        time: state.time.as_secs_f64(),
+        duration: state.duration.as_secs_f64(),
+    })
+}
+
+fn playlist(state: &State, items: &[String]) -> Response {
+    // Where it's going, if it's been asked to go somewhere else.
+    let item = state.skip.unwrap_or(state.item);
+    ok(&Playlist { item, items })
+}
+
+fn ok(body: &impl Serialize) -> Response {
+    json("200 OK", body)
+}
+
+fn error(status: &'static str, why: &str) -> Response {
+    json(status, &Error { error: why })
+}
+
+fn json(status: &'static str, body: &impl Serialize) -> Response {
+    // Nothing in these can fail to serialize.
+    let body = serde_json::to_string(body).unwrap_or_default();
+    Response { status, body }
+}
+
+/// The value of `name` in a query string like `to=1:30&item=2`.
+fn param(query: &str, name: &str) -> Result<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+        .ok_or_else(|| format!("expected ?{name}="))
+}
+
+/// Undoes what URLs do to characters like `:`, which clients tend to
+/// turn into `%3A`.
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        let hex = after
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &after[2..];
+            }
+            (b'+', _) => {
+                bytes.push(b' ');
+                rest = after;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value).to_string())
+        };
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            key: None,
+            host: Some("localhost:8080".to_string()),
+            origin: header("Origin"),
+            authorization: header("Authorization"),
+        }
+    }
+
+    fn here() -> SocketAddr {
+        "127.0.0.1:8080".parse().unwrap()
+    }
+
+    fn status(request: &Request, token: Option<&str>) -> Option<&'static str> {
+        let state = Mutex::new(State::default());
+        let items = ["intro".to_string()];
+        handle(request, here(), token, &state, &items)
+            .map(|response| response.status)
+    }
+
+    #[test]
+    fn pauses() {
+        let state = Mutex::new(State::default());
+        let response =
+            handle(&request("POST", "/pause", &[]), here(), None, &state, &[]);
+        assert_eq!(response.unwrap().status, "200 OK");
+        assert!(lock(&state).paused);
+    }
+
+    #[test]
+    fn leaves_other_paths_alone() {
+        assert_eq!(status(&request("GET", "/", &[]), None), None);
+        let wrong = request("GET", "/pause", &[]);
+        assert_eq!(status(&wrong, None), Some("405 Method Not Allowed"));
+    }
+
+    #[test]
+    fn refuses_other_sites() {
+        let here = [("Origin", "http://localhost:8080")];
+        assert_eq!(
+            status(&request("POST", "/play", &here), None),
+            Some("200 OK")
+        );
+        for origin in ["http://evil.example", "null"] {
+            let there = request("POST", "/play", &[("Origin", origin)]);
+            assert_eq!(status(&there, None), Some("403 Forbidden"));
+        }
+        // Pointed here by a name they own, so it looks like the same site.
+        let mut rebound = request("POST", "/play", &[]);
+        rebound.host = Some("evil.example:8080".to_string());
+        rebound.origin = Some("http://evil.example:8080".to_string());
+        assert_eq!(status(&rebound, None), Some("403 Forbidden"));
+    }
+
+    #[test]
+    fn asks_for_the_token() {
+        let token = Some("hunter2");
+        for authorization in [&[][..], &[("Authorization", "Bearer hunter")]] {
+            let request = request("GET", "/status", authorization);
+            assert_eq!(status(&request, token), Some("401 Unauthorized"));
+        }
+        let right = [("Authorization", "Bearer hunter2")];
+        let request = request("GET", "/status", &right);
+        assert_eq!(status(&request, token), Some("200 OK"));
+    }
+
+    #[test]
+    fn decodes_seeks() {
+        assert_eq!(param("to=1%3A30&item=2", "to").unwrap(), "1:30");
+        assert!(param("item=2", "to").is_err());
+    }
+}
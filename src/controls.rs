@@ -0,0 +1,749 @@
+// Keys that do something during playback, and the state they change.
+// Controls outlive a single file, so they're owned by main and lent to
+// each Bapple while it plays.
+
+use std::{
+    cell::Cell,
+    io::{self, IsTerminal, Write, stdin, stdout},
+    ops::Range,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use crossterm::{
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode,
+        KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind,
+    },
+    execute, terminal,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    STOP,
+    config::Config,
+    subtitles::Choice,
+    timeline::format_time,
+    tui::{Tui, View},
+};
+
+/// How long a notice stays on screen.
+const NOTICE_TIME: Duration = Duration::from_secs(2);
+
+/// How much `z` and `x` change the subtitle delay, in milliseconds.
+const SUB_DELAY_STEP: i64 = 100;
+
+/// What `?` shows.
+const HELP: [(&str, &str); 11] = [
+    ("q, Esc", "Quit"),
+    ("r", "Cycle the repeat mode"),
+    ("n, >", "Next file"),
+    ("p, <", "Previous file"),
+    ("t", "Show or hide the time"),
+    ("o", "Cycle the on-screen display"),
+    ("v", "Show or hide subtitles"),
+    ("z, x", "Subtitles earlier, later"),
+    ("j", "Next subtitle track"),
+    ("Click", "Seek, on the seek bar"),
+    ("?", "This help"),
+];
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Repeat {
+    /// Play everything once
+    #[default]
+    None,
+    /// Play the current file over and over
+    One,
+    /// Start over after the last file
+    All,
+}
+
+impl Repeat {
+    fn next(self) -> Self {
+        match self {
+            Self::None => Self::All,
+            Self::All => Self::One,
+            Self::One => Self::None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "off",
+            Self::One => "one",
+            Self::All => "all",
+        }
+    }
+}
+
+/// Leaves the current file early for another one in the playlist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skip {
+    Next,
+    Previous,
+}
+
+/// How much gets drawn on top of the video.
+#[derive(
+    ValueEnum,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+pub enum Osd {
+    #[default]
+    Off,
+    /// The time and a seek bar.
+    Time,
+    /// Plus a row of playback stats above it.
+    Stats,
+}
+
+impl Osd {
+    fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Time,
+            Self::Time => Self::Stats,
+            Self::Stats => Self::Off,
+        }
+    }
+
+    fn rows(self) -> u16 {
+        match self {
+            Self::Off => 0,
+            Self::Time => 1,
+            Self::Stats => 2,
+        }
+    }
+}
+
+/// What the OSD shows about the file that's playing.
+pub struct Status {
+    pub elapsed: Duration,
+    pub total: Duration,
+    /// The current frame and how many there are, `None` for the
+    /// visualizer.
+    pub frame: Option<(usize, usize)>,
+    /// Frames skipped to catch up.
+    pub dropped: usize,
+    /// Frames that weren't decompressed in time.
+    pub stalls: usize,
+}
+
+enum Action {
+    Quit,
+    CycleRepeat,
+    Skip(Skip),
+    ToggleTime,
+    CycleOsd,
+    ToggleSubtitles,
+    /// Milliseconds to add to the subtitle delay.
+    SubDelay(i64),
+    CycleSubTrack,
+    Help,
+    Click {
+        column: u16,
+        row: u16,
+    },
+    /// A key that doesn't do anything, besides waking up a screensaver.
+    Unbound,
+}
+
+/// Where the seek bar was last drawn, to know what a click hit.
+#[derive(Clone, Copy)]
+struct Bar {
+    row: u16,
+    start: u16,
+    width: u16,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+pub struct Controls {
+    pub repeat: Repeat,
+    /// Set when playback should stop for a skip, main takes it from here.
+    pub skip: Option<Skip>,
+    /// Set when the seek bar gets clicked, as a fraction of the whole
+    /// file. The Bapple playing takes it from here.
+    pub seek: Option<f64>,
+    /// Set when the help should be shown, which pauses playback.
+    pub help: bool,
+    /// How many milliseconds late subtitles are shown, early if it's
+    /// negative.
+    pub sub_delay: i64,
+    /// The subtitle track being shown, the first one if it's `None`.
+    pub sub_track: Option<String>,
+    /// Names and labels of every track the playing file has, set by the
+    /// Bapple playing it.
+    pub sub_tracks: Vec<(String, String)>,
+    subtitles: bool,
+    osd: Osd,
+    /// How many rows the OSD took up last time, to clear them once it
+    /// shrinks.
+    osd_rows: u16,
+    /// Rows (from 0) the last draw wrote over the frame.
+    drawn_over: Vec<Range<usize>>,
+    bar: Option<Bar>,
+    /// Frames drawn since `fps_since`, for measuring the framerate.
+    drawn: u32,
+    fps_since: Instant,
+    fps: f64,
+    /// Shown on top of the frames for a bit after something changes.
+    notice: Option<(String, Instant)>,
+    /// Names of everything in the playlist, for the `--tui` sidebar.
+    pub playlist: Vec<String>,
+    /// Which of them is playing.
+    pub current: usize,
+    /// Draws everything instead of the OSD with `--tui`.
+    tui: Option<Tui>,
+    /// Any key at all stops playback, for `--screensaver`.
+    pub screensaver: bool,
+    /// Set when a key stopped the screensaver.
+    pub woken: bool,
+}
+
+impl Controls {
+    pub fn new(repeat: Repeat, osd: Osd) -> Self {
+        Self {
+            repeat,
+            skip: None,
+            seek: None,
+            help: false,
+            sub_delay: 0,
+            sub_track: None,
+            sub_tracks: Vec::new(),
+            subtitles: true,
+            osd,
+            osd_rows: 0,
+            drawn_over: Vec::new(),
+            bar: None,
+            drawn: 0,
+            fps_since: Instant::now(),
+            fps: 0.0,
+            notice: None,
+            playlist: Vec::new(),
+            current: 0,
+            tui: None,
+            screensaver: false,
+            woken: false,
+        }
+    }
+
+    pub fn set_tui(&mut self, tui: Tui) {
+        self.tui = Some(tui);
+    }
+
+    pub fn is_tui(&self) -> bool {
+        self.tui.is_some()
+    }
+
+    /// Makes the TUI draw everything again, after the screen got cleared
+    /// or drawn over.
+    pub fn redraw(&mut self) {
+        if let Some(tui) = &mut self.tui {
+            tui.redraw();
+        }
+    }
+
+    /// Acts on every key pressed since the last call.
+    pub fn update(&mut self, keyboard: Option<&Keyboard>) {
+        let Some(keyboard) = keyboard else {
+            return;
+        };
+        // Clicks only mean something with the seek bar there, and taking
+        // the mouse keeps text from being selected.
+        keyboard.capture_mouse(self.osd != Osd::Off || self.tui.is_some());
+
+        while let Some(action) = keyboard.next_action() {
+            if self.screensaver {
+                self.woken = true;
+                continue;
+            }
+            match action {
+                Action::Quit => STOP.store(true, Ordering::Relaxed),
+                Action::CycleRepeat => {
+                    self.repeat = self.repeat.next();
+                    self.notify(format!("Repeat: {}", self.repeat.name()));
+                }
+                Action::Skip(skip) => self.skip = Some(skip),
+                Action::ToggleTime => {
+                    let osd =
+                        if self.osd == Osd::Off { Osd::Time } else { Osd::Off };
+                    self.set_osd(osd);
+                }
+                Action::CycleOsd => self.set_osd(self.osd.next()),
+                Action::ToggleSubtitles => {
+                    self.subtitles = !self.subtitles;
+                    let state = if self.subtitles { "on" } else { "off" };
+                    self.notify(format!("Subtitles: {state}"));
+                }
+                Action::SubDelay(change) => {
+                    self.sub_delay += change;
+                    self.notify(format!(
+                        "Subtitle delay: {} ms",
+                        self.sub_delay
+                    ));
+                }
+                Action::CycleSubTrack => self.cycle_sub_track(),
+                Action::Help => self.help = true,
+                Action::Click { column, row } => self.click(column, row),
+                Action::Unbound => {}
+            }
+        }
+    }
+
+    /// Remembered for next time too.
+    fn set_osd(&mut self, osd: Osd) {
+        self.osd = osd;
+        if let Err(e) = Config::update(|config| config.osd = osd) {
+            self.notify(format!("Couldn't save the config: {e}"));
+        }
+    }
+
+    fn click(&mut self, column: u16, row: u16) {
+        let Some(bar) = self.bar else {
+            return;
+        };
+        if row == bar.row
+            && (bar.start..bar.start + bar.width).contains(&column)
+        {
+            let position = f64::from(column - bar.start);
+            self.seek = Some(position / f64::from(bar.width - 1));
+        }
+    }
+
+    /// Goes on to the track after the one being shown, back to the first
+    /// after the last.
+    fn cycle_sub_track(&mut self) {
+        if self.sub_tracks.is_empty() {
+            self.notify("No subtitles".to_string());
+            return;
+        }
+        let current = self
+            .sub_track
+            .as_ref()
+            .and_then(|track| {
+                self.sub_tracks.iter().position(|(name, _)| name == track)
+            })
+            .unwrap_or(0);
+        let (name, label) =
+            self.sub_tracks[(current + 1) % self.sub_tracks.len()].clone();
+        self.notify(format!("Subtitles: {label}"));
+        self.sub_track = Some(name);
+        self.subtitles = true;
+    }
+
+    /// Which subtitles to show and how late, `None` if they're hidden.
+    pub fn subtitles(&self) -> Option<Choice<'_>> {
+        self.subtitles.then_some(Choice {
+            track: self.sub_track.as_deref(),
+            delay: self.sub_delay,
+        })
+    }
+
+    /// Whether the current file should stop playing.
+    pub fn stopping(&self) -> bool {
+        self.skip.is_some() || self.woken || STOP.load(Ordering::Relaxed)
+    }
+
+    fn notify(&mut self, text: String) {
+        self.notice = Some((text, Instant::now() + NOTICE_TIME));
+    }
+
+    /// Draws on top of the frame that was just written: the OSD at the
+    /// bottom, and the current notice in the top left corner.
+    pub fn draw<W: Write>(
+        &mut self,
+        w: &mut W,
+        status: &Status,
+    ) -> io::Result<()> {
+        self.measure_fps();
+        self.drawn_over.clear();
+
+        let rows = self.osd.rows();
+        if rows.max(self.osd_rows) > 0 {
+            let (columns, height) = terminal::size()
+                .ok()
+                .filter(|&(columns, rows)| columns > 0 && rows > 0)
+                .unwrap_or((80, 24));
+            let first = height.saturating_sub(rows.max(self.osd_rows)) + 1;
+            self.drawn_over.push(usize::from(first) - 1..usize::from(height));
+            for row in first..=height {
+                write!(w, "\x1b[{row};1H\x1b[0m\x1b[2K")?;
+            }
+
+            self.bar = None;
+            if self.osd == Osd::Stats {
+                write!(w, "\x1b[{};1H", height.saturating_sub(1))?;
+                self.draw_stats(w, status)?;
+            }
+            if self.osd != Osd::Off {
+                write!(w, "\x1b[{height};1H")?;
+                self.draw_time(w, status, columns, height)?;
+            }
+            self.osd_rows = rows;
+        }
+
+        self.expire_notice();
+        match &self.notice {
+            Some((text, _)) => {
+                self.drawn_over.push(0..1);
+                write!(w, "\x1b[1;1H\x1b[0;7m {text} \x1b[0m")
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Rows (from 0) the last [`Self::draw`] wrote over the frame.
+    pub fn drawn_over(&self) -> &[Range<usize>] {
+        &self.drawn_over
+    }
+
+    /// Draws a whole frame in the `--tui` layout. The stats show up in
+    /// the status bar when the OSD would've shown them.
+    pub fn draw_tui(
+        &mut self,
+        title: &str,
+        frame: &[u8],
+        status: &Status,
+    ) -> io::Result<()> {
+        self.measure_fps();
+        self.expire_notice();
+        let Some(tui) = &mut self.tui else {
+            return Ok(());
+        };
+
+        let view = View {
+            title,
+            status,
+            playlist: &self.playlist,
+            current: self.current,
+            repeat: self.repeat.name(),
+            fps: (self.osd == Osd::Stats).then_some(self.fps),
+            notice: self.notice.as_ref().map(|(text, _)| text.as_str()),
+        };
+        let gauge = tui.draw(frame, &view)?;
+        self.bar = (gauge.width >= 2).then_some(Bar {
+            row: gauge.y,
+            start: gauge.x,
+            width: gauge.width,
+        });
+        Ok(())
+    }
+
+    fn expire_notice(&mut self) {
+        if self
+            .notice
+            .as_ref()
+            .is_some_and(|(_, until)| Instant::now() >= *until)
+        {
+            self.notice = None;
+        }
+    }
+
+    /// Frames actually drawn in the last second or so.
+    fn measure_fps(&mut self) {
+        self.drawn += 1;
+        let elapsed = self.fps_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = f64::from(self.drawn) / elapsed.as_secs_f64();
+            self.drawn = 0;
+            self.fps_since = Instant::now();
+        }
+    }
+
+    fn draw_stats<W: Write>(
+        &self,
+        w: &mut W,
+        status: &Status,
+    ) -> io::Result<()> {
+        write!(w, "\x1b[7m ")?;
+        if let Some((frame, frames)) = status.frame {
+            write!(w, "frame {frame}/{frames} | ")?;
+        }
+        write!(w, "{:.1} fps", self.fps)?;
+        if status.frame.is_some() {
+            write!(w, " | {} dropped", status.dropped)?;
+            if status.stalls > 0 {
+                write!(w, " | {} stalled", status.stalls)?;
+            }
+        }
+        write!(w, " \x1b[0m")
+    }
+
+    /// The time, then a seek bar filling the rest of the row.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn draw_time<W: Write>(
+        &mut self,
+        w: &mut W,
+        status: &Status,
+        columns: u16,
+        row: u16,
+    ) -> io::Result<()> {
+        let (elapsed, total) = (status.elapsed, status.total);
+        let time =
+            format!(" {} / {} ", format_time(elapsed), format_time(total));
+        write!(w, "\x1b[7m{time}\x1b[0m ")?;
+
+        // Staying off the last column, some terminals scroll when it
+        // gets written to.
+        let start = u16::try_from(time.len() + 1).unwrap_or(u16::MAX);
+        let width = columns.saturating_sub(start + 1);
+        if width < 2 || total.is_zero() {
+            return Ok(());
+        }
+
+        let progress = elapsed.div_duration_f64(total).clamp(0.0, 1.0);
+        let filled = (progress * f64::from(width)).round() as usize;
+        let empty = usize::from(width) - filled;
+        write!(w, "{}{}", "=".repeat(filled), "-".repeat(empty))?;
+
+        self.bar = Some(Bar { row: row - 1, start, width });
+        Ok(())
+    }
+}
+
+/// A box in the middle of the screen listing every key.
+pub fn draw_help<W: Write>(w: &mut W) -> io::Result<()> {
+    let (columns, rows) = terminal::size()
+        .ok()
+        .filter(|&(columns, rows)| columns > 0 && rows > 0)
+        .unwrap_or((80, 24));
+
+    let keys_width = HELP.iter().map(|(keys, _)| keys.len()).max();
+    let keys_width = keys_width.unwrap_or_default();
+    let mut lines = vec![String::new()];
+    lines
+        .extend(HELP.iter().map(|(keys, action)| {
+            format!("  {keys:<keys_width$}  {action}  ")
+        }));
+    lines.push(String::new());
+    lines.push("  Press any key to go back  ".to_string());
+    lines.push(String::new());
+
+    let width = lines.iter().map(String::len).max().unwrap_or_default();
+    let height = u16::try_from(lines.len()).unwrap_or(u16::MAX);
+    let top = rows.saturating_sub(height) / 2 + 1;
+    let left = columns.saturating_sub(u16::try_from(width).unwrap_or(u16::MAX))
+        / 2
+        + 1;
+
+    for (row, line) in (top..).zip(&lines) {
+        write!(w, "\x1b[{row};{left}H\x1b[0;7m{line:<width$}\x1b[0m")?;
+    }
+    Ok(())
+}
+
+/// How the terminal was before the first `Keyboard` changed it.
+#[cfg(unix)]
+static ORIGINAL: std::sync::Mutex<Option<libc::termios>> =
+    std::sync::Mutex::new(None);
+
+/// Reads keys without waiting for Enter or echoing them, for as long as
+/// it's around. Output is left alone, unlike raw mode, so frames still
+/// get their newlines turned into line breaks.
+pub struct Keyboard {
+    #[cfg(unix)]
+    original: libc::termios,
+    mouse: Cell<bool>,
+}
+
+impl Keyboard {
+    /// `None` if there's no terminal to read keys from.
+    pub fn new() -> Option<Self> {
+        if !stdin().is_terminal() {
+            return None;
+        }
+
+        #[cfg(unix)]
+        unsafe {
+            let mut original = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &raw mut original) != 0 {
+                return None;
+            }
+            // Ones made while another's around would only get cbreak.
+            if let Ok(mut first) = ORIGINAL.lock() {
+                first.get_or_insert(original);
+            }
+            let mut cbreak = original;
+            cbreak.c_lflag &= !(libc::ICANON | libc::ECHO);
+            cbreak.c_cc[libc::VMIN] = 1;
+            cbreak.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(
+                libc::STDIN_FILENO,
+                libc::TCSANOW,
+                &raw const cbreak,
+            ) != 0
+            {
+                return None;
+            }
+            Some(Self { original, mouse: Cell::new(false) })
+        }
+
+        // Raw mode only touches the input side of a Windows console.
+        #[cfg(windows)]
+        {
+            crossterm::terminal::enable_raw_mode().ok()?;
+            Some(Self { mouse: Cell::new(false) })
+        }
+    }
+
+    fn capture_mouse(&self, capture: bool) {
+        if self.mouse.replace(capture) == capture {
+            return;
+        }
+        let _ = if capture {
+            execute!(stdout(), EnableMouseCapture)
+        } else {
+            execute!(stdout(), DisableMouseCapture)
+        };
+    }
+
+    /// Blocks until a key gets pressed, or Ctrl+C.
+    pub fn wait_for_key(&self) {
+        self.read_key();
+    }
+
+    /// Blocks until a key gets pressed, `None` after Ctrl+C.
+    #[allow(clippy::unused_self)]
+    pub fn read_key(&self) -> Option<KeyEvent> {
+        while !STOP.load(Ordering::Relaxed) {
+            if !event::poll(Duration::from_millis(50)).unwrap_or(false) {
+                continue;
+            }
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    return Some(key);
+                }
+                Err(_) => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Blocks until no key's been pressed for `idle`. `false` if it got
+    /// quit with q, Esc or Ctrl+C instead.
+    #[allow(clippy::unused_self)]
+    pub fn wait_idle(&self, idle: Duration) -> bool {
+        let mut since = Instant::now();
+        while !STOP.load(Ordering::Relaxed) {
+            let left = idle.saturating_sub(since.elapsed());
+            if left.is_zero() {
+                return true;
+            }
+            let poll = left.min(Duration::from_millis(50));
+            if !event::poll(poll).unwrap_or(false) {
+                continue;
+            }
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+                    match key.code {
+                        KeyCode::Char('c') if control => return false,
+                        KeyCode::Char('q') | KeyCode::Esc => return false,
+                        _ => since = Instant::now(),
+                    }
+                }
+                Err(_) => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Takes `&self` so keys only get read while the terminal is set up
+    /// for it.
+    #[allow(clippy::unused_self)]
+    fn next_action(&self) -> Option<Action> {
+        while event::poll(Duration::ZERO).ok()? {
+            let key = match event::read() {
+                Ok(Event::Key(key)) => key,
+                Ok(Event::Mouse(mouse)) => {
+                    if let MouseEventKind::Down(MouseButton::Left)
+                    | MouseEventKind::Drag(MouseButton::Left) = mouse.kind
+                    {
+                        return Some(Action::Click {
+                            column: mouse.column,
+                            row: mouse.row,
+                        });
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                // Windows raw mode turns Ctrl+C into a key press.
+                KeyCode::Char('c') if control => return Some(Action::Quit),
+                KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+                KeyCode::Char('r') => return Some(Action::CycleRepeat),
+                KeyCode::Char('t') => return Some(Action::ToggleTime),
+                KeyCode::Char('o') => return Some(Action::CycleOsd),
+                KeyCode::Char('?') => return Some(Action::Help),
+                KeyCode::Char('v') => return Some(Action::ToggleSubtitles),
+                KeyCode::Char('z') => {
+                    return Some(Action::SubDelay(-SUB_DELAY_STEP));
+                }
+                KeyCode::Char('x') => {
+                    return Some(Action::SubDelay(SUB_DELAY_STEP));
+                }
+                KeyCode::Char('j') => return Some(Action::CycleSubTrack),
+                KeyCode::Char('n' | '>') => {
+                    return Some(Action::Skip(Skip::Next));
+                }
+                KeyCode::Char('p' | '<') => {
+                    return Some(Action::Skip(Skip::Previous));
+                }
+                _ => return Some(Action::Unbound),
+            }
+        }
+        None
+    }
+}
+
+/// Puts the terminal's input back the way it was before any `Keyboard`,
+/// for after a panic, when none of them get dropped.
+pub fn restore_input() {
+    #[cfg(unix)]
+    if let Some(original) = ORIGINAL.try_lock().ok().and_then(|first| *first) {
+        unsafe {
+            libc::tcsetattr(
+                libc::STDIN_FILENO,
+                libc::TCSANOW,
+                &raw const original,
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    let _ = crossterm::terminal::disable_raw_mode();
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        self.capture_mouse(false);
+
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(
+                libc::STDIN_FILENO,
+                libc::TCSANOW,
+                &raw const self.original,
+            );
+        }
+
+        #[cfg(windows)]
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
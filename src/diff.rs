@@ -0,0 +1,247 @@
+// `bplay diff`, comparing two .bapple files: which frames look different
+// once decompressed (so recompressing alone doesn't count), how the sizes
+// changed, and what's different in the metadata and every other entry.
+// Exits with 1 when there's any difference, like diff(1), so it can be
+// used to check that an optimizer or another encoder didn't change what
+// gets shown.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Read,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{
+        Limits, entry_name, is_dictionary, is_frame, is_index, is_manifest,
+        is_metadata, is_signature, refuse_encrypted,
+    },
+    frames::{Dictionary, decompress, max_frame_size},
+    info::size,
+    metadata::Metadata,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// The .bapple file to compare against
+    pub old: PathBuf,
+    /// The .bapple file to compare
+    pub new: PathBuf,
+}
+
+/// What's in a file, with the frames still compressed.
+struct Side {
+    /// As they're stored.
+    frames: Vec<Vec<u8>>,
+    /// Which stored frame each played one is.
+    map: Vec<usize>,
+    /// Not compared by itself, only through the frames. The bytes are
+    /// empty without one.
+    dictionary: (Vec<u8>, Dictionary),
+    /// Everything else, by name.
+    entries: BTreeMap<PathBuf, Vec<u8>>,
+    metadata: Metadata,
+    file_size: u64,
+}
+
+impl Side {
+    fn read(path: &Path) -> Res<Self> {
+        refuse_encrypted(path)?;
+        let mut archive = Archive::new(File::open(path)?);
+        let limits = Limits::default();
+        let mut read = (0, 0);
+        let mut frames = Vec::new();
+        let mut entries = BTreeMap::new();
+        let mut metadata = Metadata::default();
+        let mut dictionary = (Vec::new(), Dictionary::default());
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let Some(name) = entry_name(&entry)? else {
+                continue;
+            };
+            limits.check(&mut read, entry.size())?;
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            if is_frame(&name) {
+                frames.push(content);
+                continue;
+            }
+            if is_dictionary(&name) {
+                dictionary = (content.clone(), Dictionary::new(&content));
+                continue;
+            }
+            // Changes along with everything else, which gets compared
+            // by itself.
+            if is_manifest(&name) || is_signature(&name) || is_index(&name) {
+                continue;
+            }
+            if is_metadata(&name) {
+                metadata = Metadata::parse(&name, &content).map_err(|e| {
+                    format!("{}: invalid metadata: {e}", path.display())
+                })?;
+            }
+            entries.insert(name, content);
+        }
+
+        let map = metadata
+            .frame_map(frames.len(), limits)?
+            .unwrap_or_else(|| (0..frames.len()).collect());
+        Ok(Self {
+            frames,
+            map,
+            dictionary,
+            entries,
+            metadata,
+            file_size: fs::metadata(path)?.len(),
+        })
+    }
+
+    fn played(&self, index: usize) -> &[u8] {
+        &self.frames[self.map[index]]
+    }
+
+    fn frames_size(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.len() as u64).sum()
+    }
+}
+
+pub fn run(args: &DiffArgs) -> Res<()> {
+    let old = Side::read(&args.old)?;
+    let new = Side::read(&args.new)?;
+    let mut differs = false;
+
+    if old.map.len() == new.map.len() {
+        println!("Frames: {}", old.map.len());
+    } else {
+        println!("Frames: {} vs {}", old.map.len(), new.map.len());
+        differs = true;
+    }
+    let changed = changed_frames(&old, &new)?;
+    if !changed.is_empty() {
+        let count: usize = changed.iter().map(ExactSizeIterator::len).sum();
+        println!("Frames that look different: {count}, {}", ranges(&changed));
+        differs = true;
+    }
+    println!(
+        "Frames size: {}",
+        compare_sizes(old.frames_size(), new.frames_size())
+    );
+    println!("File size: {}", compare_sizes(old.file_size, new.file_size));
+
+    differs |= diff_metadata(&old.metadata, &new.metadata);
+    differs |= diff_entries(args, &old.entries, &new.entries);
+
+    if differs {
+        exit(1);
+    }
+    println!("No differences in what gets played");
+    Ok(())
+}
+
+/// Frames in both files that decompress to something different, as runs.
+fn changed_frames(old: &Side, new: &Side) -> Res<Vec<Range<usize>>> {
+    let limit = max_frame_size();
+    let mut changed: Vec<Range<usize>> = Vec::new();
+    for index in 0..old.map.len().min(new.map.len()) {
+        let (a, b) = (old.played(index), new.played(index));
+        // The same bytes can't decompress to anything different, unless
+        // the dictionaries are.
+        let same = a == b && old.dictionary.0 == new.dictionary.0;
+        if same
+            || decompress(a, &old.dictionary.1, limit)?
+                == decompress(b, &new.dictionary.1, limit)?
+        {
+            continue;
+        }
+        match changed.last_mut() {
+            Some(run) if run.end == index => run.end += 1,
+            _ => changed.push(index..index + 1),
+        }
+    }
+    Ok(changed)
+}
+
+/// Like `3, 10..12`, with the ends included, and only the first few.
+fn ranges(runs: &[Range<usize>]) -> String {
+    const SHOWN: usize = 10;
+    let mut listed: Vec<String> = runs
+        .iter()
+        .take(SHOWN)
+        .map(|run| {
+            if run.len() == 1 {
+                run.start.to_string()
+            } else {
+                format!("{}..{}", run.start, run.end - 1)
+            }
+        })
+        .collect();
+    if runs.len() > SHOWN {
+        listed.push(format!("and {} more", runs.len() - SHOWN));
+    }
+    listed.join(", ")
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn compare_sizes(old: u64, new: u64) -> String {
+    if old == new {
+        return size(old);
+    }
+    let change = if old == 0 {
+        String::new()
+    } else {
+        format!(" ({:+.1}%)", (new as f64 - old as f64) / old as f64 * 100.0)
+    };
+    format!("{} vs {}{change}", size(old), size(new))
+}
+
+fn diff_metadata(old: &Metadata, new: &Metadata) -> bool {
+    let mut differs = false;
+    for key in Metadata::KEYS {
+        // Neither changes what gets played, and repeats were already
+        // gone through with the frames.
+        if key == "created" || key == "repeats" {
+            continue;
+        }
+        let (a, b) = (old.get(key), new.get(key));
+        if a != b {
+            if !differs {
+                println!("Metadata:");
+            }
+            let show = |value: Option<String>| value.unwrap_or("-".into());
+            println!("  {key}: {} vs {}", show(a), show(b));
+            differs = true;
+        }
+    }
+    differs
+}
+
+/// Everything but the frames and metadata, compared byte for byte.
+fn diff_entries(
+    args: &DiffArgs,
+    old: &BTreeMap<PathBuf, Vec<u8>>,
+    new: &BTreeMap<PathBuf, Vec<u8>>,
+) -> bool {
+    let mut differs = false;
+    let names = old.keys().chain(new.keys().filter(|k| !old.contains_key(*k)));
+    for name in names.filter(|name| !is_metadata(name)) {
+        let what = match (old.get(name), new.get(name)) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(a), Some(b)) => format!(
+                "differs ({})",
+                compare_sizes(a.len() as u64, b.len() as u64)
+            ),
+            (Some(_), None) => format!("only in {}", args.old.display()),
+            (None, _) => format!("only in {}", args.new.display()),
+        };
+        println!("{}: {what}", name.display());
+        differs = true;
+    }
+    differs
+}
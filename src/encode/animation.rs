@@ -0,0 +1,89 @@
+use std::{fs::File, io::BufReader};
+
+use image::{
+    AnimationDecoder, DynamicImage, Frames, ImageFormat, ImageReader,
+    codecs::{gif::GifDecoder, png::PngDecoder},
+};
+
+use crate::{
+    Res,
+    archive::ArchiveWriter,
+    encode::{Encoder, Resampler},
+    metadata::Metadata,
+};
+
+// What browsers do for GIFs that claim to have no delay at all.
+const DEFAULT_DELAY_MICROS: u64 = 100_000;
+
+pub fn encode(encoder: &Encoder, path: &std::path::Path) -> Res<()> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format();
+    let input = BufReader::new(File::open(path)?);
+
+    let frames: Frames<'_> = match format {
+        Some(ImageFormat::Gif) => GifDecoder::new(input)?.into_frames(),
+        Some(ImageFormat::Png) => PngDecoder::new(input)?.apng()?.into_frames(),
+        _ => {
+            return Err(format!(
+                "{}: not an animated GIF or APNG",
+                path.display()
+            )
+            .into());
+        }
+    };
+
+    let args = encoder.args;
+    let (width, height) = (encoder.width, encoder.height);
+    println!("Encoding {} to fit {width}x{height}...", path.display());
+
+    let mut resampler = args.fps.map(Resampler::new);
+    let mut writer = ArchiveWriter::create(encoder.output)?;
+    encoder.append_poster(&mut writer)?;
+    encoder.append_audio(&mut writer)?;
+    encoder.append_subtitles(&mut writer)?;
+    let mut frametimes = Vec::new();
+
+    for frame in frames {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = match u64::from(numer) * 1000 / u64::from(denom.max(1)) {
+            0 => DEFAULT_DELAY_MICROS,
+            delay => delay,
+        };
+        frametimes.push(delay);
+
+        let repeats = resampler.as_mut().map_or(1, |r| r.repeats(delay));
+        if repeats == 0 {
+            continue;
+        }
+
+        let image = DynamicImage::ImageRgba8(frame.into_buffer());
+        let compressed = encoder.compress_frame(&image)?;
+        for _ in 0..repeats {
+            writer.append_frame(&compressed)?;
+        }
+    }
+
+    if frametimes.is_empty() {
+        return Err(format!("{}: no frames", path.display()).into());
+    }
+
+    let metadata = if let Some(resampler) = resampler {
+        Metadata { frametime: resampler.frametime(), ..encoder.metadata() }
+    } else {
+        // Players that don't know about `frametimes` use the average.
+        let total: u64 = frametimes.iter().sum();
+        Metadata {
+            frametime: total / frametimes.len() as u64,
+            frametimes,
+            ..encoder.metadata()
+        }
+    };
+    writer.append_metadata(&metadata)?;
+
+    writer.append_manifest()?;
+    let frames = writer.frames();
+    writer.finish()?;
+    println!("Wrote {frames} frames to {}", args.output.display());
+    Ok(())
+}
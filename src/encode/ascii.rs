@@ -0,0 +1,180 @@
+use clap::ValueEnum;
+use image::GrayImage;
+
+pub const DEFAULT_RAMP: &str = " .:-=+*#%@";
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Style {
+    /// Pick characters by brightness, along the ramp
+    Luminance,
+    /// Line art, following the edges in the image
+    Edges,
+}
+
+/// Turns grayscale pixels into characters.
+pub struct Converter {
+    style: Style,
+    /// Character for every possible luminance value.
+    lut: Vec<char>,
+    /// Minimum gradient strength, from 0 to 1, to count as an edge.
+    edge_threshold: f32,
+}
+
+impl Converter {
+    /// `ramp` goes from darkest to brightest, and must not be empty.
+    /// A `gamma` above 1 brightens midtones, below 1 darkens them.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn new(
+        style: Style,
+        ramp: &str,
+        gamma: f32,
+        edge_threshold: f32,
+    ) -> Self {
+        let ramp = ramp.chars().collect::<Vec<_>>();
+        let last = (ramp.len() - 1) as f32;
+
+        let lut = (0..=u8::MAX)
+            .map(|luma| {
+                let corrected = (f32::from(luma) / 255.0).powf(gamma.recip());
+                ramp[(corrected * last).round() as usize]
+            })
+            .collect();
+
+        Self { style, lut, edge_threshold }
+    }
+
+    /// Picks a character for every pixel, row by row.
+    pub fn cells(&self, image: &GrayImage) -> Vec<char> {
+        let (width, height) = image.dimensions();
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| match self.style {
+                Style::Luminance => {
+                    self.lut[usize::from(image.get_pixel(x, y).0[0])]
+                }
+                Style::Edges => self.edge(image, x, y),
+            })
+            .collect()
+    }
+
+    /// Runs a Sobel operator around a pixel, and picks a character
+    /// following the edge's orientation.
+    fn edge(&self, image: &GrayImage, x: u32, y: u32) -> char {
+        // Clamp to the borders instead of treating them as black.
+        let at = |dx: i32, dy: i32| {
+            let x = x.saturating_add_signed(dx).min(image.width() - 1);
+            let y = y.saturating_add_signed(dy).min(image.height() - 1);
+            f32::from(image.get_pixel(x, y).0[0])
+        };
+
+        let gx = at(1, -1) + 2.0 * at(1, 0) + at(1, 1)
+            - at(-1, -1)
+            - 2.0 * at(-1, 0)
+            - at(-1, 1);
+        let gy = at(-1, 1) + 2.0 * at(0, 1) + at(1, 1)
+            - at(-1, -1)
+            - 2.0 * at(0, -1)
+            - at(1, -1);
+
+        // A hard black to white step gives 4 * 255 on one axis.
+        if gx.hypot(gy) / (4.0 * 255.0) < self.edge_threshold {
+            return ' ';
+        }
+
+        // The edge runs perpendicular to the gradient. Y grows downwards,
+        // so a gradient pointing down-right means an edge going up-right.
+        let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+        match angle {
+            a if !(22.5..157.5).contains(&a) => '|',
+            a if a < 67.5 => '/',
+            a if a < 112.5 && gy > 0.0 => '_',
+            a if a < 112.5 => '-',
+            _ => '\\',
+        }
+    }
+}
+
+/// Lays out cells as text, one row per line.
+pub fn render(cells: &[char], width: usize) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(cells.len() + cells.len() / width);
+    let mut buf = [0; 4];
+
+    for (y, row) in cells.chunks(width).enumerate() {
+        if y != 0 {
+            frame.push(b'\n');
+        }
+        for c in row {
+            frame.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+
+    frame
+}
+
+/// Largest size that fits in `max_width` x `max_height` cells while
+/// keeping the aspect ratio, given cells `cell_aspect` times taller
+/// than they're wide.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn fit(
+    (width, height): (u32, u32),
+    (max_width, max_height): (u32, u32),
+    cell_aspect: f32,
+) -> (u32, u32) {
+    let scale = (max_width as f32 / width as f32)
+        .min(max_height as f32 * cell_aspect / height as f32);
+
+    (
+        ((width as f32 * scale).round() as u32).clamp(1, max_width),
+        ((height as f32 * scale / cell_aspect).round() as u32)
+            .clamp(1, max_height),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    #[test]
+    fn gamma_brightens_midtones() {
+        let image = GrayImage::from_pixel(1, 1, Luma([32]));
+        let linear = Converter::new(Style::Luminance, " .#", 1.0, 0.0);
+        let brighter = Converter::new(Style::Luminance, " .#", 2.0, 0.0);
+
+        assert_eq!(linear.cells(&image), [' ']);
+        assert_eq!(brighter.cells(&image), ['.']);
+    }
+
+    #[test]
+    fn follows_edges() {
+        let converter = Converter::new(Style::Edges, DEFAULT_RAMP, 1.0, 0.5);
+        let vertical = GrayImage::from_fn(4, 4, |x, _| {
+            Luma([if x < 2 { 0 } else { 255 }])
+        });
+        let horizontal = GrayImage::from_fn(4, 4, |_, y| {
+            Luma([if y < 2 { 0 } else { 255 }])
+        });
+
+        let text = render(&converter.cells(&vertical), 4);
+        assert_eq!(text, b" || \n || \n || \n || ");
+        let text = render(&converter.cells(&horizontal), 4);
+        assert_eq!(text, b"    \n____\n____\n    ");
+    }
+
+    #[test]
+    fn fits_keeping_the_aspect_ratio() {
+        // Cells twice as tall as they're wide, so the height gets halved.
+        assert_eq!(fit((16, 16), (80, 24), 2.0), (48, 24));
+        assert_eq!(fit((1000, 10), (80, 24), 2.0), (80, 1));
+    }
+}
@@ -0,0 +1,116 @@
+// Gets any audio (or the audio track of a video) into the MP3 the player
+// expects, so there's no separate ffmpeg step.
+
+use std::{fs, fs::File, path::Path};
+
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm};
+use rodio::{Decoder, Source, source::UniformSourceIterator};
+
+use crate::Res;
+
+/// Sample rates MP3 can actually store.
+const MP3_SAMPLE_RATES: [u32; 9] =
+    [8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+/// Interleaved samples handed to LAME at once.
+const CHUNK: usize = 8192;
+
+/// MP3 files get embedded byte for byte, anything else gets transcoded.
+pub fn load(path: &Path, bitrate: u16) -> Res<Vec<u8>> {
+    let is_mp3 = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"));
+
+    if is_mp3 {
+        return Ok(fs::read(path)?);
+    }
+
+    println!("Transcoding {} to MP3...", path.display());
+    transcode(path, bitrate)
+}
+
+fn transcode(path: &Path, bitrate: u16) -> Res<Vec<u8>> {
+    let decoder = Decoder::try_from(File::open(path)?)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let sample_rate = if MP3_SAMPLE_RATES.contains(&decoder.sample_rate()) {
+        decoder.sample_rate()
+    } else {
+        44100
+    };
+    // LAME only takes interleaved input as stereo.
+    let samples = UniformSourceIterator::new(decoder, 2, sample_rate);
+    to_mp3(samples, sample_rate, bitrate)
+}
+
+/// Encodes interleaved stereo samples, at a rate MP3 can store.
+pub fn to_mp3(
+    samples: impl IntoIterator<Item = f32>,
+    sample_rate: u32,
+    bitrate: u16,
+) -> Res<Vec<u8>> {
+    let mut builder = Builder::new().ok_or("Failed to initialize LAME")?;
+    builder.set_num_channels(2)?;
+    builder.set_sample_rate(sample_rate)?;
+    builder.set_brate(to_bitrate(bitrate))?;
+    let mut lame = builder.build()?;
+
+    let mut mp3 = Vec::new();
+    let mut chunk = Vec::with_capacity(CHUNK);
+
+    for sample in samples {
+        chunk.push(sample);
+        if chunk.len() == CHUNK {
+            mp3.reserve(mp3lame_encoder::max_required_buffer_size(CHUNK / 2));
+            lame.encode_to_vec(InterleavedPcm(&chunk), &mut mp3)?;
+            chunk.clear();
+        }
+    }
+
+    // The decoder may end mid-frame, leaving an odd sample count.
+    chunk.truncate(chunk.len() & !1);
+    mp3.reserve(mp3lame_encoder::max_required_buffer_size(chunk.len() / 2));
+    lame.encode_to_vec(InterleavedPcm(&chunk), &mut mp3)?;
+    mp3.reserve(mp3lame_encoder::max_required_buffer_size(0));
+    lame.flush_to_vec::<FlushNoGap>(&mut mp3)?;
+
+    // LAME leaves room for an Info frame at the start, which only gets
+    // filled in now. Without it, the player can't tell the duration.
+    let mut tag = Vec::with_capacity(lame.lame_tag_size());
+    if lame.lame_tag_encode_to_vec(&mut tag).is_some() {
+        let start = lame.id3v2_tag_size();
+        if let Some(placeholder) = mp3.get_mut(start..start + tag.len()) {
+            placeholder.copy_from_slice(&tag);
+        }
+    }
+
+    Ok(mp3)
+}
+
+fn to_bitrate(kbps: u16) -> Bitrate {
+    match kbps {
+        96 => Bitrate::Kbps96,
+        128 => Bitrate::Kbps128,
+        160 => Bitrate::Kbps160,
+        256 => Bitrate::Kbps256,
+        320 => Bitrate::Kbps320,
+        _ => Bitrate::Kbps192,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempPath;
+
+    #[test]
+    fn embeds_mp3_as_is() {
+        let dir = TempPath::new();
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("song.MP3");
+        // Not even a real MP3, since it doesn't get decoded.
+        fs::write(&path, b"ID3 whatever").unwrap();
+
+        assert_eq!(load(&path, 128).unwrap(), b"ID3 whatever");
+    }
+}
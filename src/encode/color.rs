@@ -0,0 +1,315 @@
+// Colored output. Every cell keeps the character picked by the converter,
+// and gets a foreground color quantized down to what the target terminal
+// supports.
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use image::RgbImage;
+
+use crate::encode::ascii::render;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ColorMode {
+    /// The 16 basic ANSI colors
+    #[value(name = "16")]
+    Ansi16,
+    /// The xterm 256 color palette
+    #[value(name = "256")]
+    Ansi256,
+    /// 24-bit RGB
+    Truecolor,
+}
+
+/// What the 16 basic colors actually look like, which varies by terminal.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Palette {
+    Xterm,
+    Vga,
+    /// Campbell, the Windows Terminal default
+    Windows,
+}
+
+type Rgb = [u8; 3];
+
+const XTERM: [Rgb; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+const VGA: [Rgb; 16] = [
+    [0, 0, 0],
+    [170, 0, 0],
+    [0, 170, 0],
+    [170, 85, 0],
+    [0, 0, 170],
+    [170, 0, 170],
+    [0, 170, 170],
+    [170, 170, 170],
+    [85, 85, 85],
+    [255, 85, 85],
+    [85, 255, 85],
+    [255, 255, 85],
+    [85, 85, 255],
+    [255, 85, 255],
+    [85, 255, 255],
+    [255, 255, 255],
+];
+
+const CAMPBELL: [Rgb; 16] = [
+    [12, 12, 12],
+    [197, 15, 31],
+    [19, 161, 14],
+    [193, 156, 0],
+    [0, 55, 218],
+    [136, 23, 152],
+    [58, 150, 221],
+    [204, 204, 204],
+    [118, 118, 118],
+    [231, 72, 86],
+    [22, 198, 12],
+    [249, 241, 165],
+    [59, 120, 255],
+    [180, 0, 158],
+    [97, 214, 214],
+    [242, 242, 242],
+];
+
+/// Levels of each channel in the 6x6x6 cube of the 256 color palette.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
+pub struct Colorizer {
+    mode: ColorMode,
+    palette: &'static [Rgb; 16],
+    max_escapes: Option<usize>,
+}
+
+impl Colorizer {
+    pub fn new(
+        mode: ColorMode,
+        palette: Palette,
+        max_escapes: Option<usize>,
+    ) -> Self {
+        let palette = match palette {
+            Palette::Xterm => &XTERM,
+            Palette::Vga => &VGA,
+            Palette::Windows => &CAMPBELL,
+        };
+        Self { mode, palette, max_escapes }
+    }
+
+    /// Renders `cells` with a color change only where the color
+    /// actually changes. If that takes more escapes than allowed,
+    /// similar colors get merged until it fits.
+    pub fn paint(&self, cells: &[char], image: &RgbImage) -> Vec<u8> {
+        let width = image.width() as usize;
+        if width == 0 {
+            return render(cells, 1);
+        }
+
+        let colors = image
+            .pixels()
+            .map(|pixel| self.quantize(pixel.0))
+            .collect::<Vec<_>>();
+
+        let mut tolerance = 0;
+        loop {
+            let (frame, escapes) = self.draw(cells, width, &colors, tolerance);
+
+            // Past this, everything is the same color anyway.
+            let exhausted = tolerance > 3 * 255 * 255;
+            if exhausted || self.max_escapes.is_none_or(|max| escapes <= max) {
+                return frame;
+            }
+            tolerance = (tolerance * 2).max(16 * 16);
+        }
+    }
+
+    /// `tolerance` is the squared distance under which colors get merged.
+    fn draw(
+        &self,
+        cells: &[char],
+        width: usize,
+        colors: &[Color],
+        tolerance: u32,
+    ) -> (Vec<u8>, usize) {
+        let mut frame = Vec::with_capacity(cells.len() * 4);
+        let mut current: Option<Color> = None;
+        let mut escapes = 0;
+        let mut buf = [0; 4];
+
+        for (i, (c, color)) in cells.iter().zip(colors).enumerate() {
+            if i != 0 && i % width == 0 {
+                frame.push(b'\n');
+            }
+
+            let same = current.is_some_and(|current| {
+                distance(self.rgb(current), self.rgb(*color)) <= tolerance
+            });
+            if !same {
+                self.write_sgr(&mut frame, *color);
+                current = Some(*color);
+                escapes += 1;
+            }
+
+            frame.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+
+        frame.extend_from_slice(b"\x1b[0m");
+        (frame, escapes)
+    }
+
+    fn quantize(&self, rgb: Rgb) -> Color {
+        match self.mode {
+            ColorMode::Truecolor => Color::Rgb(rgb),
+            ColorMode::Ansi16 => Color::Indexed(nearest(self.palette, rgb)),
+            ColorMode::Ansi256 => {
+                let level = |channel: u8| nearest_level(channel);
+                let cube = [level(rgb[0]), level(rgb[1]), level(rgb[2])];
+                let cube_index = 16 + 36 * cube[0] + 6 * cube[1] + cube[2];
+
+                let average =
+                    rgb.iter().map(|&c| u32::from(c)).sum::<u32>() / 3;
+                let gray_step =
+                    u8::try_from((average.saturating_sub(8) + 5) / 10)
+                        .unwrap_or(u8::MAX)
+                        .min(23);
+                let gray_index = 232 + gray_step;
+
+                let cube_distance =
+                    distance(self.rgb(Color::Indexed(cube_index)), rgb);
+                let gray_distance =
+                    distance(self.rgb(Color::Indexed(gray_index)), rgb);
+                if gray_distance < cube_distance {
+                    Color::Indexed(gray_index)
+                } else {
+                    Color::Indexed(cube_index)
+                }
+            }
+        }
+    }
+
+    /// What a color looks like, to compare it with others.
+    fn rgb(&self, color: Color) -> Rgb {
+        match color {
+            Color::Rgb(rgb) => rgb,
+            Color::Indexed(index @ 0..16) => self.palette[usize::from(index)],
+            Color::Indexed(index @ 16..232) => {
+                let index = index - 16;
+                [
+                    CUBE[usize::from(index / 36)],
+                    CUBE[usize::from(index / 6 % 6)],
+                    CUBE[usize::from(index % 6)],
+                ]
+            }
+            Color::Indexed(index) => {
+                let gray = 8 + 10 * (index - 232);
+                [gray; 3]
+            }
+        }
+    }
+
+    fn write_sgr(&self, frame: &mut Vec<u8>, color: Color) {
+        // Writing to a Vec never fails.
+        let _ = match (self.mode, color) {
+            (ColorMode::Ansi16, Color::Indexed(index @ 0..8)) => {
+                write!(frame, "\x1b[{}m", 30 + index)
+            }
+            (ColorMode::Ansi16, Color::Indexed(index)) => {
+                write!(frame, "\x1b[{}m", 90 + index - 8)
+            }
+            (_, Color::Indexed(index)) => write!(frame, "\x1b[38;5;{index}m"),
+            (_, Color::Rgb([r, g, b])) => {
+                write!(frame, "\x1b[38;2;{r};{g};{b}m")
+            }
+        };
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    a.iter().zip(b).map(|(&a, b)| u32::from(a.abs_diff(b)).pow(2)).sum()
+}
+
+fn nearest(palette: &[Rgb; 16], rgb: Rgb) -> u8 {
+    (0..16).min_by_key(|&i| distance(palette[usize::from(i)], rgb)).unwrap_or(0)
+}
+
+fn nearest_level(channel: u8) -> u8 {
+    (0..6).min_by_key(|&i| CUBE[usize::from(i)].abs_diff(channel)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb as Pixel;
+
+    use super::*;
+
+    fn paint(
+        mode: ColorMode,
+        max_escapes: Option<usize>,
+        pixels: &[Rgb],
+    ) -> String {
+        let width = u32::try_from(pixels.len()).unwrap();
+        let image =
+            RgbImage::from_fn(width, 1, |x, _| Pixel(pixels[x as usize]));
+        let cells = vec!['#'; pixels.len()];
+        let colorizer = Colorizer::new(mode, Palette::Xterm, max_escapes);
+        String::from_utf8(colorizer.paint(&cells, &image)).unwrap()
+    }
+
+    #[test]
+    fn quantizes_to_the_mode() {
+        let red = [[255, 0, 0]];
+        assert_eq!(paint(ColorMode::Ansi16, None, &red), "\x1b[91m#\x1b[0m");
+        assert_eq!(
+            paint(ColorMode::Ansi256, None, &red),
+            "\x1b[38;5;196m#\x1b[0m"
+        );
+        assert_eq!(
+            paint(ColorMode::Truecolor, None, &red),
+            "\x1b[38;2;255;0;0m#\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn only_changes_color_when_needed() {
+        let reds = [[255, 0, 0], [255, 0, 0], [0, 0, 255]];
+        assert_eq!(
+            paint(ColorMode::Ansi16, None, &reds),
+            "\x1b[91m##\x1b[34m#\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn merges_colors_to_stay_under_the_limit() {
+        let reds = [[250, 0, 0], [255, 0, 0]];
+        assert_eq!(
+            paint(ColorMode::Truecolor, None, &reds).matches('\x1b').count(),
+            3
+        );
+        assert_eq!(
+            paint(ColorMode::Truecolor, Some(1), &reds),
+            "\x1b[38;2;250;0;0m##\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use glob::glob;
+
+use crate::{
+    Res,
+    archive::ArchiveWriter,
+    encode::{Encoder, Resampler},
+    metadata::Metadata,
+};
+
+pub fn encode(encoder: &Encoder, pattern: &str) -> Res<()> {
+    let paths = sequence(pattern)?;
+    let args = encoder.args;
+    let (width, height) = (encoder.width, encoder.height);
+
+    println!("Encoding {} frames to fit {width}x{height}...", paths.len());
+
+    let mut resampler = args.fps.map(Resampler::new);
+    let mut writer = ArchiveWriter::create(encoder.output)?;
+    encoder.append_poster(&mut writer)?;
+    encoder.append_audio(&mut writer)?;
+    encoder.append_subtitles(&mut writer)?;
+    writer.append_metadata(&Metadata {
+        frametime: resampler
+            .as_ref()
+            .map_or(args.frametime, Resampler::frametime),
+        ..encoder.metadata()
+    })?;
+
+    for path in &paths {
+        let repeats =
+            resampler.as_mut().map_or(1, |r| r.repeats(args.frametime));
+        if repeats == 0 {
+            continue;
+        }
+
+        let image = image::open(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let compressed = encoder.compress_frame(&image)?;
+        for _ in 0..repeats {
+            writer.append_frame(&compressed)?;
+        }
+    }
+
+    writer.append_manifest()?;
+    let frames = writer.frames();
+    writer.finish()?;
+    println!("Wrote {frames} frames to {}", args.output.display());
+    Ok(())
+}
+
+/// Expands the glob, ordering paths by the number in their names,
+/// so `2.png` comes before `10.png`.
+fn sequence(pattern: &str) -> Res<Vec<PathBuf>> {
+    let mut paths = glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No images matched '{pattern}'"),
+        )
+        .into());
+    }
+
+    paths.sort_by_cached_key(|path| (frame_number(path), path.clone()));
+    Ok(paths)
+}
+
+fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits = stem
+        .rsplit(|c: char| !c.is_ascii_digit())
+        .find(|part| !part.is_empty())?;
+    digits.parse().ok()
+}
@@ -0,0 +1,510 @@
+// Builds .bapple files straight from images, so simple stuff doesn't
+// need the whole ffmpeg + asciic dance.
+
+mod animation;
+mod ascii;
+pub mod audio;
+mod color;
+mod images;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ArgGroup;
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
+use zstd::encode_all;
+
+use crate::{
+    Res,
+    archive::ArchiveWriter,
+    encode::{
+        ascii::{Converter, DEFAULT_RAMP, Style, fit, render},
+        color::{ColorMode, Colorizer, Palette},
+    },
+    encryption::{Plain, encrypt, read_password},
+    metadata::{Metadata, TrackLabel, today},
+    optimize::{Options, optimize},
+    subtitles::Subtitles,
+};
+
+#[derive(clap::Args, Debug)]
+#[command(group(ArgGroup::new("input").required(true)))]
+pub struct EncodeArgs {
+    /// Glob matching a numbered image sequence, e.g. 'frames/*.png'
+    #[arg(long, value_name = "PATTERN", group = "input")]
+    pub images: Option<String>,
+    /// Animated GIF or APNG, keeping its per-frame delays
+    #[arg(long, value_name = "FILE", group = "input")]
+    pub animation: Option<PathBuf>,
+    /// Where to write the .bapple file
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// How long each image stays on screen, in microseconds
+    #[arg(
+        long,
+        default_value = "33333",
+        conflicts_with = "animation",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub frametime: u64,
+    /// Convert to this framerate, dropping or duplicating frames
+    #[arg(long, value_parser = validate_positive)]
+    pub fps: Option<f32>,
+    /// Width in columns [default: terminal width]
+    #[arg(long)]
+    pub width: Option<u16>,
+    /// Height in rows [default: terminal height]
+    #[arg(long)]
+    pub height: Option<u16>,
+    /// Fill the whole width and height, ignoring the aspect ratio
+    #[arg(long)]
+    pub stretch: bool,
+    /// How many times taller a terminal cell is than it's wide
+    #[arg(long, default_value_t = 2.0, value_parser = validate_positive)]
+    pub cell_aspect: f32,
+    /// How pixels become characters
+    #[arg(long, value_enum, default_value_t = Style::Luminance)]
+    pub style: Style,
+    /// How strong an edge has to be to get drawn, from 0 to 1
+    #[arg(long, default_value_t = 0.25, value_parser = validate_unit)]
+    pub edge_threshold: f32,
+    /// Characters to draw with, from darkest to brightest
+    #[arg(long, default_value = DEFAULT_RAMP, value_parser = validate_ramp)]
+    pub ramp: String,
+    /// Gamma applied to luminance; above 1 brightens, below 1 darkens
+    #[arg(long, default_value_t = 1.0, value_parser = validate_positive)]
+    pub gamma: f32,
+    /// Keep colors, quantized to this many [default: monochrome]
+    #[arg(long, value_enum)]
+    pub color: Option<ColorMode>,
+    /// What the 16 basic colors look like on the target terminal
+    #[arg(long, value_enum, default_value_t = Palette::Xterm)]
+    pub palette: Palette,
+    /// Most color changes per frame, merging similar colors to fit
+    #[arg(long, requires = "color")]
+    pub max_escapes: Option<usize>,
+    /// Image shown while the file loads, converted like the frames
+    #[arg(long, value_name = "IMAGE")]
+    pub poster: Option<PathBuf>,
+    /// Audio to embed, from any audio or video file
+    #[arg(long, value_name = "FILE")]
+    pub audio: Option<PathBuf>,
+    /// MP3 bitrate in kbps, when the audio needs transcoding
+    #[arg(
+        long,
+        default_value = "192",
+        value_parser = ["96", "128", "160", "192", "256", "320"]
+    )]
+    pub audio_bitrate: String,
+    /// Language of the audio, like en
+    #[arg(long, value_name = "LANG", requires = "audio")]
+    pub audio_language: Option<String>,
+    /// What to call the audio track, like English
+    #[arg(long, value_name = "NAME", requires = "audio_language")]
+    pub audio_name: Option<String>,
+    /// Subtitles to embed, as LANG=FILE.srt, once per track
+    #[arg(long, value_name = "LANG=FILE", value_parser = validate_subs)]
+    pub subs: Vec<(String, PathBuf)>,
+    /// What to call a subtitle track, as LANG=NAME, like en=English
+    #[arg(
+        long,
+        value_name = "LANG=NAME",
+        requires = "subs",
+        value_parser = validate_sub_name
+    )]
+    pub sub_name: Vec<(String, String)>,
+    /// Title stored in the metadata
+    #[arg(long)]
+    pub title: Option<String>,
+    /// Author stored in the metadata
+    #[arg(long)]
+    pub author: Option<String>,
+    /// Description stored in the metadata
+    #[arg(long)]
+    pub description: Option<String>,
+    /// Where the video came from, stored in the metadata
+    #[arg(long)]
+    pub source: Option<String>,
+    /// zstd compression level
+    #[arg(long, default_value_t = zstd::DEFAULT_COMPRESSION_LEVEL)]
+    pub compression_level: i32,
+    /// Train a zstd dictionary on the frames once they're all encoded and
+    /// compress them against it, which makes small frames a lot smaller
+    #[arg(long)]
+    pub dictionary: bool,
+    /// Encrypt the file with the password in --password-file
+    #[arg(long, requires = "password_file")]
+    pub encrypt: bool,
+    /// A file with the password to encrypt with in it
+    #[arg(long, value_name = "FILE", requires = "encrypt")]
+    pub password_file: Option<PathBuf>,
+}
+
+/// Everything needed to turn an image into a compressed frame.
+struct Encoder<'a> {
+    args: &'a EncodeArgs,
+    /// Where the file gets written, which is only `args.output` if it
+    /// isn't going to be encrypted.
+    output: &'a Path,
+    converter: Converter,
+    colorizer: Option<Colorizer>,
+    /// The area frames have to fit in.
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Encoder<'a> {
+    /// Size falls back to the current terminal, or 80x24 if there's none.
+    fn new(args: &'a EncodeArgs, output: &'a Path) -> Self {
+        let (columns, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+
+        Self {
+            args,
+            output,
+            converter: Converter::new(
+                args.style,
+                &args.ramp,
+                args.gamma,
+                args.edge_threshold,
+            ),
+            colorizer: args.color.map(|mode| {
+                Colorizer::new(mode, args.palette, args.max_escapes)
+            }),
+            width: u32::from(args.width.unwrap_or(columns)).max(1),
+            height: u32::from(args.height.unwrap_or(rows)).max(1),
+        }
+    }
+
+    /// Metadata shared by every kind of input, timing is up to the caller.
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            version: Metadata::VERSION,
+            title: self.args.title.clone(),
+            author: self.args.author.clone(),
+            description: self.args.description.clone(),
+            source: self.args.source.clone(),
+            created: Some(today()),
+            // Edges are drawn with line characters, not along the ramp.
+            charset: matches!(self.args.style, Style::Luminance)
+                .then(|| self.args.ramp.clone()),
+            audio_track: self.args.audio_language.as_ref().map(|language| {
+                TrackLabel {
+                    language: language.clone(),
+                    name: self.args.audio_name.clone().unwrap_or_default(),
+                }
+            }),
+            subtitle_tracks: self
+                .args
+                .sub_name
+                .iter()
+                .map(|(language, name)| TrackLabel {
+                    language: language.clone(),
+                    name: name.clone(),
+                })
+                .collect(),
+            // Players from before it would take it for a frame.
+            required: vec!["manifest".to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// Embeds the poster, if there is one.
+    fn append_poster<W: Write>(
+        &self,
+        writer: &mut ArchiveWriter<W>,
+    ) -> Res<()> {
+        if let Some(path) = &self.args.poster {
+            let image = image::open(path)
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            writer.append_poster(&self.compress_frame(&image)?)?;
+        }
+        Ok(())
+    }
+
+    /// Embeds the audio track, if there is one.
+    fn append_audio<W: Write>(&self, writer: &mut ArchiveWriter<W>) -> Res<()> {
+        if let Some(path) = &self.args.audio {
+            // Clap only lets valid numbers through.
+            let bitrate = self.args.audio_bitrate.parse().unwrap_or(192);
+            writer.append_audio(&audio::load(path, bitrate)?)?;
+        }
+        Ok(())
+    }
+
+    /// Embeds every subtitle track, checking they can be read first.
+    fn append_subtitles<W: Write>(
+        &self,
+        writer: &mut ArchiveWriter<W>,
+    ) -> Res<()> {
+        for (language, path) in &self.args.subs {
+            Subtitles::load(path)?;
+            writer.append_subtitles(language, &fs::read(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Resizes, converts and compresses a single frame.
+    fn compress_frame(&self, image: &DynamicImage) -> io::Result<Vec<u8>> {
+        let (width, height) = if self.args.stretch {
+            (self.width, self.height)
+        } else {
+            fit(
+                image.dimensions(),
+                (self.width, self.height),
+                self.args.cell_aspect,
+            )
+        };
+
+        let image = image.resize_exact(width, height, FilterType::Triangle);
+        let cells = self.converter.cells(&image.to_luma8());
+        let frame = match &self.colorizer {
+            Some(colorizer) => colorizer.paint(&cells, &image.to_rgb8()),
+            None => render(&cells, width as usize),
+        };
+
+        encode_all(&*frame, self.args.compression_level)
+    }
+}
+
+/// Decides how many times each source frame shows up at a constant rate.
+struct Resampler {
+    fps: f64,
+    /// Source time covered so far, in microseconds.
+    elapsed: u64,
+    emitted: u64,
+}
+
+impl Resampler {
+    fn new(fps: f32) -> Self {
+        Self { fps: f64::from(fps), elapsed: 0, emitted: 0 }
+    }
+
+    /// How many output frames land within a source frame lasting
+    /// `duration` microseconds. Zero means it gets dropped.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn repeats(&mut self, duration: u64) -> u64 {
+        self.elapsed += duration;
+        // Frametimes are rounded to whole microseconds, so give the
+        // boundaries a little slack.
+        let due = (self.elapsed as f64 * self.fps / 1_000_000.0 - 0.001).ceil()
+            as u64;
+        let repeats = due.saturating_sub(self.emitted);
+        self.emitted += repeats;
+        repeats
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn frametime(&self) -> u64 {
+        (1_000_000.0 / self.fps).round() as u64
+    }
+}
+
+pub fn run(args: &EncodeArgs) -> Res<()> {
+    for (language, _) in &args.sub_name {
+        if !args.subs.iter().any(|(subs, _)| subs == language) {
+            return Err(
+                format!("--sub-name {language}: no --subs for it").into()
+            );
+        }
+    }
+
+    let Some(path) = args.password_file.as_ref().filter(|_| args.encrypt)
+    else {
+        return write(args, &args.output);
+    };
+    let password = read_password(path)?;
+    // Never unencrypted where it's going, not even for a moment, so the
+    // unencrypted one goes somewhere only this user can read it, and
+    // it's gone once it's encrypted, or if anything goes wrong.
+    let (plain, _) = Plain::create()?;
+    write(args, &plain.path)?;
+    // Last, since nothing else can be done to it afterwards.
+    println!("Encrypting...");
+    encrypt(&plain.path, &args.output, &password)
+}
+
+/// Encodes the file to `output`, unencrypted.
+fn write(args: &EncodeArgs, output: &Path) -> Res<()> {
+    let encoder = Encoder::new(args, output);
+
+    if let Some(path) = &args.animation {
+        animation::encode(&encoder, path)?;
+    } else if let Some(pattern) = &args.images {
+        images::encode(&encoder, pattern)?;
+    } else {
+        unreachable!("clap requires one of the inputs")
+    }
+
+    if args.dictionary {
+        println!("Training a dictionary...");
+        let options =
+            Options { dedup: false, dictionary: Some(args.compression_level) };
+        optimize(output, output, &options)?;
+    }
+    Ok(())
+}
+
+fn validate_positive(s: &str) -> std::result::Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(value.is_finite() && value > 0.0) {
+        return Err("Value must be greater than zero.".to_string());
+    }
+    Ok(value)
+}
+
+fn validate_unit(s: &str) -> std::result::Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err("Value must be between 0 and 1.".to_string());
+    }
+    Ok(value)
+}
+
+fn validate_subs(s: &str) -> std::result::Result<(String, PathBuf), String> {
+    let (language, path) =
+        split_language(s, "Expected a language and a file, like en=movie.srt")?;
+    Ok((language, PathBuf::from(path)))
+}
+
+fn validate_sub_name(s: &str) -> std::result::Result<(String, String), String> {
+    let (language, name) =
+        split_language(s, "Expected a language and a name, like en=English")?;
+    Ok((language, name.to_string()))
+}
+
+/// `LANG=...`, where the language has to work in an entry's name.
+fn split_language<'a>(
+    s: &'a str,
+    error: &str,
+) -> std::result::Result<(String, &'a str), String> {
+    let (language, rest) = s
+        .split_once('=')
+        .filter(|(language, _)| {
+            !language.is_empty() && !language.contains(['/', '\\'])
+        })
+        .ok_or(error)?;
+    Ok((language.to_string(), rest))
+}
+
+fn validate_ramp(s: &str) -> std::result::Result<String, String> {
+    if s.chars().count() < 2 {
+        return Err("The ramp needs at least two characters.".to_string());
+    }
+    Ok(s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read, path::Path};
+
+    use clap::Parser;
+    use image::{
+        Delay, Frame, GrayImage, Luma, RgbaImage, codecs::gif::GifEncoder,
+    };
+    use tar::Archive;
+
+    use super::*;
+    use crate::temp::TempPath;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: EncodeArgs,
+    }
+
+    /// Encodes to 8x4 cells with `options`, giving back the metadata and
+    /// every frame, decompressed.
+    fn encode(options: &[&str]) -> (Metadata, Vec<String>) {
+        let output = TempPath::new();
+        let output = output.to_str().unwrap();
+        let fixed = ["encode", "--width", "8", "--height", "4", "-o", output];
+        let cli = Cli::parse_from(fixed.iter().chain(options));
+        run(&cli.args).unwrap();
+
+        let mut metadata = None;
+        let mut frames = Vec::new();
+        let mut archive = Archive::new(fs::File::open(output).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().into_owned();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            if name == Path::new("metadata.ron") {
+                let content = String::from_utf8(content).unwrap();
+                metadata = Some(ron::from_str(&content).unwrap());
+            } else if name
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .parse::<u64>()
+                .is_ok()
+            {
+                let frame = zstd::decode_all(&content[..]).unwrap();
+                frames.push(String::from_utf8(frame).unwrap());
+            }
+        }
+        (metadata.unwrap(), frames)
+    }
+
+    #[test]
+    fn encodes_images_in_numbered_order() {
+        let dir = TempPath::new();
+        fs::create_dir(&dir).unwrap();
+        for (name, luma) in [("1.png", 0), ("2.png", 255), ("10.png", 0)] {
+            let image = GrayImage::from_pixel(16, 16, Luma([luma]));
+            image.save(dir.join(name)).unwrap();
+        }
+        let pattern = dir.join("*.png");
+        let pattern = pattern.to_str().unwrap();
+        let (metadata, frames) =
+            encode(&["--images", pattern, "--frametime", "40000"]);
+
+        assert_eq!(metadata.frametime, 40_000);
+        assert_eq!(frames.len(), 3);
+        // 10.png comes last, not second.
+        assert_eq!(frames[0], frames[2]);
+        assert_ne!(frames[0], frames[1]);
+    }
+
+    #[test]
+    fn keeps_animation_delays() {
+        let dir = TempPath::new();
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("animation.gif");
+        let mut encoder = GifEncoder::new(fs::File::create(&path).unwrap());
+        for ms in [20, 50] {
+            let image = RgbaImage::new(16, 16);
+            let delay = Delay::from_numer_denom_ms(ms, 1);
+            encoder
+                .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                .unwrap();
+        }
+        drop(encoder);
+        let (metadata, frames) =
+            encode(&["--animation", path.to_str().unwrap()]);
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(metadata.frametimes, [20_000, 50_000]);
+        assert_eq!(metadata.frametime, 35_000);
+    }
+
+    #[test]
+    fn resamples_to_the_target_rate() {
+        // Every other frame of 60 FPS makes 30.
+        let mut halved = Resampler::new(30.0);
+        let kept: u64 = (0..60).map(|_| halved.repeats(16_667)).sum();
+        assert_eq!(kept, 30);
+        assert_eq!(halved.frametime(), 33_333);
+
+        // And 10 FPS to 30 shows each three times.
+        let mut tripled = Resampler::new(30.0);
+        assert!((0..10).all(|_| tripled.repeats(100_000) == 3));
+    }
+}
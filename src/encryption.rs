@@ -0,0 +1,375 @@
+// Encrypted .bapple files, still tar archives, but with everything that
+// makes up the file itself in one `encryption.bin` entry, encrypted with
+// ChaCha20-Poly1305 in chunks. The key comes from a password, through
+// PBKDF2-HMAC-SHA256 with the salt and rounds in `encryption.ron`. Each
+// chunk's nonce is its number and whether it's the last one, so chunks
+// can't be dropped, swapped or cut off at the end without it showing.
+// The only other entry is metadata saying it's encrypted, so players
+// from before refuse it instead of taking those entries for frames.
+// Playing one means decrypting it to a temporary file first.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit,
+    aead::{Aead, generic_array::GenericArray},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{ArchiveWriter, entry_name, is_encryption, temp_path},
+    manifest::{hex, unhex},
+    metadata::Metadata,
+};
+
+/// How many rounds of PBKDF2 new files get, what OWASP suggests for
+/// SHA-256.
+const ROUNDS: u32 = 600_000;
+/// The most rounds files get to ask for, more than enough for anyone, but
+/// not so many that deriving the key takes forever.
+const MAX_ROUNDS: u32 = 10_000_000;
+/// How much longer sealing makes each chunk.
+const TAG_SIZE: usize = 16;
+/// How much gets encrypted in one go.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The most files get to say they did at once.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// What's in `encryption.ron`.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    cipher: String,
+    kdf: String,
+    rounds: u32,
+    /// In hex.
+    salt: String,
+    chunk_size: usize,
+}
+
+/// An unencrypted copy of a file, in the temporary directory where only
+/// this user can read it, removed once it's dropped.
+pub struct Plain {
+    pub path: PathBuf,
+}
+
+impl Plain {
+    /// A new empty one, and the file to write it with.
+    pub fn create() -> io::Result<(Self, File)> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("bplay-{}-{count}.bapple", process::id());
+        let path = env::temp_dir().join(name);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        // Whatever's there already isn't this one's to remove.
+        let file = options.open(&path)?;
+        Ok((Self { path }, file))
+    }
+}
+
+impl Drop for Plain {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// What's in a password file: all of it, but the line break at the end.
+pub fn read_password(path: &Path) -> Res<Vec<u8>> {
+    let mut password =
+        fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    if password.ends_with(b"\n") {
+        password.pop();
+        if password.ends_with(b"\r") {
+            password.pop();
+        }
+    }
+    if password.is_empty() {
+        return Err(format!("{}: the password is empty", path.display()).into());
+    }
+    Ok(password)
+}
+
+/// Whether the file at `path` needs a password to play.
+pub fn is_encrypted(path: &Path) -> Res<bool> {
+    let mut archive = Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        if entry_name(&entry?)?.is_some_and(|name| is_encryption(&name)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Encrypts `path` into `output`, which can be `path` itself.
+pub fn encrypt(path: &Path, output: &Path, password: &[u8]) -> Res<()> {
+    let temp = temp_path(output);
+    let result = write_encrypted(path, &temp, password, ROUNDS);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    fs::rename(&temp, output)?;
+    Ok(())
+}
+
+fn write_encrypted(
+    path: &Path,
+    temp: &Path,
+    password: &[u8],
+    rounds: u32,
+) -> Res<()> {
+    let mut salt = [0; 16];
+    getrandom::fill(&mut salt).map_err(|e| e.to_string())?;
+    let header = Header {
+        cipher: "chacha20-poly1305".to_string(),
+        kdf: "pbkdf2-sha256".to_string(),
+        rounds,
+        salt: hex(&salt),
+        chunk_size: CHUNK_SIZE,
+    };
+    let key = derive_key(password, &salt, rounds);
+
+    let mut writer = ArchiveWriter::create(temp)?;
+    writer.append_metadata(&Metadata {
+        version: Metadata::VERSION,
+        required: vec!["encryption".to_string()],
+        ..Default::default()
+    })?;
+    let serialized = ron::to_string(&header)?;
+    let header_path = Path::new("encryption.ron");
+    writer.append_raw(
+        header_path,
+        serialized.len() as u64,
+        serialized.as_bytes(),
+    )?;
+
+    // Always at least one chunk, so there's a last one.
+    let size = fs::metadata(path)?.len();
+    let chunks = size.div_ceil(CHUNK_SIZE as u64).max(1);
+    let sealed = size + chunks * TAG_SIZE as u64;
+    let sealing = Sealing {
+        file: File::open(path)?,
+        key,
+        chunks,
+        next: 0,
+        buffer: Vec::new(),
+        position: 0,
+    };
+    writer.append_raw(Path::new("encryption.bin"), sealed, sealing)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Decrypts `path` to a temporary file only this user can read.
+pub fn decrypt(path: &Path, password: &[u8]) -> Res<Plain> {
+    let (decrypted, mut output) = Plain::create()?;
+
+    let mut archive = Archive::new(File::open(path)?);
+    let mut header = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        if !is_encryption(&name) {
+            continue;
+        }
+        if name.extension().is_some_and(|extension| extension == "ron") {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            header = Some(ron::from_str::<Header>(&content)?);
+            continue;
+        }
+        let header =
+            header.as_ref().ok_or("the encryption header is missing")?;
+        if header.cipher != "chacha20-poly1305" || header.kdf != "pbkdf2-sha256"
+        {
+            return Err(format!(
+                "encrypted with {} and {}, which this version can't decrypt",
+                header.cipher, header.kdf
+            )
+            .into());
+        }
+        let salt: Option<[u8; 16]> = unhex(&header.salt);
+        let chunk_size = header.chunk_size;
+        // Anything bigger would be a waste of memory, on purpose or not.
+        // Or time, with too many rounds.
+        let (Some(salt), 1..=MAX_CHUNK_SIZE, 1..=MAX_ROUNDS) =
+            (salt, chunk_size, header.rounds)
+        else {
+            return Err("the encryption header is damaged".into());
+        };
+        let key = derive_key(password, &salt, header.rounds);
+        let size = entry.size();
+        open_chunks(&mut entry, size, &key, chunk_size, &mut output)?;
+        return Ok(decrypted);
+    }
+    Err("there's nothing encrypted in it".into())
+}
+
+/// Decrypts chunk after chunk of `size` bytes in all, writing them out.
+fn open_chunks(
+    sealed: &mut impl Read,
+    size: u64,
+    key: &[u8; 32],
+    chunk_size: usize,
+    output: &mut impl Write,
+) -> Res<()> {
+    let mut left = size;
+    let mut buffer = vec![0; chunk_size + TAG_SIZE];
+    for number in 0.. {
+        let length = left.min((chunk_size + TAG_SIZE) as u64);
+        #[allow(clippy::cast_possible_truncation)]
+        let chunk = &mut buffer[..length as usize];
+        sealed.read_exact(chunk)?;
+        left -= length;
+        let nonce = nonce(number, left == 0);
+        let plain = cipher(key)
+            .decrypt(GenericArray::from_slice(&nonce), &*chunk)
+            .map_err(|_| "wrong password, or the file is damaged")?;
+        output.write_all(&plain)?;
+        if left == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a file, encrypting it chunk by chunk.
+struct Sealing {
+    file: File,
+    key: [u8; 32],
+    chunks: u64,
+    /// The number of the chunk to encrypt next.
+    next: u64,
+    /// The one encrypted last, and how much of it has been read.
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Read for Sealing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.buffer.len() {
+            if self.next == self.chunks {
+                return Ok(0);
+            }
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            (&mut self.file).take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+            let last = self.next + 1 == self.chunks;
+            let nonce = nonce(self.next, last);
+            self.buffer = cipher(&self.key)
+                .encrypt(GenericArray::from_slice(&nonce), &*chunk)
+                .expect("chunks are small enough to encrypt");
+            self.position = 0;
+            self.next += 1;
+        }
+        let read = buf.len().min(self.buffer.len() - self.position);
+        buf[..read].copy_from_slice(&self.buffer[self.position..][..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+fn nonce(number: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(&number.to_le_bytes());
+    nonce[8] = u8::from(last);
+    nonce
+}
+
+fn cipher(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(GenericArray::from_slice(key))
+}
+
+fn derive_key(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, rounds, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempPath;
+
+    /// Something to encrypt, a few chunks long.
+    fn plain() -> (TempPath, Vec<u8>) {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 100)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        let plain = TempPath::new();
+        fs::write(&plain, &content).unwrap();
+        (plain, content)
+    }
+
+    #[test]
+    fn derives_keys_like_pbkdf2() {
+        // RFC 7914, section 11, cut down to one block.
+        let key = derive_key(b"passwd", b"salt", 1);
+        assert_eq!(
+            hex(&key),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let (plain, content) = plain();
+        let encrypted = TempPath::new();
+        // Fewer rounds than it'd take for real, to not take all day.
+        write_encrypted(&plain, &encrypted, b"password", 1000).unwrap();
+
+        assert!(is_encrypted(&encrypted).unwrap());
+        let decrypted = decrypt(&encrypted, b"password").unwrap();
+        assert_eq!(fs::read(&decrypted.path).unwrap(), content);
+        assert!(decrypt(&encrypted, b"Password").is_err());
+    }
+
+    #[test]
+    fn refuses_too_many_rounds() {
+        let header = Header {
+            cipher: "chacha20-poly1305".to_string(),
+            kdf: "pbkdf2-sha256".to_string(),
+            rounds: u32::MAX,
+            salt: hex(&[0; 16]),
+            chunk_size: CHUNK_SIZE,
+        };
+        let serialized = ron::to_string(&header).unwrap();
+        let encrypted = TempPath::new();
+        let mut writer = ArchiveWriter::create(&encrypted).unwrap();
+        let header_path = Path::new("encryption.ron");
+        writer
+            .append_raw(
+                header_path,
+                serialized.len() as u64,
+                serialized.as_bytes(),
+            )
+            .unwrap();
+        let sealed = [0; TAG_SIZE];
+        writer
+            .append_raw(
+                Path::new("encryption.bin"),
+                TAG_SIZE as u64,
+                &sealed[..],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        // Rather than spending the rest of the day deriving the key.
+        let error = decrypt(&encrypted, b"password").err().unwrap();
+        assert_eq!(error.to_string(), "the encryption header is damaged");
+    }
+}
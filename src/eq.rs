@@ -0,0 +1,245 @@
+// A few fixed EQ curves applied between the decoder and the sink,
+// mostly because laptop speakers murder the Bad Apple bassline.
+// Filters are the usual biquads from the Audio EQ Cookbook.
+
+use std::{f32::consts::PI, time::Duration};
+
+use clap::ValueEnum;
+use rodio::{Source, source::SeekError};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preset {
+    /// Leave the audio alone
+    #[default]
+    Flat,
+    /// Boost the lows
+    Bass,
+    /// Boost the highs
+    Treble,
+    /// Boost both ends, for listening quietly
+    Loudness,
+    /// Cut what tiny speakers can't play and push the upper bass instead
+    Laptop,
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    LowShelf,
+    HighShelf,
+    Peak,
+    HighPass,
+}
+
+#[derive(Clone, Copy)]
+struct Band {
+    shape: Shape,
+    frequency: f32,
+    /// In dB, ignored by the high-pass.
+    gain: f32,
+    q: f32,
+}
+
+const fn band(shape: Shape, frequency: f32, gain: f32, q: f32) -> Band {
+    Band { shape, frequency, gain, q }
+}
+
+const BASS: &[Band] = &[band(Shape::LowShelf, 120.0, 6.0, 0.707)];
+const TREBLE: &[Band] = &[band(Shape::HighShelf, 6000.0, 6.0, 0.707)];
+const LOUDNESS: &[Band] = &[
+    band(Shape::LowShelf, 100.0, 6.0, 0.707),
+    band(Shape::HighShelf, 8000.0, 4.0, 0.707),
+];
+const LAPTOP: &[Band] = &[
+    band(Shape::HighPass, 80.0, 0.0, 0.707),
+    band(Shape::Peak, 180.0, 6.0, 1.0),
+];
+
+impl Preset {
+    fn bands(self) -> &'static [Band] {
+        match self {
+            Self::Flat => &[],
+            Self::Bass => BASS,
+            Self::Treble => TREBLE,
+            Self::Loudness => LOUDNESS,
+            Self::Laptop => LAPTOP,
+        }
+    }
+
+    /// Turns everything down by the biggest boost, so it can't clip.
+    fn preamp(self) -> f32 {
+        let boost = self.bands().iter().map(|b| b.gain).fold(0.0, f32::max);
+        10.0_f32.powf(-boost / 20.0)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn new(band: Band, sample_rate: f32) -> Self {
+        // Anything past Nyquist makes the filter blow up.
+        let frequency = band.frequency.min(sample_rate * 0.45);
+        let a = 10.0_f32.powf(band.gain / 40.0);
+        let (sin, cos) = (2.0 * PI * frequency / sample_rate).sin_cos();
+        let alpha = sin / (2.0 * band.q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.shape {
+            Shape::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            Shape::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            Shape::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            Shape::HighPass => (
+                f32::midpoint(1.0, cos),
+                -(1.0 + cos),
+                f32::midpoint(1.0, cos),
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        (self.x2, self.x1) = (self.x1, x);
+        (self.y2, self.y1) = (self.y1, y);
+        y
+    }
+}
+
+/// Runs every sample through the preset's filters, each channel
+/// keeping its own filter state.
+pub struct Equalizer<S> {
+    inner: S,
+    preset: Preset,
+    preamp: f32,
+    /// One chain of filters per channel.
+    filters: Vec<Vec<Biquad>>,
+    channel: usize,
+    /// What the filters were built for, they're rebuilt when it changes.
+    format: (u16, u32),
+}
+
+impl<S: Source> Equalizer<S> {
+    pub fn new(inner: S, preset: Preset) -> Self {
+        Self {
+            inner,
+            preset,
+            preamp: preset.preamp(),
+            filters: Vec::new(),
+            channel: 0,
+            format: (0, 0),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn rebuild(&mut self) {
+        let (channels, sample_rate) = self.format;
+        let chain = self
+            .preset
+            .bands()
+            .iter()
+            .map(|band| Biquad::new(*band, sample_rate as f32))
+            .collect::<Vec<_>>();
+        self.filters = vec![chain; usize::from(channels.max(1))];
+    }
+}
+
+impl<S: Source> Iterator for Equalizer<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        if self.preset == Preset::Flat {
+            return Some(sample);
+        }
+
+        if self.channel == 0 {
+            let format = (self.inner.channels(), self.inner.sample_rate());
+            if format != self.format {
+                self.format = format;
+                self.rebuild();
+            }
+        }
+
+        let filtered = self.filters[self.channel]
+            .iter_mut()
+            .fold(sample * self.preamp, |sample, filter| {
+                filter.process(sample)
+            });
+        self.channel = (self.channel + 1) % self.filters.len();
+
+        Some(filtered)
+    }
+}
+
+impl<S: Source> Source for Equalizer<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        // Old filter state belongs to audio that's no longer playing.
+        self.channel = 0;
+        self.rebuild();
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+// Everything done to a frame between decompressing and drawing it, as a
+// list of filters run one after the other. What goes in the list comes
+// from the options and what the terminal can do, see `Settings`.
+
+use crate::{
+    clock::time_of_day,
+    render::{
+        Crop, Levels, Pad, Position, Tint, crop, overlay, pad, remap,
+        strip_escapes, tint, to_ascii,
+    },
+    terminal::{Capabilities, ColorDepth},
+};
+
+/// Takes a frame, gives back a changed one.
+pub trait FrameFilter {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8>;
+}
+
+/// Filters run in the order they were pushed.
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn FrameFilter + Send>>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, filter: impl FrameFilter + Send + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        self.filters.iter().fold(frame, |frame, filter| filter.apply(frame))
+    }
+}
+
+/// Drops colors, for terminals without any.
+pub struct StripColor;
+
+impl FrameFilter for StripColor {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        strip_escapes(&frame)
+    }
+}
+
+/// Swaps block and braille characters for ASCII ones.
+pub struct Ascii;
+
+impl FrameFilter for Ascii {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        to_ascii(&frame)
+    }
+}
+
+/// Moves characters along `from`, and maybe over to `to`, see [`remap`].
+pub struct RemapCharset {
+    pub from: Vec<char>,
+    pub levels: Levels,
+    pub to: Option<Vec<char>>,
+}
+
+impl FrameFilter for RemapCharset {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        remap(frame, &self.from, self.levels, self.to.as_deref())
+    }
+}
+
+/// Colors frames that don't have colors of their own.
+pub struct Colorize {
+    pub tint: Tint,
+    pub depth: ColorDepth,
+}
+
+impl FrameFilter for Colorize {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        tint(frame, self.tint, self.depth)
+    }
+}
+
+/// Writes the same text over every frame.
+pub struct TextOverlay {
+    pub text: String,
+    pub position: Position,
+}
+
+impl FrameFilter for TextOverlay {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        overlay(&frame, &self.text, self.position)
+    }
+}
+
+/// Writes the time over every frame, as of when it's drawn.
+pub struct ClockOverlay {
+    pub position: Position,
+}
+
+impl FrameFilter for ClockOverlay {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        let (hours, minutes, seconds) = time_of_day();
+        let time = format!("{hours:02}:{minutes:02}:{seconds:02}");
+        overlay(&frame, &time, self.position)
+    }
+}
+
+impl FrameFilter for Crop {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        crop(&frame, *self)
+    }
+}
+
+impl FrameFilter for Pad {
+    fn apply(&self, frame: Vec<u8>) -> Vec<u8> {
+        pad(&frame, *self)
+    }
+}
+
+/// What the filters get made from, besides the terminal and the file.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub levels: Levels,
+    /// Drawn instead of the file's charset, darkest to brightest.
+    pub charset: Option<Vec<char>>,
+    pub crop: Option<Crop>,
+    pub overlay_text: Option<String>,
+    pub overlay_position: Position,
+    /// Where the time goes, if anywhere.
+    pub clock: Option<Position>,
+    pub tint: Option<Tint>,
+    pub pad: Option<Pad>,
+}
+
+impl Settings {
+    /// Whether any of it needs the file's charset.
+    pub fn remaps(&self) -> bool {
+        !self.levels.is_identity() || self.charset.is_some()
+    }
+
+    /// `charset` is the file's own, darkest to brightest. Remapping comes
+    /// first since it goes by the file's characters, padding last so it
+    /// stays blank.
+    pub fn pipeline(
+        &self,
+        capabilities: Capabilities,
+        charset: Option<&[char]>,
+    ) -> Pipeline {
+        let mut pipeline = Pipeline::default();
+        if let (true, Some(from)) = (self.remaps(), charset) {
+            pipeline.push(RemapCharset {
+                from: from.to_vec(),
+                levels: self.levels,
+                to: self.charset.clone(),
+            });
+        }
+        if let Some(crop) = self.crop {
+            pipeline.push(crop);
+        }
+        if let Some(text) = &self.overlay_text {
+            pipeline.push(TextOverlay {
+                text: text.clone(),
+                position: self.overlay_position,
+            });
+        }
+        if let Some(position) = self.clock {
+            pipeline.push(ClockOverlay { position });
+        }
+        if capabilities.color == ColorDepth::None {
+            pipeline.push(StripColor);
+        }
+        if !capabilities.unicode {
+            pipeline.push(Ascii);
+        }
+        if let Some(tint) = self.tint {
+            pipeline.push(Colorize { tint, depth: capabilities.color });
+        }
+        if let Some(pad) = self.pad {
+            pipeline.push(pad);
+        }
+        pipeline
+    }
+}
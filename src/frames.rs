@@ -0,0 +1,408 @@
+// Every compressed frame of a file in one buffer, instead of a separate
+// allocation each. Files with tens of thousands of frames load with a
+// handful of allocations, and neighbouring frames sit next to each other
+// in memory while they're played. With --max-mem whatever doesn't fit
+// goes to a temporary file, and with --low-mem they're streamed from the
+// archive instead, a few at a time. Files on a web server get theirs
+// fetched as they're needed, see `remote`. Decompressed frames are capped,
+// so a file made to blow up into gigabytes can't take all the memory.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, SyncSender, sync_channel},
+    },
+    thread,
+};
+
+use crossterm::terminal;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator};
+use tar::Archive;
+use zstd::{
+    Decoder, dict::DecoderDictionary, zstd_safe::find_frame_compressed_size,
+};
+
+use crate::{
+    Res,
+    archive::{entry_name, is_frame},
+    remote::Remote,
+};
+
+/// How many frames the streaming thread reads ahead.
+const READ_AHEAD: usize = 32;
+
+/// How big a frame can decompress to by default, for each character cell
+/// of the terminal. A cell with both colors in true color takes about 40
+/// bytes, and frames can be bigger than the terminal showing them.
+const BYTES_PER_CELL: usize = 256;
+/// The smallest that default gets, however small the terminal is.
+const MIN_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Where playback gets its compressed frames from.
+pub enum FrameSource {
+    /// All of them, read up front.
+    Memory(Frames),
+    /// Read as they're needed, see `Stream`.
+    Stream(Stream),
+    /// Fetched from a server as they're needed.
+    Remote(Remote),
+}
+
+impl FrameSource {
+    pub fn get(&mut self, index: usize) -> io::Result<&[u8]> {
+        match self {
+            Self::Memory(frames) => frames.get(index),
+            Self::Stream(stream) => stream.get(index),
+            Self::Remote(remote) => remote.get(index),
+        }
+    }
+
+    /// Plays stored frames as often as `map` says, see `Metadata::frame_map`.
+    pub fn repeat(&mut self, map: Vec<usize>) {
+        match self {
+            Self::Memory(frames) => frames.repeat(&map),
+            Self::Stream(stream) => stream.map = Some(map),
+            Self::Remote(remote) => remote.map = Some(map),
+        }
+    }
+
+    /// Whether frames before the last one read can be gone back to.
+    pub fn seeks_back(&self) -> bool {
+        matches!(self, Self::Memory(_) | Self::Remote(_))
+    }
+}
+
+/// How a file's frames are kept around while it plays.
+#[derive(Clone, Copy, Default)]
+pub enum Loading {
+    /// All of them in memory.
+    #[default]
+    All,
+    /// In memory up to this many bytes, the rest in a temporary file.
+    Budget(usize),
+    /// Only the ones being played, see `Stream`.
+    Stream,
+}
+
+impl Loading {
+    pub fn budget(self) -> Option<usize> {
+        match self {
+            Self::Budget(bytes) => Some(bytes),
+            Self::All | Self::Stream => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Frames {
+    data: Vec<u8>,
+    /// Where each frame is in `data`, or in the spill file once it's
+    /// started, in order.
+    spans: Vec<Range<usize>>,
+    /// The most `data` gets to hold.
+    budget: Option<usize>,
+    spill: Option<Spill>,
+}
+
+/// Where frames go once they don't fit the budget anymore. Every frame
+/// after the first one in here is in here too.
+struct Spill {
+    path: PathBuf,
+    file: File,
+    /// The index of the first frame in the file.
+    first: usize,
+    /// The frame read back last, and its index.
+    buffer: Vec<u8>,
+    buffered: Option<usize>,
+    /// Checked on the way in, since they won't all be read back at once.
+    corrupt: Option<usize>,
+}
+
+impl Spill {
+    fn create(first: usize) -> io::Result<Self> {
+        let path = spill_path();
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            first,
+            buffer: Vec::new(),
+            buffered: None,
+            corrupt: None,
+        })
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Frames {
+    /// Keeps at most `bytes` of frames in memory if there's a budget,
+    /// spilling the rest to a temporary file.
+    pub fn with_budget(bytes: Option<usize>) -> Self {
+        Self { budget: bytes, ..Self::default() }
+    }
+
+    /// Reads a whole frame onto the end. A frame that fails to read is
+    /// left out entirely.
+    pub fn read_from(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let Some(budget) = self.budget else {
+            let start = self.data.len();
+            if let Err(e) = reader.read_to_end(&mut self.data) {
+                self.data.truncate(start);
+                return Err(e);
+            }
+            self.spans.push(start..self.data.len());
+            return Ok(());
+        };
+
+        let mut frame = Vec::new();
+        reader.read_to_end(&mut frame)?;
+        let start = self.data.len();
+        let end = start + frame.len();
+        if self.spill.is_none() && end <= budget {
+            if end > self.data.capacity() {
+                // Growing the usual way could overshoot it by up to twice.
+                let wanted = (self.data.capacity() * 2).clamp(end, budget);
+                self.data.reserve_exact(wanted - start);
+            }
+            self.data.extend_from_slice(&frame);
+            self.spans.push(start..end);
+        } else {
+            self.spill(&frame)?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self, frame: &[u8]) -> io::Result<()> {
+        let index = self.spans.len();
+        let spill = match self.spill.take() {
+            Some(spill) => spill,
+            None => Spill::create(index)?,
+        };
+        let spill = self.spill.insert(spill);
+        let start = self
+            .spans
+            .get(spill.first..)
+            .map_or(0, |spilled| spilled.last().map_or(0, |span| span.end));
+        spill.file.write_all(frame)?;
+        if spill.corrupt.is_none() && find_frame_compressed_size(frame).is_err()
+        {
+            spill.corrupt = Some(index);
+        }
+        self.spans.push(start..start + frame.len());
+        Ok(())
+    }
+
+    /// Points every frame played at the one stored for it, so repeats
+    /// don't take any more room.
+    fn repeat(&mut self, map: &[usize]) {
+        self.spans =
+            map.iter().map(|&frame| self.spans[frame].clone()).collect();
+        if let Some(spill) = &mut self.spill {
+            spill.first = map.partition_point(|&frame| frame < spill.first);
+            spill.buffered = None;
+        }
+    }
+
+    /// Gives back whatever reading left unused, once all frames are in.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.spans.shrink_to_fit();
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn get(&mut self, index: usize) -> io::Result<&[u8]> {
+        let span = self.spans[index].clone();
+        let Some(spill) = self.spill.as_mut().filter(|s| index >= s.first)
+        else {
+            return Ok(&self.data[span]);
+        };
+        if spill.buffered != Some(index) {
+            spill.buffered = None;
+            spill.buffer.resize(span.len(), 0);
+            spill.file.seek(SeekFrom::Start(span.start as u64))?;
+            spill.file.read_exact(&mut spill.buffer)?;
+            spill.buffered = Some(index);
+        }
+        Ok(&spill.buffer)
+    }
+
+    /// The first frame that isn't valid zstd, the ones in memory checked
+    /// in parallel.
+    pub fn find_corrupt(&self) -> Option<usize> {
+        let in_memory = self.spill.as_ref().map_or(self.len(), |s| s.first);
+        self.spans[..in_memory]
+            .par_iter()
+            .position_first(|span| {
+                find_frame_compressed_size(&self.data[span.clone()]).is_err()
+            })
+            .or_else(|| self.spill.as_ref()?.corrupt)
+    }
+}
+
+/// A new file in the temporary directory for each spill, since files
+/// can load while another one plays.
+fn spill_path() -> PathBuf {
+    static SPILLS: AtomicUsize = AtomicUsize::new(0);
+    let count = SPILLS.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("bplay-{}-{count}.spill", process::id()))
+}
+
+/// Frames read from the archive in order, only ever holding the one being
+/// played and the few read ahead. Going back means starting over from the
+/// beginning of the file.
+pub struct Stream {
+    path: PathBuf,
+    reader: Option<Receiver<io::Result<Vec<u8>>>>,
+    /// The index of the frame `reader` gives next.
+    next: usize,
+    /// The frame given out last, and its index.
+    current: Option<(usize, Vec<u8>)>,
+    /// Which stored frame each played one is, if any are repeated.
+    map: Option<Vec<usize>>,
+}
+
+impl Stream {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            reader: None,
+            next: 0,
+            current: None,
+            map: None,
+        }
+    }
+
+    pub fn get(&mut self, index: usize) -> io::Result<&[u8]> {
+        // A repeat is the frame given out last, so it isn't read again.
+        let index = self
+            .map
+            .as_ref()
+            .and_then(|map| map.get(index).copied())
+            .unwrap_or(index);
+        if self.current.as_ref().is_none_or(|(at, _)| *at != index) {
+            if index < self.next {
+                self.reader = None;
+                self.next = 0;
+            }
+            self.current = None;
+            let reader = self.reader.get_or_insert_with(|| {
+                let (sender, receiver) = sync_channel(READ_AHEAD);
+                let path = self.path.clone();
+                thread::spawn(move || read_frames(&path, &sender));
+                receiver
+            });
+            // Frames skipped over get dropped unread.
+            while self.next <= index {
+                let frame = reader.recv().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("couldn't read frame {}", self.next),
+                    )
+                })??;
+                self.next += 1;
+                if self.next > index {
+                    self.current = Some((index, frame));
+                }
+            }
+        }
+        // Just set if it wasn't already.
+        Ok(&self.current.as_ref().unwrap().1)
+    }
+}
+
+/// Sends every frame of the archive in order, until they run out or
+/// nobody's listening anymore. Reading stops at the first thing that goes
+/// wrong, which gets sent instead, for playback to stop on.
+fn read_frames(path: &Path, sender: &SyncSender<io::Result<Vec<u8>>>) {
+    if let Err(e) = send_frames(path, sender) {
+        let _ = sender.send(Err(io::Error::other(e.to_string())));
+    }
+}
+
+fn send_frames(
+    path: &Path,
+    sender: &SyncSender<io::Result<Vec<u8>>>,
+) -> Res<()> {
+    let mut archive = Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry_name(&entry)?.is_some_and(|name| is_frame(&name)) {
+            continue;
+        }
+        let mut frame = Vec::new();
+        entry.read_to_end(&mut frame)?;
+        if sender.send(Ok(frame)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The default cap on a decompressed frame, going by the terminal's size.
+pub fn max_frame_size() -> usize {
+    let cells = terminal::size()
+        .map_or(0, |(columns, rows)| usize::from(columns) * usize::from(rows));
+    (cells * BYTES_PER_CELL).max(MIN_FRAME_SIZE)
+}
+
+/// What frames compressed against a file's shared dictionary need to be
+/// decompressed. Empty for files without one.
+#[derive(Clone, Default)]
+pub struct Dictionary(Option<Arc<DecoderDictionary<'static>>>);
+
+impl Dictionary {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self(Some(Arc::new(DecoderDictionary::copy(bytes))))
+    }
+}
+
+/// Decompresses a frame, giving up as soon as it goes past `limit` bytes.
+pub fn decompress<R: Read>(
+    compressed: R,
+    dictionary: &Dictionary,
+    limit: usize,
+) -> io::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    let limit_to = limit as u64 + 1;
+    match &dictionary.0 {
+        Some(dictionary) => {
+            let reader = BufReader::new(compressed);
+            Decoder::with_prepared_dictionary(reader, dictionary)?
+                .take(limit_to)
+                .read_to_end(&mut frame)?
+        }
+        None => {
+            Decoder::new(compressed)?.take(limit_to).read_to_end(&mut frame)?
+        }
+    };
+    if frame.len() > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "A frame decompresses to more than {} MB, which is more than \
+                 any terminal needs (--max-frame-size raises the limit)",
+                limit.div_ceil(1024 * 1024)
+            ),
+        ));
+    }
+    Ok(frame)
+}
@@ -0,0 +1,121 @@
+// Everything playback changes about the terminal, kept in one place so it
+// all gets put back however playback ends: finishing, an error coming up
+// through `?`, or a panic unwinding past it.
+
+use std::io::{self, Write, stdout};
+
+use crate::controls::Keyboard;
+
+pub struct TerminalGuard {
+    /// Reads keys for as long as the guard is around.
+    pub keyboard: Option<Keyboard>,
+    alt_screen: bool,
+    /// Already put back through the writer playback went to.
+    left: bool,
+}
+
+impl TerminalGuard {
+    /// Switches to the alternate screen (when the terminal has one),
+    /// hides the cursor, saves the title and takes over `keyboard`.
+    pub fn enter<W: Write>(
+        w: &mut W,
+        alt_screen: bool,
+        keyboard: Option<Keyboard>,
+    ) -> io::Result<Self> {
+        if alt_screen {
+            enter_alt_screen(w)?;
+        }
+        hide_cursor(w)?;
+        push_title(w)?;
+        Ok(Self { keyboard, alt_screen, left: false })
+    }
+
+    /// Puts everything back through `w`. Dropping the guard without
+    /// this does the same on stdout.
+    pub fn leave<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        self.left = true;
+        self.restore(w)
+    }
+
+    fn restore<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.keyboard = None;
+        show_cursor(w)?;
+        pop_title(w)?;
+        if self.alt_screen {
+            leave_alt_screen(w)?;
+        }
+        #[cfg(windows)]
+        restore_console_mode();
+        Ok(())
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if !self.left {
+            let mut w = stdout().lock();
+            let _ = self.restore(&mut w).and_then(|()| w.flush());
+        }
+    }
+}
+
+/// How the console was before escape sequences got turned on.
+#[cfg(windows)]
+static CONSOLE_MODE: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+
+/// False when the console can't do escape sequences at all.
+#[cfg(windows)]
+pub fn enable_virtual_terminal_processing() -> bool {
+    use winapi::um::consoleapi::GetConsoleMode;
+    use winapi::um::consoleapi::SetConsoleMode;
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_OUTPUT_HANDLE;
+    use winapi::um::wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        if handle != INVALID_HANDLE_VALUE {
+            let mut mode = 0;
+            if GetConsoleMode(handle, &raw mut mode) != 0 {
+                CONSOLE_MODE.get_or_init(|| mode);
+                return SetConsoleMode(
+                    handle,
+                    mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+                ) != 0;
+            }
+            eprintln!("Warning: Failed to get console mode");
+        }
+    }
+    true
+}
+
+#[cfg(windows)]
+fn restore_console_mode() {
+    use winapi::um::consoleapi::SetConsoleMode;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_OUTPUT_HANDLE;
+
+    if let Some(&mode) = CONSOLE_MODE.get() {
+        unsafe {
+            SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), mode);
+        }
+    }
+}
+
+macro_rules! write_fn {
+    ($fn_name:ident, $val:expr) => {
+        #[inline]
+        fn $fn_name<W: Write>(w: &mut W) -> io::Result<()> {
+            w.write_all($val)
+        }
+    };
+}
+
+write_fn!(show_cursor, b"\x1b[?25h");
+write_fn!(hide_cursor, b"\x1b[?25l");
+// Saves and restores the current title on xterm-like terminals.
+write_fn!(push_title, b"\x1b[22;0t");
+write_fn!(pop_title, b"\x1b[23;0t");
+write_fn!(enter_alt_screen, b"\x1b[?1049h");
+write_fn!(leave_alt_screen, b"\x1b[?1049l");
@@ -0,0 +1,110 @@
+// Files played lately and where they were left off, in history.ron under
+// the state directory. Listed by `--history`, and at the top of the file
+// browser to pick up where things were left.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ron::ser::{PrettyConfig, to_string_pretty};
+use serde::{Deserialize, Serialize};
+
+use crate::{Res, http::is_url, paths, timeline::format_time};
+
+/// How many files are remembered.
+const LIMIT: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Where it was stopped, zero if it played to the end.
+    pub position: Duration,
+    /// When it was played, in seconds since the Unix epoch.
+    pub played: u64,
+}
+
+/// Most recent first.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+impl History {
+    /// A missing or broken file is just an empty history.
+    pub fn load() -> Self {
+        path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|content| ron::de::from_bytes(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Res<()> {
+        let path = path().ok_or("Couldn't find a state directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, to_string_pretty(self, PrettyConfig::default())?)?;
+        Ok(())
+    }
+
+    /// Puts `path` at the top, remembering where it was left.
+    pub fn record(path: &Path, position: Duration) -> Res<()> {
+        let path = if is_url(path) {
+            path.to_path_buf()
+        } else {
+            fs::canonicalize(path)?
+        };
+        let played = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut history = Self::load();
+        history.entries.retain(|entry| entry.path != path);
+        history.entries.insert(0, Entry { path, position, played });
+        history.entries.truncate(LIMIT);
+        history.save()
+    }
+}
+
+/// `bplay --history`.
+pub fn print() {
+    let history = History::load();
+    if history.entries.is_empty() {
+        println!("Nothing's been played yet");
+        return;
+    }
+
+    for (number, entry) in (1..).zip(&history.entries) {
+        print!("{number:>2}. {}", entry.path.display());
+        if !entry.position.is_zero() {
+            print!(", stopped at {}", format_time(entry.position));
+        }
+        println!(", {}", ago(entry.played));
+    }
+}
+
+/// Roughly how long ago a Unix timestamp was.
+pub fn ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let seconds = now.saturating_sub(timestamp);
+
+    let (amount, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86_400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86_400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{amount} {unit}{plural} ago")
+}
+
+fn path() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join("history.ron"))
+}
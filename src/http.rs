@@ -0,0 +1,354 @@
+// Just enough HTTP/1.1 to fetch a file, or parts of it, from a server, for
+// playing files straight off of one (see `remote`). Only plain `http://`,
+// with a new connection for every request.
+
+use std::{
+    fmt::Display,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    ops::Range,
+    path::Path,
+    time::Duration,
+};
+
+use crate::Res;
+
+/// How long to wait on a server before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How many redirects get followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+/// The most a response's status line and headers get to take up.
+const MAX_HEADERS: u64 = 16 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Url {
+    /// Still in brackets, for IPv6 addresses.
+    host: String,
+    port: u16,
+    /// Everything after the host, starting with `/`.
+    path: String,
+}
+
+/// Which part of a file to ask for.
+pub enum Bytes {
+    All,
+    Range(Range<u64>),
+    /// The last this many.
+    Last(u64),
+}
+
+pub struct Response {
+    /// Whether it's only the part that was asked for. Servers that don't
+    /// do ranges send all of it instead.
+    pub partial: bool,
+    /// How big all of it is, if the server says.
+    pub total: Option<u64>,
+    body: Box<dyn Read + Send>,
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// What a server answered with.
+enum Reply {
+    Done(Response),
+    /// Go ask over there.
+    Redirect(String),
+}
+
+/// Whether `path` is really a URL, for a file on a server.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("http://"))
+}
+
+/// Refuses `https://` URLs, which would otherwise be looked for on disk.
+pub fn refuse_https(path: &Path) -> Res<()> {
+    if path.to_str().is_some_and(|path| path.starts_with("https://")) {
+        let why = "https:// isn't supported, only http:// URLs can be played";
+        return Err(format!("{}: {why}", path.display()).into());
+    }
+    Ok(())
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Res<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err("only http:// URLs can be played".into());
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        // Anything before an @ is a user name and password, which
+        // aren't supported, and the port goes after the last colon,
+        // unless that's inside an IPv6 address.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port =
+                    port.parse().map_err(|_| format!("invalid port {port}"))?;
+                (host, port)
+            }
+            _ => (authority, 80),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err("invalid host".into());
+        }
+        let path = if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.to_string()
+        };
+        Ok(Self { host: host.to_string(), port, path })
+    }
+
+    /// Asks for `bytes` of the file, following redirects.
+    pub fn get(&self, bytes: &Bytes) -> Res<Response> {
+        let mut url = self.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            match url.request(bytes)? {
+                Reply::Done(response) => return Ok(response),
+                Reply::Redirect(location) => url = url.join(&location)?,
+            }
+        }
+        Err("too many redirects".into())
+    }
+
+    fn request(&self, bytes: &Bytes) -> Res<Reply> {
+        let range = match bytes {
+            Bytes::All => String::new(),
+            // There's no asking for nothing, the end is inclusive.
+            Bytes::Range(range) if range.is_empty() => {
+                return Err("asked for an empty range".into());
+            }
+            Bytes::Range(range) => {
+                format!("Range: bytes={}-{}\r\n", range.start, range.end - 1)
+            }
+            Bytes::Last(length) => format!("Range: bytes=-{length}\r\n"),
+        };
+        let mut stream = self.connect()?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bplay/{}\r\n\
+             {range}Connection: close\r\n\r\n",
+            self.path,
+            self.authority(),
+            env!("CARGO_PKG_VERSION"),
+        )?;
+
+        let mut reader = BufReader::new(stream.take(MAX_HEADERS));
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        let status_line = status_line.trim_end();
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or("not an HTTP server")?;
+
+        let mut length = None;
+        let mut total: Option<u64> = None;
+        let mut first = None;
+        let mut location = None;
+        let mut chunked = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            // Running out is the same as the end of the headers, unless
+            // it's because there were too many of them.
+            if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                if reader.get_ref().limit() == 0 {
+                    return Err("the server sent too many headers".into());
+                }
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => length = value.parse().ok(),
+                // bytes 0-99/1234
+                "content-range" => {
+                    let (range, all) = value.rsplit_once('/').unzip();
+                    total = all.and_then(|total| total.parse().ok());
+                    first = range
+                        .and_then(|range| range.strip_prefix("bytes "))
+                        .and_then(|range| range.split_once('-'))
+                        .and_then(|(first, _)| first.parse().ok());
+                }
+                "location" => location = Some(value.to_string()),
+                "transfer-encoding" => {
+                    chunked = !value.eq_ignore_ascii_case("identity");
+                }
+                _ => {}
+            }
+        }
+
+        match status {
+            200 | 206 => {}
+            301 | 302 | 303 | 307 | 308 => {
+                let location = location.ok_or("redirected to nowhere")?;
+                return Ok(Reply::Redirect(location));
+            }
+            _ => return Err(status_line.into()),
+        }
+        if chunked {
+            return Err(
+                "the server sent it in chunks, which isn't supported".into()
+            );
+        }
+        reader.get_mut().set_limit(u64::MAX);
+        let partial = status == 206;
+        if !partial {
+            total = length;
+        }
+        // Where what was asked for starts.
+        let start = match bytes {
+            Bytes::All => None,
+            Bytes::Range(range) => Some(range.start),
+            Bytes::Last(length) => {
+                total.map(|total| total.saturating_sub(*length))
+            }
+        };
+        if partial && (start.is_none() || first != start) {
+            return Err("the server sent some other part of it".into());
+        }
+        // Without a length, it's over once the server hangs up.
+        let body: Box<dyn Read + Send> = match length {
+            Some(length) => Box::new(reader.take(length)),
+            None => Box::new(reader),
+        };
+        Ok(Reply::Done(Response { partial, total, body }))
+    }
+
+    fn connect(&self) -> Res<TcpStream> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let addresses = (host, self.port).to_socket_addrs()?;
+        let mut error = None;
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(TIMEOUT))?;
+                    stream.set_write_timeout(Some(TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) => Err(e.into()),
+            None => Err(format!("couldn't find {host}").into()),
+        }
+    }
+
+    /// Where a redirect to `location` goes.
+    fn join(&self, location: &str) -> Res<Self> {
+        // Or it'd end up in the next request as it is.
+        if location.contains(|c: char| c.is_control() || c.is_whitespace()) {
+            return Err("redirected somewhere invalid".into());
+        }
+        if location.contains("://") {
+            return Self::parse(location);
+        }
+        let path = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            // Relative to the directory the file's in.
+            let directory = self.path.split('?').next().unwrap_or_default();
+            let directory = &directory[..=directory.rfind('/').unwrap_or(0)];
+            format!("{directory}{location}")
+        };
+        Ok(Self { path, ..self.clone() })
+    }
+
+    fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// A server that answers one request with `response`.
+    fn serve(response: Vec<u8>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            // It may have hung up already.
+            let _ = (&stream).write_all(&response);
+        });
+        Url::parse(&format!("http://127.0.0.1:{port}/file")).unwrap()
+    }
+
+    #[test]
+    fn gets_a_range() {
+        let url = serve(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 3\r\n\
+              Content-Range: bytes 2-4/10\r\n\r\nabcdef"
+                .to_vec(),
+        );
+        let mut response = url.get(&Bytes::Range(2..5)).unwrap();
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        assert!(response.partial);
+        assert_eq!(response.total, Some(10));
+        assert_eq!(body, "abc");
+    }
+
+    #[test]
+    fn refuses_other_ranges() {
+        let url = serve(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 3\r\n\
+              Content-Range: bytes 0-2/10\r\n\r\nabc"
+                .to_vec(),
+        );
+        assert!(url.get(&Bytes::Range(2..5)).is_err());
+    }
+
+    #[test]
+    fn refuses_redirects_with_control_characters() {
+        let url = Url::parse("http://127.0.0.1:1/file").unwrap();
+        assert_eq!(url.join("other").unwrap().path, "/other");
+        assert!(url.join("other\r\nX-Injected: yes").is_err());
+    }
+
+    #[test]
+    fn refuses_empty_ranges() {
+        let url = Url::parse("http://127.0.0.1:1/file").unwrap();
+        assert!(url.request(&Bytes::Range(5..5)).is_err());
+    }
+
+    #[test]
+    fn refuses_endless_headers() {
+        let mut response = b"HTTP/1.1 200 OK\r\n".to_vec();
+        for _ in 0..MAX_HEADERS {
+            response.extend(b"X: y\r\n");
+        }
+        let Err(e) = serve(response).get(&Bytes::All) else {
+            panic!("the headers never ended");
+        };
+        assert_eq!(e.to_string(), "the server sent too many headers");
+    }
+}
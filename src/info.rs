@@ -0,0 +1,208 @@
+// `bplay info`, a quick look at what's inside a .bapple file.
+
+use std::{fs::File, io::Read, path::Path, time::Duration};
+
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{
+        Limits, entry_name, is_audio, is_dictionary, is_encryption, is_index,
+        is_manifest, is_metadata, is_poster, is_signature, subtitle_language,
+    },
+    metadata::{Episode, Metadata},
+    timeline::{Timeline, format_time},
+};
+
+pub fn run(path: &Path) -> Res<()> {
+    let mut archive = Archive::new(File::open(path)?);
+    let mut metadata = None;
+    let mut frames = 0;
+    let mut frames_size = 0;
+    let mut audio_size = None;
+    let mut subtitles = Vec::new();
+    let mut poster = false;
+    let mut dictionary_size = None;
+    let mut manifest = false;
+    let mut signed = false;
+    let mut encrypted_size = 0;
+    let mut indexed = false;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+
+        if is_metadata(&name) {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            metadata = Some(Metadata::parse(&name, &content));
+        } else if is_audio(&name) {
+            audio_size = Some(entry.size());
+        } else if is_poster(&name) {
+            poster = true;
+        } else if is_dictionary(&name) {
+            dictionary_size = Some(entry.size());
+        } else if is_manifest(&name) {
+            manifest = true;
+        } else if is_signature(&name) {
+            signed = true;
+        } else if is_encryption(&name) {
+            encrypted_size += entry.size();
+        } else if is_index(&name) {
+            indexed = true;
+        } else if let Some(language) = subtitle_language(&name) {
+            // Labels are in the metadata, which may not have come up yet.
+            subtitles.push(language.to_string());
+        } else {
+            frames += 1;
+            frames_size += entry.size();
+        }
+    }
+
+    let metadata = match metadata {
+        Some(Ok(metadata)) => metadata,
+        Some(Err(e)) => {
+            println!("Metadata: invalid, {e}");
+            Metadata::default()
+        }
+        None => Metadata::default(),
+    };
+    print_fields(&metadata);
+    // Everything else is in there.
+    if encrypted_size > 0 {
+        println!("Encrypted: yes ({})", size(encrypted_size));
+        return Ok(());
+    }
+
+    let played = metadata.played(frames, Limits::default())?;
+    if played == frames {
+        println!("Frames: {frames} ({})", size(frames_size));
+    } else {
+        println!("Frames: {played} ({frames} stored, {})", size(frames_size));
+    }
+
+    if let Some(dictionary_size) = dictionary_size {
+        println!("Dictionary: yes ({})", size(dictionary_size));
+    }
+
+    let timeline = print_timing(&metadata, played);
+
+    let audio_track = match &metadata.audio_track {
+        Some(track) => format!(", {track}"),
+        None => String::new(),
+    };
+    match audio_size {
+        Some(audio_size) => {
+            println!("Audio: yes ({}){audio_track}", size(audio_size));
+        }
+        None => println!("Audio: no"),
+    }
+    if poster {
+        println!("Poster: yes");
+    }
+    if manifest {
+        let signed = if signed { ", signed" } else { "" };
+        println!("Hashes: yes{signed} (bplay validate checks them)");
+    }
+    if indexed {
+        println!("Index: yes (plays straight off of a web server)");
+    }
+    if !subtitles.is_empty() {
+        let labels: Vec<_> = subtitles
+            .iter()
+            .map(|language| metadata.subtitle_label(language).to_string())
+            .collect();
+        println!("Subtitles: {}", labels.join(", "));
+    }
+    print_episodes(&metadata.episodes, &timeline);
+
+    Ok(())
+}
+
+/// What the metadata says about the file itself.
+fn print_fields(metadata: &Metadata) {
+    let fields = [
+        ("Title", &metadata.title),
+        ("Author", &metadata.author),
+        ("Description", &metadata.description),
+        ("Source", &metadata.source),
+        ("Created", &metadata.created),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+
+    if metadata.is_newer() {
+        println!("Format: {} (newer than this bplay)", metadata.version);
+    } else {
+        println!("Format: {}", metadata.version);
+    }
+    let unknown = metadata.unknown_requirements();
+    if !unknown.is_empty() {
+        println!("Needs a newer bplay for: {}", unknown.join(", "));
+    }
+
+    // Quoted, since it usually starts with a space.
+    if let Some(charset) = &metadata.charset {
+        println!("Charset: {charset:?}");
+    }
+}
+
+/// The framerate and how long it all plays for.
+fn print_timing(metadata: &Metadata, frames: usize) -> Timeline {
+    let frametime = metadata.effective_frametime();
+    let timeline = Timeline::new(
+        frametime,
+        &metadata.frametimes,
+        &metadata.speeds,
+        frames,
+    );
+    if !metadata.frametimes.is_empty() {
+        println!("Framerate: variable");
+        println!("Duration: {}", format_time(timeline.duration()));
+    } else if frametime != 0 {
+        let frametime = Duration::from_micros(frametime);
+        println!("Framerate: {:.3} fps", frametime.as_secs_f64().recip());
+        println!("Duration: {}", format_time(timeline.duration()));
+    } else {
+        println!("Framerate: unknown");
+    }
+    if !metadata.speeds.is_empty() {
+        println!("Speed changes: {}", metadata.speeds.len());
+    }
+    timeline
+}
+
+/// Numbered from 1, like `--episode` takes them.
+fn print_episodes(episodes: &[Episode], timeline: &Timeline) {
+    if episodes.is_empty() {
+        return;
+    }
+    println!("Episodes:");
+    for (number, episode) in episodes.iter().enumerate() {
+        let length = timeline
+            .start(episode.end)
+            .saturating_sub(timeline.start(episode.start));
+        println!(
+            "  {}. {} (frames {}..{}, {})",
+            number + 1,
+            episode.title,
+            episode.start,
+            episode.end,
+            format_time(length)
+        );
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
@@ -0,0 +1,544 @@
+#![warn(clippy::pedantic)]
+use std::{
+    env,
+    io::{IsTerminal, stdout},
+    panic,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use crossterm::{
+    cursor::Show,
+    event::DisableMouseCapture,
+    execute,
+    style::ResetColor,
+    terminal::{EndSynchronizedUpdate, LeaveAlternateScreen},
+};
+
+use crate::{
+    archive::Limits,
+    config::Config,
+    controls::{Controls, Repeat, Skip},
+    encryption::Plain,
+    filter::Settings,
+    frames::Loading,
+    history::History,
+    http::is_url,
+    messages::NO_MANIFEST,
+    playlist::{Item, Shuffle},
+    primitives::{Args, Bapple, Command, show_poster},
+    render::Levels,
+    spinner::Spinner,
+    subtitles::{Choice, Subtitles},
+    terminal::{Capabilities, ColorDepth},
+    timeline::format_time,
+    timings::Timings,
+    tui::Tui,
+};
+
+type Res<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+mod archive;
+mod audio;
+mod browser;
+mod clock;
+mod completions;
+mod concat;
+mod config;
+#[cfg(windows)]
+mod console;
+mod control;
+mod controls;
+mod diff;
+mod encode;
+mod encryption;
+mod eq;
+mod filter;
+mod frames;
+mod guard;
+mod history;
+mod http;
+mod info;
+mod manifest;
+mod messages;
+mod meta;
+mod metadata;
+mod metrics;
+mod optimize;
+mod paths;
+mod playlist;
+mod prefetch;
+mod primitives;
+mod priority;
+mod remote;
+mod remux;
+mod render;
+mod screen;
+mod screensaver;
+mod serve;
+mod signature;
+pub mod snapshot;
+mod spinner;
+mod split;
+mod stats;
+mod subtitles;
+#[cfg(test)]
+mod temp;
+mod terminal;
+mod timeline;
+mod timings;
+mod trace;
+mod trim;
+mod tui;
+mod visualizer;
+mod websocket;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Everything `bplay` does, going by its command line arguments.
+///
+/// # Errors
+///
+/// Whatever stopped it, to be printed before exiting.
+pub fn run() -> Res<()> {
+    ctrlc::set_handler(ctrl_c)?;
+    restore_on_panic();
+    let args = Args::parse();
+
+    if let Some(command) = args.command {
+        return subcommand(command);
+    }
+
+    // Kept until the end, which is when the trace gets written out.
+    let _trace = args.trace.as_deref().map(trace::start).transpose()?;
+
+    if args.history {
+        history::print();
+        return Ok(());
+    }
+
+    let capabilities = capabilities(&args);
+    if let Some(dir) = &args.screensaver {
+        return screensaver::run(dir, &args, capabilities);
+    }
+    let Some(items) = items(&args, capabilities)? else {
+        return Ok(());
+    };
+
+    // Kept until the end too, since they're what gets played.
+    let (paths, _decrypted) = decrypt(&items, &args)?;
+
+    if args.verify || args.require_signed {
+        verify(&items, &paths, &args)?;
+    }
+
+    // Counted from now, so loading is part of the wait.
+    let mut start_at = args.start_at.map(start_at);
+
+    println!("Processing frames...");
+
+    if let Some(dir) = &args.render_to_dir {
+        return render_to_dir(dir, &items, &paths, &args, capabilities);
+    }
+
+    let mut shuffle = args.shuffle.then(|| {
+        let seed = args.seed.unwrap_or_else(|| {
+            let seed = Shuffle::random_seed();
+            println!("Shuffling with --seed {seed}");
+            seed
+        });
+        Shuffle::new(seed)
+    });
+
+    let repeat = if args.r#loop { Repeat::All } else { args.repeat };
+    let mut controls = Controls::new(repeat, Config::load().with_env().osd);
+    controls.sub_delay = args.sub_delay;
+    controls.sub_track.clone_from(&args.sub_track);
+    if args.tui {
+        controls.set_tui(Tui::new()?);
+        controls.playlist = items.iter().map(Item::name).collect();
+    }
+    // Shared by every item, so the audio doesn't cut out between them.
+    let mut output = None;
+    let mut queue = playlist::pass(items.len(), shuffle.as_mut(), None);
+    let mut next = None;
+    // For going back with the previous key.
+    let mut history = Vec::new();
+    // Added up over everything played.
+    let mut timings = Timings::default();
+
+    while let Some(index) = queue.pop_front() {
+        let loading = match next.take() {
+            Some((preloaded, loading)) if preloaded == index => loading,
+            _ => preload(&paths[index], &args),
+        };
+        let mut bapple = wait(loading, &paths[index], &args, capabilities)?;
+
+        // Every pass gets shuffled again.
+        let starts_over = queue.is_empty() && controls.repeat == Repeat::All;
+        if starts_over {
+            queue = playlist::pass(items.len(), shuffle.as_mut(), Some(index));
+        }
+        // Load the next one while this one plays.
+        if let Some(&following) = queue.front() {
+            next = Some((following, preload(&paths[following], &args)));
+        }
+
+        configure(&mut bapple, &args, &items[index], capabilities)?;
+        bapple.set_start_at(start_at.take());
+        controls.current = index;
+        let mut plays = 0;
+        loop {
+            bapple.play(&mut output, &mut controls)?;
+            if STOP.load(Ordering::Relaxed) {
+                remember(&items[index], &bapple, &controls);
+                timings.merge(bapple.timings());
+                return report(&args, &timings);
+            }
+
+            plays += 1;
+            let done = plays >= items[index].options.loops
+                && controls.repeat != Repeat::One;
+            if done || controls.skip.is_some() {
+                break;
+            }
+        }
+
+        remember(&items[index], &bapple, &controls);
+        timings.merge(bapple.timings());
+
+        // The repeat mode might have changed while it was playing.
+        if queue.is_empty() && controls.repeat == Repeat::All {
+            queue = playlist::pass(items.len(), shuffle.as_mut(), Some(index));
+        } else if starts_over && controls.repeat != Repeat::All {
+            queue.clear();
+        }
+
+        // Going back from the first one starts it over.
+        if controls.skip.take() == Some(Skip::Previous) {
+            queue.push_front(index);
+            if let Some(previous) = history.pop() {
+                queue.push_front(previous);
+            }
+        } else {
+            history.push(index);
+        }
+    }
+    report(&args, &timings)
+}
+
+/// When to start for `--start-at`, saying so.
+fn start_at(at: Duration) -> Instant {
+    let wait = clock::until(at);
+    println!("Starting at {}, in {}", format_time(at), format_time(wait));
+    Instant::now() + wait
+}
+
+/// Writes every frame of every item out instead of playing them.
+fn render_to_dir(
+    dir: &Path,
+    items: &[Item],
+    paths: &[PathBuf],
+    args: &Args,
+    capabilities: Capabilities,
+) -> Res<()> {
+    let subtitles =
+        Choice { track: args.sub_track.as_deref(), delay: args.sub_delay };
+    for (index, (item, path)) in items.iter().zip(paths).enumerate() {
+        let mut bapple = Bapple::new(path, loading(args), limits(args))?;
+        configure(&mut bapple, args, item, capabilities)?;
+        // One directory per playlist item.
+        if items.len() == 1 {
+            bapple.render_to_dir(dir, subtitles)?;
+        } else {
+            let dir = dir.join(format!("{index:03}"));
+            bapple.render_to_dir(&dir, subtitles)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints or writes out the timings, if they were asked for.
+fn report(args: &Args, timings: &Timings) -> Res<()> {
+    if let Some(path) = &args.timings_json {
+        timings.write_json(path)?;
+    } else if args.timings {
+        timings.print();
+    }
+    Ok(())
+}
+
+fn capabilities(args: &Args) -> Capabilities {
+    let mut capabilities = terminal::detect();
+    // https://no-color.org
+    if args.no_color || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        capabilities.color = ColorDepth::None;
+    }
+    if args.ascii {
+        capabilities.unicode = false;
+    }
+    capabilities
+}
+
+/// The file given, or whatever gets picked in the browser without one.
+/// `None` if nothing got picked.
+fn items(args: &Args, capabilities: Capabilities) -> Res<Option<Vec<Item>>> {
+    if let Some(file) = &args.file {
+        return playlist::load(file).map(Some);
+    }
+
+    let dir = args.browse.as_deref().unwrap_or(Path::new("."));
+    let picked = browser::pick(dir, capabilities, max_frame_size(args))?;
+    let Some(picked) = picked else {
+        return Ok(None);
+    };
+    let mut items = playlist::load(&picked.path)?;
+    // Picked from the history, so it carries on from where it was left.
+    if let [item] = items.as_mut_slice() {
+        item.options.start = picked.start;
+    }
+    Ok(Some(items))
+}
+
+/// The subcommands, none of which play anything.
+fn subcommand(command: Command) -> Res<()> {
+    match command {
+        Command::Encode(encode) => encode::run(&encode),
+        Command::Concat(concat) => concat::run(&concat),
+        Command::Split(split) => split::run(&split),
+        Command::Trim(trim) => trim::run(&trim),
+        Command::Remux(remux) => remux::run(&remux),
+        Command::Audio(audio) => audio::run(&audio),
+        Command::Diff(diff) => diff::run(&diff),
+        Command::Stats(stats) => stats::run(&stats),
+        Command::Optimize(optimize) => optimize::run(&optimize),
+        Command::Validate { file } => manifest::run(&file),
+        Command::Sign(sign) => signature::run(&sign),
+        Command::Keygen { key } => signature::keygen(&key),
+        Command::Info { file } => info::run(&file),
+        Command::Meta(meta) => meta::run(&meta),
+        Command::Serve(serve) => serve::run(&serve),
+        Command::Completions { shell } => completions::run(shell),
+    }
+}
+
+/// Decrypts whichever files are encrypted, giving the paths to read
+/// every item from and the decrypted copies, which go once dropped.
+fn decrypt(items: &[Item], args: &Args) -> Res<(Vec<PathBuf>, Vec<Plain>)> {
+    let mut paths = Vec::new();
+    let mut decrypted = Vec::new();
+    for item in items {
+        let path = item.path.display();
+        // Those on a server get turned down once they're read.
+        if is_url(&item.path) || !encryption::is_encrypted(&item.path)? {
+            paths.push(item.path.clone());
+            continue;
+        }
+        let Some(password_file) = &args.password_file else {
+            return Err(format!(
+                "{path}: is encrypted, play it with --password-file"
+            )
+            .into());
+        };
+        println!("Decrypting {path}...");
+        let password = encryption::read_password(password_file)?;
+        let copy = encryption::decrypt(&item.path, &password)
+            .map_err(|e| format!("{path}: {e}"))?;
+        paths.push(copy.path.clone());
+        decrypted.push(copy);
+    }
+    Ok((paths, decrypted))
+}
+
+/// Refuses to go on if any of the files got damaged, or with
+/// `--require-signed`, if any of them isn't signed by a trusted key.
+fn verify(items: &[Item], paths: &[PathBuf], args: &Args) -> Res<()> {
+    for (item, read) in items.iter().zip(paths) {
+        let path = item.path.display();
+        if is_url(&item.path) {
+            return Err(format!(
+                "{path}: can't be checked without downloading all of it"
+            )
+            .into());
+        }
+        let Some(report) = manifest::verify(read)? else {
+            if args.require_signed {
+                return Err(format!("{path}: isn't signed").into());
+            }
+            eprintln!("{path}:{NO_MANIFEST}");
+            continue;
+        };
+        if let Some(problem) = report.problems.first() {
+            let more = match report.problems.len() - 1 {
+                0 => String::new(),
+                more => format!(" (and {more} more)"),
+            };
+            return Err(format!("{path}: {problem}{more}").into());
+        }
+        let trusted = report
+            .signer
+            .is_some_and(|signer| args.trusted_key.contains(&signer));
+        if args.require_signed && !trusted {
+            let why = match report.signer {
+                Some(_) => "is signed by a key that isn't trusted",
+                None => "isn't signed",
+            };
+            return Err(format!("{path}: {why}").into());
+        }
+    }
+    Ok(())
+}
+
+fn configure(
+    bapple: &mut Bapple,
+    args: &Args,
+    item: &Item,
+    capabilities: Capabilities,
+) -> Res<()> {
+    bapple.set_visualizer(args.visualizer);
+    bapple.set_eq(args.eq);
+    bapple.set_resample(args.resample);
+    bapple.set_warnings((!args.no_warn).then_some(args.warn_delay));
+    bapple.set_precise_timing(args.precise_timing);
+    bapple.set_decode_ahead(args.decode_ahead);
+    bapple.set_high_priority(args.high_priority);
+    bapple.set_max_frame_size(max_frame_size(args));
+    bapple.set_capabilities(capabilities);
+    bapple.set_filters(filters(args));
+    if let Some(path) = &args.subs {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        bapple.add_subtitles(name.into_owned(), Subtitles::load(path)?);
+    }
+    bapple.set_sub_languages(args.slang.clone());
+
+    let options = item.options;
+    bapple.set_range(options.start, options.end);
+
+    let fps = options.fps.unwrap_or(args.frames_per_second);
+    if fps != 0.0 {
+        bapple.set_frametime(1_000_000.0 / fps);
+    }
+
+    if let Some(frames) = args.frames {
+        bapple.set_frames(frames.start, frames.end)?;
+    } else if let Some(frame) = args.start_frame {
+        bapple.set_frames(frame, None)?;
+    } else if let Some(episode) = &args.episode {
+        bapple.set_episode(episode)?;
+    }
+    Ok(())
+}
+
+/// Adds the file to the history, along with where it was stopped if it
+/// didn't play to the end.
+fn remember(item: &Item, bapple: &Bapple, controls: &Controls) {
+    let position =
+        if controls.stopping() { bapple.position() } else { Duration::ZERO };
+    if let Err(e) = History::record(&item.path, position) {
+        eprintln!("Couldn't save the history: {e}");
+    }
+}
+
+/// What the filter options ask for.
+fn filters(args: &Args) -> Settings {
+    Settings {
+        levels: Levels {
+            invert: args.invert,
+            brightness: args.brightness,
+            contrast: args.contrast,
+        },
+        charset: args.charset.as_ref().map(|charset| charset.chars().collect()),
+        crop: args.crop,
+        overlay_text: args.overlay_text.clone(),
+        overlay_position: args.overlay_position,
+        clock: args.clock,
+        tint: args.tint,
+        pad: args.pad,
+    }
+}
+
+fn loading(args: &Args) -> Loading {
+    if args.low_mem {
+        Loading::Stream
+    } else if let Some(megabytes) = args.max_mem {
+        Loading::Budget(megabytes_to_bytes(megabytes))
+    } else {
+        Loading::All
+    }
+}
+
+/// What `--max-frame-size` asks for, or what the terminal needs.
+fn max_frame_size(args: &Args) -> usize {
+    args.max_frame_size.map_or_else(frames::max_frame_size, megabytes_to_bytes)
+}
+
+fn limits(args: &Args) -> Limits {
+    Limits {
+        entries: args.max_entries,
+        bytes: args.max_archive_size.saturating_mul(1024 * 1024),
+    }
+}
+
+fn megabytes_to_bytes(megabytes: u64) -> usize {
+    let bytes = megabytes.saturating_mul(1024 * 1024);
+    usize::try_from(bytes).unwrap_or(usize::MAX)
+}
+
+/// Reads a file in the background. Errors become strings, since boxed
+/// ones can't leave the thread.
+fn preload(path: &Path, args: &Args) -> JoinHandle<Result<Bapple, String>> {
+    let path = path.to_path_buf();
+    let (loading, limits) = (loading(args), limits(args));
+    thread::spawn(move || {
+        Bapple::new(&path, loading, limits)
+            .map_err(|e| format!("{}: {e}", path.display()))
+    })
+}
+
+/// Takes a loaded file, showing its poster if it isn't ready yet.
+fn wait(
+    loading: JoinHandle<Result<Bapple, String>>,
+    path: &Path,
+    args: &Args,
+    capabilities: Capabilities,
+) -> Result<Bapple, String> {
+    // The TUI has a screen of its own to draw on.
+    if loading.is_finished() || args.tui {
+        return loading.join().expect("loading thread panicked");
+    }
+    if stdout().is_terminal() {
+        let max_size = max_frame_size(args);
+        show_poster(path, &filters(args), capabilities, max_size);
+    }
+    let _spinner = Spinner::start("Reading archive", capabilities.unicode);
+    loading.join().expect("loading thread panicked")
+}
+
+/// Puts the terminal back before the panic gets printed, since nothing
+/// past it gets the chance to: the cursor shown, colors reset, the mouse
+/// let go of, keys echoed again and the alternate screen left, so the
+/// message doesn't vanish along with it.
+fn restore_on_panic() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if stdout().is_terminal() {
+            let _ = execute!(
+                stdout(),
+                EndSynchronizedUpdate,
+                ResetColor,
+                Show,
+                DisableMouseCapture,
+                LeaveAlternateScreen
+            );
+        }
+        controls::restore_input();
+        default(info);
+    }));
+}
+
+fn ctrl_c() {
+    STOP.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,381 @@
+// The hashes of everything in a .bapple file, stored in it as
+// `manifest.ron`, the very last entry. Each entry gets its own SHA-256,
+// and one more goes over all of their names and hashes in order, so a
+// file that got damaged on the way over (a flipped bit, a missing or
+// reordered entry, a cut off end) doesn't go unnoticed. `bplay validate`
+// checks a file against it, and so does playing one with `--verify`. The
+// manifest can be signed in turn, see `signature`.
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write as _},
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    process::exit,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{
+        entry_name, is_index, is_manifest, is_signature, read_metadata,
+        refuse_encrypted,
+    },
+    signature::Signature,
+};
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    /// How it all got hashed, only ever `sha256` so far.
+    pub algorithm: String,
+    /// In the order they're in the archive.
+    pub entries: Vec<Hashed>,
+    /// Over the names and hashes of every entry.
+    pub digest: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hashed {
+    pub name: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// What's wrong with a file, going by its manifest.
+pub enum Problem {
+    /// The entry isn't what got hashed.
+    Changed(String),
+    Missing(String),
+    /// There's an entry the manifest doesn't know of.
+    Extra(String),
+    /// Every entry is right, but not in the right order.
+    Reordered,
+    /// The manifest doesn't match itself, so it's what got damaged.
+    Manifest,
+    /// The signature isn't over this manifest.
+    Signature,
+}
+
+/// How checking a file went.
+pub struct Report {
+    /// Empty if it's all fine.
+    pub problems: Vec<Problem>,
+    /// The key that signed the manifest, if one did.
+    pub signer: Option<[u8; 32]>,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Changed(name) => write!(f, "{name}: damaged"),
+            Self::Missing(name) => write!(f, "{name}: missing"),
+            Self::Extra(name) => write!(f, "{name}: not in the manifest"),
+            Self::Reordered => write!(f, "The entries are out of order"),
+            Self::Manifest => write!(f, "The manifest itself is damaged"),
+            Self::Signature => write!(f, "The signature doesn't match"),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(entries: Vec<Hashed>) -> Self {
+        let digest = digest(&entries);
+        Self { algorithm: "sha256".to_string(), entries, digest }
+    }
+
+    pub fn parse(content: &[u8]) -> Res<Self> {
+        Ok(ron::from_str(str::from_utf8(content)?)?)
+    }
+
+    /// Whatever's different between what got hashed and what's there.
+    pub fn check(&self, found: &[Hashed]) -> Vec<Problem> {
+        if self.digest != digest(&self.entries) {
+            return vec![Problem::Manifest];
+        }
+        let expected: HashMap<&str, &Hashed> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect();
+        let mut problems = Vec::new();
+        for entry in found {
+            match expected.get(entry.name.as_str()) {
+                Some(hashed) if *hashed == entry => {}
+                Some(_) => problems.push(Problem::Changed(entry.name.clone())),
+                None => problems.push(Problem::Extra(entry.name.clone())),
+            }
+        }
+        for entry in &self.entries {
+            if found.iter().all(|found| found.name != entry.name) {
+                problems.push(Problem::Missing(entry.name.clone()));
+            }
+        }
+        if problems.is_empty() && self.entries != found {
+            problems.push(Problem::Reordered);
+        }
+        problems
+    }
+}
+
+/// A reader that hashes everything that goes through it.
+pub struct Hashing<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> Hashing<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new(), size: 0 }
+    }
+
+    pub fn finish(self, name: &Path) -> Hashed {
+        Hashed {
+            name: name.to_string_lossy().into_owned(),
+            size: self.size,
+            hash: hex(&self.hasher.finalize()),
+        }
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+/// What a file has to be checked against.
+struct Found {
+    entries: Vec<Hashed>,
+    /// As it's stored, since that's what gets signed, and parsed.
+    manifest: Option<(Vec<u8>, Manifest)>,
+    signature: Option<Vec<u8>>,
+}
+
+/// Hashes every entry of a file, picking up the manifest and signature
+/// on the way. A file with more than one of either is refused, since
+/// there'd be no telling which one counts.
+fn hash_entries(path: &Path) -> Res<Found> {
+    let mut archive = Archive::new(File::open(path)?);
+    let mut found =
+        Found { entries: Vec::new(), manifest: None, signature: None };
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        // Written after the manifest, since it has to go last.
+        if is_index(&name) {
+            continue;
+        }
+        if is_manifest(&name) || is_signature(&name) {
+            let twice = if is_signature(&name) {
+                found.signature.is_some()
+            } else {
+                found.manifest.is_some()
+            };
+            if twice {
+                return Err(format!(
+                    "{}: has more than one {}",
+                    path.display(),
+                    name.display()
+                )
+                .into());
+            }
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            if is_signature(&name) {
+                found.signature = Some(content);
+                continue;
+            }
+            let manifest = Manifest::parse(&content).map_err(|e| {
+                format!("{}: invalid manifest: {e}", path.display())
+            })?;
+            found.manifest = Some((content, manifest));
+            continue;
+        }
+        let mut hashing = Hashing::new(entry);
+        io::copy(&mut hashing, &mut io::sink())?;
+        found.entries.push(hashing.finish(&name));
+    }
+
+    Ok(found)
+}
+
+/// `bplay validate`, exiting with 1 if the file doesn't match, or has
+/// nothing to match.
+pub fn run(path: &Path) -> Res<()> {
+    refuse_encrypted(path)?;
+    let Some(report) = verify(path)? else {
+        return Err(format!(
+            "{}: has no hashes stored in it to check against",
+            path.display()
+        )
+        .into());
+    };
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+    if !report.problems.is_empty() {
+        exit(1);
+    }
+    println!("{}: every entry matches its hash", path.display());
+    if let Some(key) = report.signer {
+        println!("Signed by {}", hex(&key));
+    }
+    Ok(())
+}
+
+/// Checks a file against its manifest, and the manifest against its
+/// signature, `None` if it never had a manifest.
+pub fn verify(path: &Path) -> Res<Option<Report>> {
+    let found = hash_entries(path)?;
+    let Some((content, manifest)) = found.manifest else {
+        // Going last, it's the first thing to go when a file gets cut
+        // off.
+        let metadata = read_metadata(path)?.unwrap_or_default();
+        let missing = Problem::Missing("manifest.ron".to_string());
+        return Ok(metadata
+            .requires("manifest")
+            .then(|| Report { problems: vec![missing], signer: None }));
+    };
+
+    let mut problems = manifest.check(&found.entries);
+    let mut signer = None;
+    if let Some(signature) = found.signature {
+        signer = Signature::parse(&signature)
+            .ok()
+            .and_then(|signature| signature.check(&content));
+        if signer.is_none() {
+            problems.push(Problem::Signature);
+        }
+    }
+    Ok(Some(Report { problems, signer }))
+}
+
+fn digest(entries: &[Hashed]) -> String {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        hasher.update(entry.name.as_bytes());
+        hasher.update([0]);
+        hasher.update(entry.hash.as_bytes());
+    }
+    hex(&hasher.finalize())
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Exactly `N` bytes' worth of hex, or `None`.
+pub fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != 2 * N || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use tar::{Archive, Builder, Header};
+
+    use super::*;
+    use crate::{
+        archive::{ArchiveWriter, is_frame, write_signature},
+        signature::{Signature, read_manifest},
+        temp::TempPath,
+    };
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    /// A file with two frames, signed with `SECRET`.
+    fn signed(path: &Path) {
+        let mut writer = ArchiveWriter::create(path).unwrap();
+        writer.append_frame(b"one").unwrap();
+        writer.append_frame(b"two").unwrap();
+        writer.append_manifest().unwrap();
+        writer.finish().unwrap();
+        let manifest = read_manifest(path).unwrap().unwrap();
+        let signature = Signature::new(&SECRET, &manifest);
+        let serialized = ron::to_string(&signature).unwrap();
+        write_signature(path, path, serialized.as_bytes()).unwrap();
+    }
+
+    /// `path` again, with `name` slipped in right before the manifest.
+    fn tampered(path: &Path, output: &Path, name: &str) {
+        let mut archive = Archive::new(File::open(path).unwrap());
+        let mut builder = Builder::new(File::create(output).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let header = entry.header().clone();
+            if is_manifest(&entry.path().unwrap()) {
+                let mut extra = Header::new_gnu();
+                extra.set_size(3);
+                extra.set_mode(0o644);
+                builder.append_data(&mut extra, name, &b"bad"[..]).unwrap();
+            }
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            builder.append(&header, &content[..]).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn signed_file_verifies() {
+        let path = TempPath::new();
+        signed(&path);
+        let report = verify(&path).unwrap().unwrap();
+
+        assert!(report.problems.is_empty());
+        assert!(report.signer.is_some());
+    }
+
+    #[test]
+    fn entries_named_like_the_index_get_hashed() {
+        let path = TempPath::new();
+        signed(&path);
+
+        for name in
+            ["frames/index.zst", "audio/index.mp3", "frames/manifest.zst"]
+        {
+            let output = TempPath::new();
+            tampered(&path, &output, name);
+            let report = verify(&output).unwrap().unwrap();
+
+            let extra = report.problems.iter().any(
+                |problem| matches!(problem, Problem::Extra(extra) if extra == name),
+            );
+            assert!(extra, "{name} went unnoticed");
+        }
+        assert!(is_frame(Path::new("frames/index.zst")));
+    }
+
+    #[test]
+    fn refuses_a_second_manifest_or_signature() {
+        let path = TempPath::new();
+        signed(&path);
+
+        let output = TempPath::new();
+        tampered(&path, &output, "manifest.sig");
+        assert!(verify(&output).is_err());
+        tampered(&path, &output, "manifest.ron");
+        assert!(hash_entries(&output).is_err());
+        assert!(read_manifest(&output).is_err());
+    }
+}
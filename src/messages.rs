@@ -1,14 +1,53 @@
 #[cfg(target_os = "linux")]
-pub const ALSA_WARNING: &str = "
-\x1b[33m[warning]\x1b[0m ALSA may not be configured for your audio server.
+pub const NO_OUTPUT_DEVICE: &str = "
+\x1b[33m[warning]\x1b[0m No usable audio output device was found, ALSA may not be configured for your audio server.
 If you use pipewire AND the audio doesn't work, try running:
     echo 'pcm.!default { type pipewire }' | sudo tee /etc/alsa/conf.d/99-pipewire.conf
     echo 'ctl.!default { type pipewire }' | sudo tee -a /etc/alsa/conf.d/99-pipewire.conf
 ";
 
-pub const FRAMETIME_ZERO: &str = "
-\x1b[31m[fatal]\x1b[0m The .bapple file you tried to play was likely compiled with an old version of asciic, or it's corrupted.
-The file metadata could not be parsed.
-Please, try re-converting the file or passing an FPS value as such:
-    bplay <fps>
+#[cfg(not(target_os = "linux"))]
+pub const NO_OUTPUT_DEVICE: &str = "
+\x1b[33m[warning]\x1b[0m No usable audio output device was found, the audio probably won't play.
+";
+
+pub const NO_PRIORITY: &str = "
+\x1b[33m[warning]\x1b[0m --high-priority isn't allowed to raise the priority, so playback goes on at the normal one.
+On Linux, that takes root, CAP_SYS_NICE, or an rtprio or nice limit for your user in /etc/security/limits.conf.
+";
+
+pub const FRAMETIME_ZERO: &str = "The file's metadata couldn't be parsed, so there's no telling how long frames last. It was likely made with an old version of asciic, or it's corrupted. Try converting it again, or give it an FPS with bplay <fps>";
+
+pub const NO_CHARSET: &str = "
+\x1b[33m[warning]\x1b[0m This file doesn't say which characters it's drawn with, so --invert, --brightness, --contrast and --charset can't do anything.
+If you know them, from darkest to brightest, they can be added with:
+    bplay meta set charset ' .:-=+*#%@' <file>
+";
+
+pub const INVALID_METADATA: &str = "
+\x1b[33m[warning]\x1b[0m This file's metadata couldn't be read, so it's being ignored:
+";
+
+pub const NEWER_FORMAT: &str = "
+\x1b[33m[warning]\x1b[0m This file was made by a newer version of bplay, so some of it may not play right.
+";
+
+pub const UNSUPPORTED_COLORS: &str = "
+\x1b[33m[warning]\x1b[0m This file uses more colors than your terminal seems to support, so it may look off.
+";
+
+pub const AUDIO_MISSING: &str = "
+\x1b[33m[warning]\x1b[0m Only some of these files have audio, so the joined file won't have any.
+";
+
+pub const AUDIO_MISMATCH: &str = "
+\x1b[33m[warning]\x1b[0m These files have audio at different sample rates, which can't be joined into one track, so the joined file won't have any.
+";
+
+pub const DICTIONARY_FAILED: &str = "
+\x1b[33m[warning]\x1b[0m There's too little in this file to train a dictionary on, so its frames are compressed by themselves.
+";
+
+pub const NO_MANIFEST: &str = "
+\x1b[33m[warning]\x1b[0m This file has no hashes stored in it, so there's nothing to check it against.
 ";
@@ -0,0 +1,65 @@
+// `bplay meta`, for fixing up metadata without re-packing anything by hand.
+
+use std::path::PathBuf;
+
+use clap::{Subcommand, builder::PossibleValuesParser};
+
+use crate::{
+    Res,
+    archive::{read_metadata, write_metadata},
+    metadata::Metadata,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum MetaCommand {
+    /// Print every metadata field
+    List {
+        /// Path to a .bapple file
+        file: PathBuf,
+    },
+    /// Print a single metadata field
+    Get {
+        #[arg(value_parser = PossibleValuesParser::new(Metadata::KEYS))]
+        key: String,
+        /// Path to a .bapple file
+        file: PathBuf,
+    },
+    /// Change a metadata field, rewriting only the metadata entry
+    Set {
+        #[arg(value_parser = PossibleValuesParser::new(Metadata::KEYS))]
+        key: String,
+        value: String,
+        /// Path to a .bapple file
+        file: PathBuf,
+    },
+}
+
+pub fn run(command: &MetaCommand) -> Res<()> {
+    match command {
+        MetaCommand::List { file } => {
+            let metadata = read_metadata(file)?.unwrap_or_default();
+            for key in Metadata::KEYS {
+                println!("{key}: {}", metadata.get(key).unwrap_or_default());
+            }
+        }
+        MetaCommand::Get { key, file } => {
+            let metadata = read_metadata(file)?.unwrap_or_default();
+            println!("{}", metadata.get(key).unwrap_or_default());
+        }
+        MetaCommand::Set { key, value, file } => {
+            let mut metadata = read_metadata(file)?.unwrap_or_default();
+            // Fields this version doesn't know would get dropped.
+            if metadata.is_newer() {
+                return Err(format!(
+                    "{}: made by a newer version of bplay, which this one \
+                     can't change without losing things",
+                    file.display()
+                )
+                .into());
+            }
+            metadata.set(key, value)?;
+            write_metadata(file, &metadata)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,447 @@
+// The `metadata` entry of a .bapple file. Every field is optional, so
+// files from older versions of asciic keep working. Newer files carry a
+// format version, and can list fields a player has to understand to play
+// them right, so ones from a newer bplay don't quietly play wrong. It's
+// RON when written here, but JSON and TOML are read too, for encoders in
+// other languages.
+
+use std::{fmt::Display, iter::repeat_n, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::Limits;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Metadata {
+    /// The format the file was written in, 0 for files from before there
+    /// was one.
+    #[serde(default)]
+    pub version: u32,
+    /// Fields that can't be ignored, for players that don't know them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+    #[serde(default)]
+    pub frametime: u64,
+    /// DEPRECATED, `frametime` wins if both are set. Still written, since
+    /// players from before `frametime` can't read files without it.
+    #[serde(default)]
+    pub fps: u64,
+    /// Per-frame durations in microseconds, for variable framerate files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub frametimes: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Where the video came from, usually a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// When the file was created, as YYYY-MM-DD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// Parts of the video to play faster or slower.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub speeds: Vec<SpeedRegion>,
+    /// Characters frames are drawn with, from darkest to brightest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_track: Option<TrackLabel>,
+    /// Only for tracks that have a name, the rest go by their language.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitle_tracks: Vec<TrackLabel>,
+    /// For collections shipped as one file, each episode by itself.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub episodes: Vec<Episode>,
+    /// Runs of identical frames, each stored only once.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repeats: Vec<Repeat>,
+}
+
+/// Plays everything between `start` and `end` (in microseconds, as if
+/// played normally) at `speed` times the usual rate, so 0.5 is slow
+/// motion. The audio is left alone, it has to be made to match.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SpeedRegion {
+    pub start: u64,
+    pub end: u64,
+    pub speed: f64,
+}
+
+/// Frames `start` up to, but not including, `end`, played on their own
+/// with `--episode`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Episode {
+    pub title: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The `frame`th frame stored in the archive (from 0) shows up `times`
+/// more times right after itself.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Repeat {
+    pub frame: usize,
+    pub times: usize,
+}
+
+/// What a track is in, like `en`, and what to call it, like `English`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackLabel {
+    pub language: String,
+    /// Empty if it has none, rather than an `Option`, so it's easier to
+    /// write by hand with `bplay meta set`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+}
+
+impl Display for TrackLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "{}", self.language)
+        } else {
+            write!(f, "{} ({})", self.name, self.language)
+        }
+    }
+}
+
+impl Metadata {
+    /// Reads the entry called `name`, going by its extension, or by how it
+    /// starts if it doesn't have a known one. JSON and TOML are there for
+    /// encoders written in other languages, and go through the same serde
+    /// derives, so nothing here has to change when a field gets added.
+    pub fn parse(name: &Path, content: &[u8]) -> Result<Self, String> {
+        let content = String::from_utf8_lossy(content);
+        let start = content.trim_start().chars().next();
+        match name.extension().and_then(|e| e.to_str()) {
+            Some("json") => from_json(&content),
+            Some("toml") => from_toml(&content),
+            Some("ron") => from_ron(&content),
+            _ if start == Some('{') => from_json(&content),
+            // RON can't start with a bare key or a table header.
+            _ if start != Some('(') => {
+                from_toml(&content).or_else(|_| from_ron(&content))
+            }
+            _ => from_ron(&content),
+        }
+    }
+
+    /// The format this version writes, bumped whenever older players
+    /// would get something wrong.
+    pub const VERSION: u32 = 2;
+
+    /// Fields that can be read and changed through `bplay meta`.
+    pub const KEYS: [&str; 14] = [
+        "frametime",
+        "fps",
+        "frametimes",
+        "title",
+        "author",
+        "description",
+        "source",
+        "created",
+        "speeds",
+        "charset",
+        "audio_track",
+        "subtitle_tracks",
+        "episodes",
+        "repeats",
+    ];
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "frametime" => Some(self.frametime.to_string()),
+            "fps" => Some(self.fps.to_string()),
+            "frametimes" => ron::to_string(&self.frametimes).ok(),
+            "title" => self.title.clone(),
+            "author" => self.author.clone(),
+            "description" => self.description.clone(),
+            "source" => self.source.clone(),
+            "created" => self.created.clone(),
+            "speeds" => ron::to_string(&self.speeds).ok(),
+            "charset" => self.charset.clone(),
+            "audio_track" => {
+                self.audio_track.as_ref().and_then(|t| ron::to_string(t).ok())
+            }
+            "subtitle_tracks" => ron::to_string(&self.subtitle_tracks).ok(),
+            "episodes" => ron::to_string(&self.episodes).ok(),
+            "repeats" => ron::to_string(&self.repeats).ok(),
+            _ => None,
+        }
+    }
+
+    /// An empty value clears optional fields.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let text = (!value.is_empty()).then(|| value.to_string());
+
+        match key {
+            "frametime" => self.frametime = parse_field(key, value)?,
+            "fps" => self.fps = parse_field(key, value)?,
+            "frametimes" => {
+                self.frametimes = ron::from_str(value)
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            "title" => self.title = text,
+            "author" => self.author = text,
+            "description" => self.description = text,
+            "source" => self.source = text,
+            "created" => self.created = text,
+            "speeds" => {
+                self.speeds = ron::from_str(value)
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            "charset" => self.charset = text,
+            "audio_track" => {
+                self.audio_track = text
+                    .map(|text| ron::from_str(&text))
+                    .transpose()
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            "subtitle_tracks" => {
+                self.subtitle_tracks = ron::from_str(value)
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            "episodes" => {
+                self.episodes = ron::from_str(value)
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            "repeats" => {
+                self.repeats = ron::from_str(value)
+                    .map_err(|e| format!("Invalid value for {key}: {e}"))?;
+            }
+            _ => return Err(format!("Unknown metadata field '{key}'")),
+        }
+        Ok(())
+    }
+
+    /// Whether the file was written by a newer bplay.
+    pub fn is_newer(&self) -> bool {
+        self.version > Self::VERSION
+    }
+
+    /// What files can need besides the fields: entries players have to
+    /// know about, so they don't get taken for frames.
+    pub const FEATURES: [&str; 3] = ["dictionary", "manifest", "encryption"];
+
+    /// Whether `required` lists `field`.
+    pub fn requires(&self, field: &str) -> bool {
+        self.required.iter().any(|required| required == field)
+    }
+
+    /// Fields a newer file needs that this version doesn't know about.
+    pub fn unknown_requirements(&self) -> Vec<&str> {
+        if !self.is_newer() {
+            return Vec::new();
+        }
+        self.required
+            .iter()
+            .map(String::as_str)
+            .filter(|field| {
+                !Self::KEYS.contains(field) && !Self::FEATURES.contains(field)
+            })
+            .collect()
+    }
+
+    /// Errors out on files that can't be played right, see `required`.
+    pub fn check_requirements(&self) -> Result<(), String> {
+        let unknown = self.unknown_requirements();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "needs a newer version of bplay, for {}",
+                unknown.join(", ")
+            ))
+        }
+    }
+
+    /// The label for the subtitle track in `language`, which is just the
+    /// language if the file doesn't name it.
+    pub fn subtitle_label(&self, language: &str) -> TrackLabel {
+        self.subtitle_tracks
+            .iter()
+            .find(|track| track.language == language)
+            .cloned()
+            .unwrap_or_else(|| TrackLabel {
+                language: language.to_string(),
+                name: String::new(),
+            })
+    }
+
+    /// How many frames get played out of `stored` ones, counting
+    /// repeats. Repeats of frames that aren't there are left out. Like
+    /// entries, there can only be so many, so a tiny file can't say it
+    /// plays more than there's memory for.
+    pub fn played(
+        &self,
+        stored: usize,
+        limits: Limits,
+    ) -> Result<usize, String> {
+        self.repeats
+            .iter()
+            .filter(|repeat| repeat.frame < stored)
+            .try_fold(stored, |played, repeat| played.checked_add(repeat.times))
+            .filter(|&played| played <= limits.entries)
+            .ok_or_else(|| {
+                format!(
+                    "The file plays more than {} frames, counting repeats \
+                     (--max-entries raises the limit)",
+                    limits.entries
+                )
+            })
+    }
+
+    /// Which stored frame each played frame is, `None` if every frame is
+    /// stored by itself.
+    pub fn frame_map(
+        &self,
+        stored: usize,
+        limits: Limits,
+    ) -> Result<Option<Vec<usize>>, String> {
+        if self.repeats.is_empty() {
+            return Ok(None);
+        }
+        // Nothing adds up to more than this.
+        let played = self.played(stored, limits)?;
+        let mut times = vec![0; stored];
+        for repeat in &self.repeats {
+            if let Some(times) = times.get_mut(repeat.frame) {
+                *times += repeat.times;
+            }
+        }
+        let mut map = Vec::with_capacity(played);
+        for (frame, times) in times.into_iter().enumerate() {
+            map.extend(repeat_n(frame, times + 1));
+        }
+        Ok(Some(map))
+    }
+
+    pub fn effective_frametime(&self) -> u64 {
+        if self.frametime != 0 {
+            self.frametime
+        } else {
+            // DEPRECATED
+            1_000_000_u64.checked_div(self.fps).unwrap_or(0)
+        }
+    }
+}
+
+fn from_json(content: &str) -> Result<Metadata, String> {
+    serde_json::from_str(content).map_err(|e| e.to_string())
+}
+
+fn from_toml(content: &str) -> Result<Metadata, String> {
+    toml::from_str(content).map_err(|e| e.to_string())
+}
+
+fn from_ron(content: &str) -> Result<Metadata, String> {
+    ron::from_str(content).map_err(|e| e.to_string())
+}
+
+fn parse_field<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value.parse().map_err(|e| format!("Invalid value for {key}: {e}"))
+}
+
+/// Today's date in UTC, as YYYY-MM-DD.
+pub fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+
+    // Howard Hinnant's days_from_civil, backwards.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repeating(repeats: &[(usize, usize)]) -> Metadata {
+        let repeats = repeats
+            .iter()
+            .map(|&(frame, times)| Repeat { frame, times })
+            .collect();
+        Metadata { repeats, ..Metadata::default() }
+    }
+
+    fn parse(name: &str, content: &str) -> Result<Metadata, String> {
+        Metadata::parse(Path::new(name), content.as_bytes())
+    }
+
+    #[test]
+    fn reads_every_format() {
+        let json = r#"{"frametime": 40000, "title": "Bad Apple!!",
+            "author": null, "frametimes": [1, 2]}"#;
+        let toml = "frametime = 40000\ntitle = \"Bad Apple!!\"\n\
+            frametimes = [1, 2]\n";
+        let ron = r#"(frametime: 40000, title: Some("Bad Apple!!"),
+            frametimes: [1, 2])"#;
+        for (name, content) in [
+            ("metadata.json", json),
+            ("metadata.toml", toml),
+            ("metadata.ron", ron),
+            ("metadata", json),
+            ("metadata", toml),
+            ("metadata", ron),
+        ] {
+            let metadata = parse(name, content).unwrap();
+            assert_eq!(metadata.frametime, 40000, "{name}: {content}");
+            assert_eq!(metadata.title.as_deref(), Some("Bad Apple!!"));
+            assert_eq!(metadata.author, None);
+            assert_eq!(metadata.frametimes, [1, 2]);
+        }
+    }
+
+    #[test]
+    fn refuses_deep_nesting() {
+        // Without running out of stack first.
+        let deep = 100_000;
+        let json =
+            format!(r#"{{"title": {}{}}}"#, "[".repeat(deep), "]".repeat(deep));
+        assert!(parse("metadata.json", &json).is_err());
+        let toml = format!("title = {}{}", "[".repeat(deep), "]".repeat(deep));
+        assert!(parse("metadata.toml", &toml).is_err());
+        let toml =
+            format!("title = {}{}", "{a=".repeat(deep), "}".repeat(deep));
+        assert!(parse("metadata.toml", &toml).is_err());
+    }
+
+    #[test]
+    fn maps_repeated_frames() {
+        let metadata = repeating(&[(1, 2), (5, 1)]);
+        let map = metadata.frame_map(3, Limits::default()).unwrap();
+        assert_eq!(map, Some(vec![0, 1, 1, 1, 2]));
+    }
+
+    #[test]
+    fn refuses_repeats_that_overflow() {
+        let metadata = repeating(&[(0, usize::MAX), (1, usize::MAX)]);
+        assert!(metadata.played(2, Limits::default()).is_err());
+        assert!(metadata.frame_map(2, Limits::default()).is_err());
+    }
+
+    #[test]
+    fn refuses_repeats_over_the_limit() {
+        let limits = Limits { entries: 10, ..Limits::default() };
+        assert_eq!(repeating(&[(0, 9)]).played(1, limits), Ok(10));
+        assert!(repeating(&[(0, 10)]).frame_map(1, limits).is_err());
+    }
+}
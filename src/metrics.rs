@@ -0,0 +1,90 @@
+// What `bplay serve --metrics` answers `/metrics` with, for Prometheus to
+// keep an eye on servers that run for a long time, in its text format:
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds for how long decoding a frame takes, in seconds.
+const DECODE_BUCKETS: [f64; 10] =
+    [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Decoded and sent out, to however many clients.
+    pub frames: AtomicU64,
+    /// Not sent to clients that were behind, once for each of them.
+    pub dropped: AtomicU64,
+    /// Written to clients, WebSocket framing and all.
+    pub bytes: AtomicU64,
+    pub clients: AtomicU64,
+    pub decode: Histogram,
+}
+
+/// How long things took, counted into `DECODE_BUCKETS`.
+#[derive(Default)]
+pub struct Histogram {
+    /// Not added up, the last one being for everything longer.
+    buckets: [AtomicU64; DECODE_BUCKETS.len() + 1],
+    nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = DECODE_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(DECODE_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let counters = [
+            ("frames_rendered_total", "Frames sent out", &self.frames),
+            ("frames_dropped_total", "Frames clients missed", &self.dropped),
+            ("bytes_written_total", "Bytes written to clients", &self.bytes),
+        ];
+        for (name, help, value) in counters {
+            metric(&mut text, name, help, "counter");
+            let value = value.load(Ordering::Relaxed);
+            let _ = writeln!(text, "bplay_{name} {value}");
+        }
+
+        metric(&mut text, "clients", "Clients connected", "gauge");
+        let clients = self.clients.load(Ordering::Relaxed);
+        let _ = writeln!(text, "bplay_clients {clients}");
+
+        let name = "decode_seconds";
+        metric(&mut text, name, "Time taken to decode a frame", "histogram");
+        let mut count = 0;
+        for (i, bucket) in self.decode.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = DECODE_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), ToString::to_string);
+            let _ = writeln!(
+                text,
+                r#"bplay_{name}_bucket{{le="{bound}"}} {count}"#
+            );
+        }
+        let nanos = self.decode.nanos.load(Ordering::Relaxed);
+        let seconds = Duration::from_nanos(nanos).as_secs_f64();
+        let _ = writeln!(text, "bplay_{name}_sum {seconds}");
+        let _ = writeln!(text, "bplay_{name}_count {count}");
+        text
+    }
+}
+
+fn metric(text: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(text, "# HELP bplay_{name} {help}.");
+    let _ = writeln!(text, "# TYPE bplay_{name} {kind}");
+}
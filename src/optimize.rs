@@ -0,0 +1,329 @@
+// `bplay optimize`, making a .bapple file smaller without changing what
+// gets played. Runs of identical frames (once decompressed) get stored
+// only once, with the metadata saying how many more times each one shows
+// up. With `--dictionary`, a zstd dictionary gets trained on the frames
+// and they're all compressed again against it, which small frames, with
+// little in them to find patterns in by themselves, gain the most from.
+// Players from before either know to refuse such files.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use tar::Archive;
+use zstd::{bulk::Compressor, dict::from_samples};
+
+use crate::{
+    Res,
+    archive::{
+        ArchiveWriter, entry_name, is_dictionary, is_frame, is_index,
+        is_manifest, is_metadata, is_signature, read_dictionary, read_metadata,
+        refuse_encrypted, temp_path,
+    },
+    frames::{Dictionary, decompress, max_frame_size},
+    info::size,
+    messages::DICTIONARY_FAILED,
+    metadata::{Metadata, Repeat},
+};
+
+/// The most a dictionary gets to be, zstd's own default.
+const DICTIONARY_SIZE: usize = 110 * 1024;
+/// How much of the frames to train on, at most. Longer files only get
+/// every so many frames looked at.
+const SAMPLE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(clap::Args, Debug)]
+pub struct OptimizeArgs {
+    /// The .bapple file to optimize
+    pub file: PathBuf,
+    /// Where to write the result, instead of changing the file in place
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Also train a dictionary on the frames and compress them against it
+    #[arg(long)]
+    pub dictionary: bool,
+    /// zstd compression level, when compressing against a dictionary
+    #[arg(long, default_value_t = zstd::DEFAULT_COMPRESSION_LEVEL)]
+    pub compression_level: i32,
+}
+
+/// What to do to the file.
+pub struct Options {
+    /// Store runs of identical frames once.
+    pub dedup: bool,
+    /// Train a dictionary and compress the frames against it at this
+    /// level.
+    pub dictionary: Option<i32>,
+}
+
+/// How it went.
+pub struct Summary {
+    pub played: usize,
+    pub stored: usize,
+    /// The size of the dictionary trained, if one was.
+    pub dictionary: Option<usize>,
+}
+
+pub fn run(args: &OptimizeArgs) -> Res<()> {
+    let output = args.output.as_ref().unwrap_or(&args.file);
+    let before = fs::metadata(&args.file)?.len();
+    let options = Options {
+        dedup: true,
+        dictionary: args.dictionary.then_some(args.compression_level),
+    };
+    let summary = optimize(&args.file, output, &options)?;
+    let after = fs::metadata(output)?.len();
+
+    let dictionary = match summary.dictionary {
+        Some(bytes) => format!(", with a {} dictionary", size(bytes as u64)),
+        None => String::new(),
+    };
+    println!(
+        "Stored {} of {} frames{dictionary}, {} down to {}",
+        summary.stored,
+        summary.played,
+        size(before),
+        size(after)
+    );
+    Ok(())
+}
+
+/// Rewrites `path` to `output`, which can be `path` itself, through a
+/// temporary file so a failure halfway through doesn't leave a broken
+/// archive behind.
+pub fn optimize(path: &Path, output: &Path, options: &Options) -> Res<Summary> {
+    refuse_encrypted(path)?;
+    let mut metadata = read_metadata(path)?.unwrap_or_default();
+    // Fields this version doesn't know would get dropped.
+    if metadata.is_newer() {
+        return Err(format!(
+            "{}: made by a newer version of bplay, which this one can't \
+             change without losing things",
+            path.display()
+        )
+        .into());
+    }
+
+    let temp = temp_path(output);
+    let result = rewrite(path, &temp, &mut metadata, options);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    let summary = result?;
+    fs::rename(&temp, output)?;
+    Ok(summary)
+}
+
+/// Copies everything over but the frames, which only get written when
+/// they're different from the one before, if deduplicating, and get
+/// compressed again if there's a new dictionary.
+fn rewrite(
+    path: &Path,
+    temp: &Path,
+    metadata: &mut Metadata,
+    options: &Options,
+) -> Res<Summary> {
+    let old = read_dictionary(path)?;
+    let trained = match options.dictionary {
+        Some(level) => train(path, &old)?.map(|dictionary| (dictionary, level)),
+        None => None,
+    };
+    let mut compressor = match &trained {
+        Some((dictionary, level)) => {
+            Some(Compressor::with_dictionary(*level, dictionary)?)
+        }
+        None => None,
+    };
+    let mut has_dictionary = false;
+    let mut manifest = false;
+
+    // Repeats the file already had, since those frames count too.
+    let mut times = HashMap::new();
+    for repeat in &metadata.repeats {
+        *times.entry(repeat.frame).or_insert(0) += repeat.times;
+    }
+
+    let mut archive = Archive::new(File::open(path)?);
+    let mut writer = ArchiveWriter::create(temp)?;
+    let limit = max_frame_size();
+    let mut repeats = Vec::new();
+    // The last frame written, decompressed, and how many more times it
+    // has shown up since.
+    let mut last: Option<(Vec<u8>, usize)> = None;
+    let (mut stored, mut played) = (0, 0);
+    let mut compressed = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry)? else {
+            continue;
+        };
+        if is_metadata(&name) || is_index(&name) {
+            continue;
+        }
+        // Made again at the end, to match the new frames, so the
+        // signature over the old one goes.
+        if is_manifest(&name) || is_signature(&name) {
+            manifest |= is_manifest(&name);
+            continue;
+        }
+        if is_dictionary(&name) {
+            // Replaced by the new one, if there is one.
+            if trained.is_none() {
+                writer.append_raw(&name, entry.size(), entry)?;
+                has_dictionary = true;
+            }
+            continue;
+        }
+        if !is_frame(&name) {
+            writer.append_raw(&name, entry.size(), entry)?;
+            continue;
+        }
+
+        compressed.clear();
+        entry.read_to_end(&mut compressed)?;
+        let frame = decompress(&compressed[..], &old, limit)?;
+        let count = 1 + times.get(&stored).copied().unwrap_or(0);
+        stored += 1;
+        played += count;
+        match &mut last {
+            Some((previous, extra)) if options.dedup && *previous == frame => {
+                *extra += count;
+            }
+            _ => {
+                flush(&mut repeats, last.take(), writer.frames());
+                if let (Some((dictionary, _)), Some(compressor)) =
+                    (&trained, &mut compressor)
+                {
+                    if !has_dictionary {
+                        writer.append_dictionary(dictionary)?;
+                        has_dictionary = true;
+                    }
+                    writer.append_frame(&compressor.compress(&frame)?)?;
+                } else {
+                    writer.append_frame(&compressed)?;
+                }
+                last = Some((frame, count - 1));
+            }
+        }
+    }
+    flush(&mut repeats, last, writer.frames());
+
+    metadata.version = Metadata::VERSION;
+    metadata.repeats = repeats;
+    metadata
+        .required
+        .retain(|field| field != "repeats" && field != "dictionary");
+    if !metadata.repeats.is_empty() {
+        metadata.required.push("repeats".to_string());
+    }
+    if has_dictionary {
+        metadata.required.push("dictionary".to_string());
+    }
+    writer.append_metadata(metadata)?;
+    if manifest {
+        writer.append_manifest()?;
+    }
+    let summary = Summary {
+        played,
+        stored: writer.frames(),
+        dictionary: trained.map(|(dictionary, _)| dictionary.len()),
+    };
+    writer.finish()?;
+    Ok(summary)
+}
+
+/// Notes down the repeats of the frame written last, if it had any.
+fn flush(
+    repeats: &mut Vec<Repeat>,
+    last: Option<(Vec<u8>, usize)>,
+    written: usize,
+) {
+    if let Some((_, times)) = last.filter(|(_, times)| *times > 0) {
+        repeats.push(Repeat { frame: written - 1, times });
+    }
+}
+
+/// A dictionary trained on the frames, or `None` (with a warning) if
+/// there's too little to train one on.
+fn train(path: &Path, old: &Dictionary) -> Res<Option<Vec<u8>>> {
+    let mut archive = Archive::new(File::open(path)?);
+    let limit = max_frame_size();
+    let mut samples: Vec<Vec<u8>> = Vec::new();
+    let mut total = 0;
+    // Only every `stride`th frame gets kept, doubling whenever they add
+    // up to too much.
+    let mut stride = 1;
+    let mut index = 0;
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry_name(&entry)?.is_some_and(|name| is_frame(&name)) {
+            continue;
+        }
+        index += 1;
+        if (index - 1) % stride != 0 {
+            continue;
+        }
+        let frame = decompress(entry, old, limit)?;
+        total += frame.len();
+        samples.push(frame);
+        while total > SAMPLE_BYTES && samples.len() > 1 {
+            samples = samples.into_iter().step_by(2).collect();
+            total = samples.iter().map(Vec::len).sum();
+            stride *= 2;
+        }
+    }
+
+    // zstd wants a good hundred times as much to train on as it makes.
+    let size = (total / 100).clamp(1024, DICTIONARY_SIZE);
+    let dictionary = from_samples(&samples, size).ok();
+    if dictionary.is_none() {
+        eprintln!("{DICTIONARY_FAILED}");
+    }
+    Ok(dictionary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{archive::Contents, temp::TempPath};
+
+    /// Played frames, decompressed.
+    fn played(path: &Path) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        Contents::read(path)
+            .unwrap()
+            .for_each_frame(|_, frame| {
+                frames.push(zstd::decode_all(frame)?);
+                Ok(())
+            })
+            .unwrap();
+        frames
+    }
+
+    #[test]
+    fn stores_runs_of_frames_once() {
+        let file = TempPath::new();
+        let mut writer = ArchiveWriter::create(&file).unwrap();
+        let metadata = Metadata { frametime: 40_000, ..Default::default() };
+        writer.append_metadata(&metadata).unwrap();
+        // Compressed at different levels, the same frames still count.
+        for (i, frame) in ["a", "a", "a", "b", "a"].iter().enumerate() {
+            let level = i32::try_from(i).unwrap() + 1;
+            let compressed = zstd::encode_all(frame.as_bytes(), level).unwrap();
+            writer.append_frame(&compressed).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let output = TempPath::new();
+        let options = Options { dedup: true, dictionary: None };
+        let summary = optimize(&file, &output, &options).unwrap();
+
+        assert_eq!((summary.played, summary.stored), (5, 3));
+        assert_eq!(played(&output), played(&file));
+    }
+}
@@ -0,0 +1,40 @@
+// Where bplay keeps its files. The XDG base directory variables win
+// everywhere, for people who set them on purpose, otherwise it's whatever
+// is usual for the platform:
+//
+//              config                      state
+//   Linux      ~/.config/bplay             ~/.local/state/bplay
+//   macOS      ~/Library/Application Support/bplay, for both
+//   Windows    %APPDATA%\bplay             %LOCALAPPDATA%\bplay
+
+use std::{env, path::PathBuf};
+
+/// Settings, like config.ron.
+pub fn config_dir() -> Option<PathBuf> {
+    dir("XDG_CONFIG_HOME", "APPDATA", &[".config"])
+}
+
+/// What bplay keeps track of by itself, like the history.
+pub fn state_dir() -> Option<PathBuf> {
+    dir("XDG_STATE_HOME", "LOCALAPPDATA", &[".local", "state"])
+}
+
+/// `home` is where it goes under the home directory on Linux and such.
+fn dir(xdg: &str, windows: &str, home: &[&str]) -> Option<PathBuf> {
+    let base = var(xdg).or_else(|| {
+        if cfg!(windows) {
+            var(windows)
+        } else if cfg!(target_os = "macos") {
+            Some(var("HOME")?.join("Library").join("Application Support"))
+        } else {
+            Some(home.iter().fold(var("HOME")?, |path, part| path.join(part)))
+        }
+    })?;
+    Some(base.join("bplay"))
+}
+
+/// Unset, empty and relative paths all get ignored, as the XDG spec
+/// says to.
+fn var(name: &str) -> Option<PathBuf> {
+    env::var_os(name).map(PathBuf::from).filter(|path| path.is_absolute())
+}
@@ -0,0 +1,182 @@
+// Playlists are plain text files listing .bapple files, one per line.
+// Relative paths start from the playlist's directory, and lines starting
+// with '#' are comments, so simple M3U files work too.
+//
+// A `#bplay:` line sets options for the item right after it, like
+//     #bplay: fps=24 start=1:30 end=2:00 loop=2
+// which other players just see as a comment.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    hash::{BuildHasher, RandomState},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    Res,
+    http::{is_url, refuse_https},
+    primitives::validate_fps,
+    timeline::parse_time,
+};
+
+const OPTIONS: &str = "#bplay:";
+
+pub struct Item {
+    pub path: PathBuf,
+    pub options: Options,
+}
+
+impl Item {
+    /// The file name without its extension, for showing in a list.
+    pub fn name(&self) -> String {
+        self.path.file_stem().map_or_else(
+            || self.path.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Options {
+    /// Overrides the file's framerate, and the one given on the command
+    /// line.
+    pub fps: Option<f64>,
+    pub start: Duration,
+    pub end: Option<Duration>,
+    /// Times to play the item in a row.
+    pub loops: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { fps: None, start: Duration::ZERO, end: None, loops: 1 }
+    }
+}
+
+impl Options {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut options = Self::default();
+
+        for option in line.split_whitespace() {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {option}"))?;
+            match key {
+                "fps" => options.fps = Some(validate_fps(value)?),
+                "start" => options.start = parse_time(value)?,
+                "end" => options.end = Some(parse_time(value)?),
+                "loop" => {
+                    options.loops =
+                        value.parse().map_err(|e| format!("loop: {e}"))?;
+                }
+                _ => return Err(format!("unknown option {key}")),
+            }
+        }
+
+        if options.end.is_some_and(|end| end <= options.start) {
+            return Err("end has to come after start".to_string());
+        }
+        Ok(options)
+    }
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        ["m3u", "m3u8", "txt"]
+            .iter()
+            .any(|known| extension.eq_ignore_ascii_case(known))
+    })
+}
+
+/// The items of a playlist, or just the file itself if it isn't one.
+pub fn load(path: &Path) -> Res<Vec<Item>> {
+    refuse_https(path)?;
+    if !is_playlist(path) {
+        return Ok(vec![Item {
+            path: path.to_path_buf(),
+            options: Options::default(),
+        }]);
+    }
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut items = Vec::new();
+    let mut options = Options::default();
+
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if let Some(line) = line.strip_prefix(OPTIONS) {
+            options = Options::parse(line).map_err(|e| {
+                format!("{}:{}: {e}", path.display(), number + 1)
+            })?;
+        } else if !line.is_empty() && !line.starts_with('#') {
+            refuse_https(Path::new(line))?;
+            let path = if is_url(Path::new(line)) {
+                line.into()
+            } else {
+                base.join(line)
+            };
+            items.push(Item { path, options });
+            options = Options::default();
+        }
+    }
+
+    if items.is_empty() {
+        return Err(format!("{}: empty playlist", path.display()).into());
+    }
+    Ok(items)
+}
+
+/// Shuffles the playlist, the same way every time for the same seed.
+/// Nothing fancy (splitmix64), it only has to look random.
+pub struct Shuffle {
+    state: u64,
+}
+
+impl Shuffle {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A different seed every run.
+    pub fn random_seed() -> u64 {
+        RandomState::new().hash_one(0)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// The order to play `len` items in on one pass through the playlist.
+/// `last` is whatever played just before, which a shuffled pass won't
+/// start with, so nothing plays twice in a row.
+pub fn pass(
+    len: usize,
+    shuffle: Option<&mut Shuffle>,
+    last: Option<usize>,
+) -> VecDeque<usize> {
+    let mut order = (0..len).collect::<VecDeque<_>>();
+    let Some(shuffle) = shuffle else {
+        return order;
+    };
+
+    // Fisher-Yates
+    for i in (1..len).rev() {
+        order.swap(i, shuffle.below(i + 1));
+    }
+    if len > 1 && order.front() == last.as_ref() {
+        order.swap(0, 1 + shuffle.below(len - 1));
+    }
+    order
+}
@@ -0,0 +1,134 @@
+// Decompresses frames on another thread, a few ahead of the one being
+// drawn, so one slow frame doesn't hold up the drawing. Frames come out
+// in order; jumping somewhere else tells the thread to start over there.
+
+use std::{
+    io,
+    sync::{
+        Arc, Mutex, PoisonError,
+        mpsc::{Receiver, Sender, TryRecvError, channel, sync_channel},
+    },
+    thread,
+};
+
+use tracing::debug;
+
+use crate::{
+    frames::{Dictionary, FrameSource, decompress},
+    priority,
+};
+
+pub struct Prefetch {
+    /// Where to start over from.
+    restarts: Sender<usize>,
+    frames: Receiver<(usize, io::Result<Vec<u8>>)>,
+    /// The index of the frame that comes through next, unless there's a
+    /// restart on the way.
+    next: usize,
+    /// The restart asked for that hasn't come through yet.
+    restarting: Option<usize>,
+    /// The frame given out last, since it can be asked for again while
+    /// waiting on the clock.
+    last: Option<(usize, Vec<u8>)>,
+    depth: usize,
+    /// How many frames weren't ready by the time they were needed.
+    pub stalls: usize,
+}
+
+impl Prefetch {
+    /// Starts decoding at `from`, keeping up to `depth` frames ready.
+    /// Frames are decompressed against the file's dictionary and cut off
+    /// at a maximum size, like everywhere else. With `high_priority`, the
+    /// thread asks to be run first.
+    pub fn start(
+        source: Arc<Mutex<FrameSource>>,
+        from: usize,
+        length: usize,
+        depth: usize,
+        (dictionary, max_size): (Dictionary, usize),
+        high_priority: bool,
+    ) -> Self {
+        let (restarts, requests) = channel();
+        let (sender, frames) = sync_channel(depth);
+        thread::spawn(move || {
+            // Whether it's allowed gets said by the thread drawing.
+            let _raised = high_priority.then(priority::raise);
+            let mut next = from;
+            loop {
+                match requests.try_recv() {
+                    Ok(index) => next = index,
+                    Err(TryRecvError::Disconnected) => return,
+                    Err(TryRecvError::Empty) => {}
+                }
+                // Nothing left, until there's somewhere else to go.
+                if next >= length {
+                    let Ok(index) = requests.recv() else { return };
+                    next = index;
+                    continue;
+                }
+                let frame = source
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(next)
+                    .and_then(|frame| decompress(frame, &dictionary, max_size));
+                if sender.send((next, frame)).is_err() {
+                    return;
+                }
+                next += 1;
+            }
+        });
+        Self {
+            restarts,
+            frames,
+            next: from,
+            restarting: None,
+            last: None,
+            depth,
+            stalls: 0,
+        }
+    }
+
+    /// The decompressed frame at `index`, waiting for it if it isn't
+    /// ready. Frames before it are thrown away.
+    pub fn get(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        if let Some((at, frame)) = &self.last
+            && *at == index
+        {
+            return Ok(frame.clone());
+        }
+        // Too far either way to just skip ahead to it.
+        let jumped = index < self.next || index - self.next > self.depth;
+        if jumped && self.restarting != Some(index) {
+            debug!(from = self.next, to = index, "decoding restarted");
+            self.restarts.send(index).map_err(|_| stopped())?;
+            self.restarting = Some(index);
+        }
+        let mut waited = false;
+        loop {
+            let (at, frame) = match self.frames.try_recv() {
+                Ok(decoded) => decoded,
+                Err(TryRecvError::Empty) => {
+                    waited = true;
+                    self.frames.recv().map_err(|_| stopped())?
+                }
+                Err(TryRecvError::Disconnected) => return Err(stopped()),
+            };
+            self.next = at + 1;
+            if at == index {
+                // Waiting for a restart to get going doesn't count.
+                if waited && self.restarting.is_none() {
+                    debug!(index, "frame wasn't ready");
+                    self.stalls += 1;
+                }
+                self.restarting = None;
+                let frame = frame?;
+                self.last = Some((index, frame.clone()));
+                return Ok(frame);
+            }
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("frame decoding stopped")
+}
@@ -1,235 +1,1554 @@
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{self, Cursor, Read, Write, stdout},
-    path::PathBuf,
-    process::exit,
-    sync::{Arc, atomic::Ordering},
-    thread::{sleep, spawn},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError, atomic::Ordering},
+    thread::sleep,
     time::{Duration, Instant},
 };
 
-use clap::{Parser, crate_version};
-use rodio::{Decoder, OutputStreamBuilder, Sink, Source};
-use ron::de::from_bytes;
-use serde::Deserialize;
-use tar::{Archive, Entry};
-use zstd::decode_all;
+use clap::{Parser, Subcommand, builder::FalseyValueParser, crate_version};
+use clap_complete::Shell;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rodio::{
+    Decoder, OutputStream, OutputStreamBuilder, Sink, Source,
+    source::UniformSourceIterator, stream::OutputStreamConfig,
+};
+use tar::{Archive, Entries, Entry};
+use tracing::{debug, info_span, instrument};
 
 use crate::{
     Res, STOP,
-    backup_counter::{SYNC_COUNTER, outside_counter},
-    messages::FRAMETIME_ZERO,
+    archive::{
+        Limits, entry_name, is_audio, is_dictionary, is_encryption, is_frame,
+        is_metadata, is_poster, read_poster, subtitle_language,
+    },
+    audio::AudioCommand,
+    clock::{Clock, Playhead, SystemClock},
+    concat::ConcatArgs,
+    controls::{Controls, Keyboard, Repeat, Status, draw_help},
+    diff::DiffArgs,
+    encode::EncodeArgs,
+    eq::{Equalizer, Preset},
+    filter::{Pipeline, Settings},
+    frames::{
+        Dictionary, FrameSource, Frames, Loading, Stream, decompress,
+        max_frame_size,
+    },
+    guard::TerminalGuard,
+    http::{Url, is_url},
+    messages::{
+        FRAMETIME_ZERO, INVALID_METADATA, NEWER_FORMAT, NO_CHARSET,
+        NO_PRIORITY, UNSUPPORTED_COLORS,
+    },
+    meta::MetaCommand,
+    metadata::{Episode, Metadata},
+    optimize::OptimizeArgs,
+    prefetch::Prefetch,
+    priority,
+    remote::{self, Opened},
+    remux::RemuxArgs,
+    render::{Crop, Pad, Position, Tint, caption, to_ascii},
+    screen::Screen,
+    serve::ServeArgs,
+    signature::{SignArgs, parse_key},
+    spinner::Spinner,
+    split::SplitArgs,
+    stats::StatsArgs,
+    subtitles::{Choice, Subtitles, Tracks},
+    terminal::{Capabilities, ColorDepth, frame_colors},
+    timeline::{Timeline, format_time},
+    timings::Timings,
+    trim::TrimArgs,
+    visualizer::{self, Tap, Visualizer},
+};
+#[cfg(windows)]
+use crate::{
+    console::ConsoleWriter, guard::enable_virtual_terminal_processing,
 };
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct Bapple {
-    compressed_frames: Vec<Vec<u8>>,
+    /// Shared with `prefetch` while it's decoding.
+    compressed_frames: Arc<Mutex<FrameSource>>,
+    /// What the frames were compressed against, if anything.
+    dictionary: Dictionary,
     audio: Arc<[u8]>, // May be empty
     has_audio: bool,
-    frametime: Duration,
+    timeline: Timeline,
     counter: usize,
     length: usize,
-}
-
-impl Drop for Bapple {
-    fn drop(&mut self) {
-        let _ = show_cursor(&mut stdout().lock());
-    }
+    /// Shown in the terminal title, falls back to the file name.
+    title: String,
+    episodes: Vec<Episode>,
+    /// Only there during playback.
+    terminal: Option<TerminalGuard>,
+    capabilities: Capabilities,
+    /// From the metadata, darkest to brightest.
+    charset: Option<Vec<char>>,
+    /// What `pipeline` gets made from once playback starts, when the
+    /// capabilities are known for sure.
+    filters: Settings,
+    pipeline: Pipeline,
+    /// Whether `pipeline` is made from the filters and capabilities as they
+    /// are, so snapshots don't make it all over again.
+    prepared: bool,
+    subtitles: Tracks,
+    /// Subtitle languages to pick a track by, most wanted first.
+    sub_languages: Vec<String>,
+    /// The metadata format the file was written in.
+    version: u32,
+    /// Used instead of frames when there are none.
+    visualizer: Visualizer,
+    eq: Preset,
+    /// Output sample rate, instead of the device's default.
+    resample: Option<u32>,
+    /// How long to wait after each warning so it can be read,
+    /// `None` if warnings are off.
+    warn_delay: Option<Duration>,
+    precise_timing: bool,
+    /// How many frames to decode ahead of time, none at all with 0.
+    decode_ahead: usize,
+    /// Whether the thread decoding ahead asks to be run first.
+    high_priority: bool,
+    /// How big a frame can decompress to before it's taken for a bomb.
+    max_frame_size: usize,
+    /// Only there during playback, if decoding ahead.
+    prefetch: Option<Prefetch>,
+    /// What's on the terminal, so only what changed gets drawn.
+    screen: Screen,
+    /// Everything drawn for a frame, kept around for the next one.
+    composed: Vec<u8>,
+    /// Over every time it's been played.
+    timings: Timings,
+    /// Where playback starts.
+    start: Duration,
+    /// Where playback stops, instead of the end of the file.
+    end: Option<Duration>,
+    /// When playback starts, once everything's ready, for `--start-at`.
+    start_at: Option<Instant>,
 }
 
 impl Bapple {
-    pub fn new(path: PathBuf) -> Res<Self> {
-        println!("Processing frames...");
+    /// When streaming, frames are only counted here and get read from
+    /// the file while it plays.
+    #[instrument(skip_all, fields(path = %path.display()))]
+    pub fn new(path: &Path, loading: Loading, limits: Limits) -> Res<Self> {
+        let (mut length, mut compressed_frames, others) =
+            Self::read_archive(path, loading, limits)?;
+        let parts: Vec<_> = others
+            .into_par_iter()
+            .map(|(name, content)| Self::process_entry(&name, content))
+            .collect();
 
         let mut audio = Vec::new();
         let mut has_audio = false;
-        let mut frametime = 0;
-
-        let compressed_frames = Archive::new(File::open(path)?)
-            .entries()?
-            .filter_map(|e| {
-                Self::process_frames(
-                    e,
-                    &mut has_audio,
-                    &mut audio,
-                    &mut frametime,
-                )
-            })
-            .collect::<Vec<_>>();
-
-        let length = compressed_frames.len();
+        let mut dictionary = Dictionary::default();
+        let mut metadata = Ok(Metadata::default());
+        let mut subtitles = Vec::new();
+        for part in parts {
+            match part {
+                Part::Audio(content) => {
+                    has_audio = true;
+                    audio = content;
+                }
+                Part::Dictionary(content) => {
+                    dictionary = Dictionary::new(&content);
+                }
+                // No further processing, since this can be
+                // overriden by the FPS arg
+                Part::Metadata(parsed) => metadata = *parsed,
+                Part::Subtitles(language, track) => {
+                    subtitles.push((language, track));
+                }
+                Part::Skipped => {}
+            }
+        }
+
+        // Going on without it, since the framerate can come from the
+        // options instead.
+        let metadata = metadata.unwrap_or_else(|e| {
+            eprintln!("{INVALID_METADATA}    {e}\n");
+            Metadata::default()
+        });
+        metadata.check_requirements()?;
+        if let Some(map) = metadata.frame_map(length, limits)? {
+            length = map.len();
+            compressed_frames.repeat(map);
+        }
+
+        let timeline = Timeline::new(
+            metadata.effective_frametime(),
+            &metadata.frametimes,
+            &metadata.speeds,
+            length,
+        );
+
+        let mut tracks = Tracks::default();
+        for (language, track) in subtitles {
+            let label = metadata.subtitle_label(&language).to_string();
+            tracks.push(language, label, track);
+        }
+
+        let version = metadata.version;
+        let episodes = metadata.episodes;
+        let charset = metadata.charset.map(|charset| charset.chars().collect());
+        let title = metadata.title.unwrap_or_else(|| {
+            path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+        });
 
         Ok(Self {
-            compressed_frames,
+            compressed_frames: Arc::new(Mutex::new(compressed_frames)),
+            dictionary,
             audio: audio.into(),
             has_audio,
-            frametime: Duration::from_micros(frametime),
+            timeline,
             counter: 0,
             length,
+            title,
+            episodes,
+            terminal: None,
+            capabilities: Capabilities::default(),
+            charset,
+            filters: Settings::default(),
+            pipeline: Pipeline::default(),
+            prepared: false,
+            subtitles: tracks,
+            version,
+            sub_languages: Vec::new(),
+            visualizer: Visualizer::default(),
+            eq: Preset::default(),
+            resample: None,
+            warn_delay: Some(Duration::ZERO),
+            precise_timing: false,
+            decode_ahead: 0,
+            high_priority: false,
+            max_frame_size: max_frame_size(),
+            prefetch: None,
+            screen: Screen::default(),
+            composed: Vec::new(),
+            timings: Timings::default(),
+            start: Duration::ZERO,
+            end: None,
+            start_at: None,
         })
     }
 
-    pub fn play(&mut self) -> Res<()> {
-        if self.frametime.is_zero() {
-            eprintln!("{FRAMETIME_ZERO}");
-            exit(1);
+    /// The frames (or just how many there are, when streaming) and every
+    /// other entry still to be processed.
+    #[instrument(skip_all)]
+    fn read_archive(
+        path: &Path,
+        loading: Loading,
+        limits: Limits,
+    ) -> Res<(usize, FrameSource, Others)> {
+        if is_url(path) {
+            let url = Url::parse(&path.to_string_lossy())?;
+            return match remote::open(&url, limits)? {
+                Opened::Indexed(remote, others) => {
+                    Ok((remote.len(), FrameSource::Remote(remote), others))
+                }
+                // With no file to go back to, it can't be streamed.
+                Opened::Whole(response) => {
+                    let mut archive = Archive::new(response);
+                    Self::read_entries(
+                        archive.entries()?,
+                        None,
+                        loading,
+                        limits,
+                    )
+                }
+            };
         }
+        let mut archive = Archive::new(File::open(path)?);
+        if matches!(loading, Loading::Stream) {
+            let entries = archive.entries_with_seek()?;
+            Self::read_entries(entries, Some(path), loading, limits)
+        } else {
+            Self::read_entries(archive.entries()?, None, loading, limits)
+        }
+    }
 
-        #[cfg(target_os = "linux")]
-        if self.has_audio {
-            Self::check_alsa_config();
+    /// Goes through the entries, streaming the frames from `stream` later
+    /// if there is one.
+    fn read_entries<R: Read>(
+        entries: Entries<'_, R>,
+        stream: Option<&Path>,
+        loading: Loading,
+        limits: Limits,
+    ) -> Res<(usize, FrameSource, Others)> {
+        // Reading has to go in archive order, but the rest doesn't, which
+        // adds up with thousands of frames.
+        let low_mem = stream.is_some();
+        let mut frames = Frames::with_budget(loading.budget());
+        let mut streamed = 0;
+        let mut others = Vec::new();
+        let mut read = (0, 0);
+        for entry in entries {
+            let entry = entry?;
+            limits.check(&mut read, entry.size())?;
+            let Some(name) = entry_name(&entry)? else {
+                continue;
+            };
+            // Local ones get decrypted before they get here.
+            if is_encryption(&name) {
+                let why = "is encrypted, download it to play it with \
+                           --password-file";
+                return Err(why.into());
+            }
+            if !is_frame(&name) {
+                others.push((name, Self::read_entry(entry)?));
+            } else if low_mem {
+                streamed += 1;
+            } else {
+                frames.read_from(entry)?;
+            }
+        }
+        let (length, source) = if let Some(path) = stream {
+            (streamed, FrameSource::Stream(Stream::new(path)))
+        } else {
+            frames.shrink_to_fit();
+            if let Some(index) = frames.find_corrupt() {
+                // Better now than halfway through playing it.
+                return Err(format!("frame {index} is corrupt").into());
+            }
+            (frames.len(), FrameSource::Memory(frames))
+        };
+        Ok((length, source, others))
+    }
+
+    /// `output` is opened the first time there's audio to play, and can
+    /// be passed on to the next file so there's no gap between them.
+    pub fn play(
+        &mut self,
+        output: &mut Option<OutputStream>,
+        controls: &mut Controls,
+    ) -> Res<()> {
+        let clock = SystemClock::new(self.precise_timing);
+        let keyboard = Keyboard::new();
+
+        // The TUI draws through crossterm, which handles old consoles
+        // by itself.
+        #[cfg(windows)]
+        if !enable_virtual_terminal_processing() && !controls.is_tui() {
+            let mut w = ConsoleWriter::new()?;
+            return self.play_on(&mut w, &clock, keyboard, output, controls);
         }
 
-        let mut sink = None;
-        let mut total = None;
+        self.play_on(&mut stdout().lock(), &clock, keyboard, output, controls)
+    }
 
-        // Don't drop prematurely, or else the audio won't play.
-        let output_stream = OutputStreamBuilder::open_default_stream()?;
+    /// Starts decoding the frames up to `end` ahead, unless turned off.
+    fn start_prefetch(&self, end: usize) -> Option<Prefetch> {
+        (self.decode_ahead > 0).then(|| {
+            let source = self.compressed_frames.clone();
+            let decoding = (self.dictionary.clone(), self.max_frame_size);
+            Prefetch::start(
+                source,
+                self.counter,
+                end,
+                self.decode_ahead,
+                decoding,
+                self.high_priority,
+            )
+        })
+    }
 
-        if self.has_audio {
-            let decoder = Decoder::new_mp3(Cursor::new(self.audio.clone()))?;
-            let inner_total = decoder.total_duration().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Unable to determine audio duration",
-                )
-            })?;
-            total = Some(inner_total);
-            let source = decoder.track_position();
-
-            let inner_sink = Sink::connect_new(output_stream.mixer());
-            inner_sink.append(source);
-            inner_sink.play();
-            sink = Some(inner_sink);
+    /// Plays to any writer, keeping time with any clock, taking keys from
+    /// `keyboard` if there is one. With a capturing writer and a fake
+    /// clock, this runs as fast as frames decode.
+    pub fn play_on<W: Write, C: Clock>(
+        &mut self,
+        w: &mut W,
+        clock: &C,
+        keyboard: Option<Keyboard>,
+        output: &mut Option<OutputStream>,
+        controls: &mut Controls,
+    ) -> Res<()> {
+        if self.version > Metadata::VERSION {
+            self.warning(NEWER_FORMAT);
+        }
+        if self.length == 0 && self.has_audio {
+            return self.visualize(w, clock, keyboard, output, controls);
+        }
+
+        if self.timeline.is_zero() {
+            return Err(FRAMETIME_ZERO.into());
+        }
+
+        self.check_colors()?;
+        self.prepare_filters();
+
+        // Everything else is ready by now, so all that's left to do
+        // after waiting is to start.
+        if !self.wait_to_start(clock, output)? {
+            return Ok(());
+        }
+
+        let (sink, total) = if self.has_audio {
+            let (sink, total) = self.start_audio(output, !controls.is_tui())?;
+            (Some(sink), Some(total))
         } else {
-            let frametime = self.frametime;
-            let length = self.length;
-            spawn(move || outside_counter(frametime, length));
+            (None, None)
+        };
+
+        self.enter(w, keyboard)?;
+        controls.redraw();
+        self.offer_sub_tracks(controls);
+        let mut title_second = None;
+        // Frames are shown when the playhead gets to them, which follows
+        // the audio if there is any.
+        let mut playhead = Playhead::new(self.start, clock.now());
+        let mut dropped = 0;
+        self.counter = self.timeline.frame_at(self.start);
+        let end = self.end.map_or(self.length, |end| {
+            self.timeline.frame_at(end).min(self.length)
+        });
+        self.prefetch = self.start_prefetch(end);
+        // Only while drawing, once the audio and decoding have started.
+        let raised = self.high_priority.then(priority::raise);
+        if matches!(raised, Some(None)) {
+            self.warning(NO_PRIORITY);
         }
 
-        let mut lock = stdout().lock();
+        while self.counter < end {
+            let _span = info_span!("frame", index = self.counter).entered();
+            controls.update(self.keyboard());
+            if controls.stopping() {
+                break;
+            }
 
-        #[cfg(windows)]
-        enable_virtual_terminal_processing();
+            if controls.help {
+                playhead.pause(self.help(w, clock, controls, sink.as_ref())?);
+            }
 
-        clear(&mut lock)?;
-        hide_cursor(&mut lock)?;
+            if let Some(fraction) = controls.seek.take() {
+                let to = self.timeline.duration().mul_f64(fraction);
+                let frame = self.timeline.frame_at(to);
+                let seeked = match (&sink, total) {
+                    (Some(sink), Some(total)) => {
+                        self.can_seek_to(frame)
+                            && sink.try_seek(total.mul_f64(fraction)).is_ok()
+                    }
+                    _ => self.can_seek_to(frame),
+                };
+                if seeked {
+                    playhead.set(to, clock.now());
+                    self.counter = frame;
+                }
+            }
 
-        while self.counter < self.length {
-            if STOP.load(Ordering::Relaxed) {
+            // More than a frame behind, like when decoding can't keep up,
+            // so it skips ahead to the one that should be up by now.
+            let due = self.timeline.frame_at(playhead.at(clock.now()));
+            if due > self.counter + 1 {
+                debug!(from = self.counter, to = due, "dropped");
+                dropped += due - self.counter;
+                self.counter = due;
+            }
+            if self.counter >= end {
                 break;
             }
 
-            let task_time = Instant::now();
+            let task_time = clock.now();
             let decompressed_frame =
-                decode_all(&*self.compressed_frames[self.counter])?;
-
-            return_home(&mut lock)?;
-            lock.write_all(&decompressed_frame)?;
-            lock.flush()?;
-
-            if !self.counter.is_multiple_of(15) {
-                self.counter += 1;
-            } else if self.has_audio {
-                // Same condition, safe unwrap.
-                self.counter =
-                    self.get_pos(sink.as_ref().unwrap(), total.unwrap());
-            } else {
-                self.backup_resync();
+                self.render_frame(self.counter, controls.subtitles())?;
+
+            let decoded = clock.now();
+            let status = self.status(sink.as_ref(), total, dropped);
+            self.show(
+                w,
+                &decompressed_frame,
+                &status,
+                controls,
+                &mut title_second,
+            )?;
+            let drawn = clock.now().saturating_sub(decoded);
+            self.timings.frame(decoded.saturating_sub(task_time), drawn);
+
+            if let (Some(sink), Some(total)) = (&sink, total) {
+                let audio = self.media_position(sink.get_pos(), total);
+                self.timings.drift(self.timeline.start(self.counter), audio);
+                if playhead.follow(audio, clock.now()) {
+                    debug!(frame = self.counter, "resynced to the audio");
+                }
             }
 
-            if let Some(remaining) =
-                self.frametime.checked_sub(task_time.elapsed())
+            self.counter += 1;
+            let next = self.timeline.start(self.counter);
+            clock.sleep(next.saturating_sub(playhead.at(clock.now())));
+        }
+
+        drop(raised);
+        self.prefetch = None;
+        self.timings.dropped += dropped;
+        self.leave(w)?;
+        Ok(())
+    }
+
+    fn status(
+        &self,
+        sink: Option<&Sink>,
+        total: Option<Duration>,
+        dropped: usize,
+    ) -> Status {
+        let (elapsed, total) = match (sink, total) {
+            (Some(sink), Some(total)) => (sink.get_pos(), total),
+            _ => (self.timeline.start(self.counter), self.timeline.duration()),
+        };
+        Status {
+            elapsed,
+            total,
+            frame: Some((self.counter, self.length)),
+            dropped,
+            stalls: self.prefetch.as_ref().map_or(0, |p| p.stalls),
+        }
+    }
+
+    /// Starts playing the audio, returning the sink and how long it is.
+    #[instrument(skip_all)]
+    fn start_audio(
+        &self,
+        output: &mut Option<OutputStream>,
+        spin: bool,
+    ) -> Res<(Sink, Duration)> {
+        let output_stream = self.output(output)?;
+        let _spinner = spin.then(|| self.spinner("Decoding audio"));
+        let source = self.audio_source(output_stream.config())?;
+        let total = source.total_duration().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unable to determine audio duration",
+            )
+        })?;
+        let source = source.track_position();
+
+        let sink = Sink::connect_new(output_stream.mixer());
+        sink.append(source);
+        sink.play();
+        if !self.start.is_zero() {
+            sink.try_seek(self.start)?;
+        }
+        debug!(total = ?total, "audio started");
+        Ok((sink, total))
+    }
+
+    /// Streamed frames can only go forwards.
+    fn can_seek_to(&self, frame: usize) -> bool {
+        frame >= self.counter || self.frames().seeks_back()
+    }
+
+    fn frames(&self) -> MutexGuard<'_, FrameSource> {
+        self.compressed_frames.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Decompresses a frame and gets it ready for the terminal.
+    #[instrument(name = "decode", skip_all)]
+    fn render_frame(
+        &mut self,
+        index: usize,
+        subtitles: Option<Choice>,
+    ) -> Res<Vec<u8>> {
+        let frame = match &mut self.prefetch {
+            Some(prefetch) => prefetch.get(index)?,
+            None => decompress(
+                self.frames().get(index)?,
+                &self.dictionary,
+                self.max_frame_size,
+            )?,
+        };
+        let frame = self.pipeline.apply(frame);
+        Ok(self.subtitle(frame, self.timeline.start(index), subtitles))
+    }
+
+    /// Writes whatever subtitles are on at `position` over the frame, from
+    /// the chosen track, or none at all if there's no choice. They go on
+    /// after the filters, which would only mess them up.
+    fn subtitle(
+        &self,
+        frame: Vec<u8>,
+        position: Duration,
+        choice: Option<Choice>,
+    ) -> Vec<u8> {
+        let Some(Choice { track, delay: sub_delay }) = choice else {
+            return frame;
+        };
+        let Some(subtitles) = self.subtitles.get(track) else {
+            return frame;
+        };
+        let delay = Duration::from_millis(sub_delay.unsigned_abs());
+        let position = if sub_delay >= 0 {
+            position.saturating_sub(delay)
+        } else {
+            position + delay
+        };
+        let mut lines = subtitles.at(position);
+        if lines.is_empty() {
+            return frame;
+        }
+        if !self.capabilities.unicode {
+            for line in &mut lines {
+                *line = String::from_utf8_lossy(&to_ascii(line.as_bytes()))
+                    .into_owned();
+            }
+        }
+        caption(&frame, &lines)
+    }
+
+    /// One frame exactly as it would be drawn, without subtitles.
+    pub fn snapshot(&mut self, index: usize) -> Res<Vec<u8>> {
+        if index >= self.length {
+            let length = self.length;
+            return Err(
+                format!("There's no frame {index}, only {length}").into()
+            );
+        }
+        if !self.prepared {
+            self.prepare_filters();
+        }
+        self.render_frame(index, None)
+    }
+
+    /// Writes every frame to its own file in `dir`, exactly as it would
+    /// have been drawn.
+    pub fn render_to_dir(&mut self, dir: &Path, subtitles: Choice) -> Res<()> {
+        if self.length == 0 {
+            return Err("There are no frames to render".into());
+        }
+
+        self.prepare_filters();
+        let preferred =
+            self.subtitles.preferred(&self.sub_languages).map(str::to_string);
+        let subtitles = Choice {
+            track: preferred.as_deref().or(subtitles.track),
+            ..subtitles
+        };
+        fs::create_dir_all(dir)?;
+        for index in 0..self.length {
+            let path = dir.join(format!("{index:06}.txt"));
+            fs::write(path, self.render_frame(index, Some(subtitles))?)?;
+        }
+
+        println!("Wrote {} frames to {}", self.length, dir.display());
+        Ok(())
+    }
+
+    /// Waits for `--start-at`, if there is one, with the audio device
+    /// already open. `false` if it got stopped instead.
+    fn wait_to_start<C: Clock>(
+        &mut self,
+        clock: &C,
+        output: &mut Option<OutputStream>,
+    ) -> Res<bool> {
+        let Some(at) = self.start_at.take() else {
+            return Ok(true);
+        };
+        if self.has_audio {
+            self.output(output)?;
+        }
+        // From here on it's the clock that says how long's left.
+        let at = clock.now() + at.saturating_duration_since(Instant::now());
+        wait_until(at, clock);
+        Ok(!STOP.load(Ordering::Relaxed))
+    }
+
+    /// Plays the audio with a visualizer, for archives without frames.
+    fn visualize<W: Write, C: Clock>(
+        &mut self,
+        w: &mut W,
+        clock: &C,
+        keyboard: Option<Keyboard>,
+        output: &mut Option<OutputStream>,
+        controls: &mut Controls,
+    ) -> Res<()> {
+        let output_stream = self.output(output)?;
+        let spinner =
+            (!controls.is_tui()).then(|| self.spinner("Decoding audio"));
+        let source = self.audio_source(output_stream.config())?;
+        let total = source.total_duration().unwrap_or_default();
+        let tap = Tap::new(source);
+        let sample_rate = tap.sample_rate();
+        let samples = tap.samples();
+
+        let sink = Sink::connect_new(output_stream.mixer());
+        sink.append(tap);
+        sink.play();
+        if !self.start.is_zero() {
+            sink.try_seek(self.start)?;
+        }
+        drop(spinner);
+
+        self.enter(w, keyboard)?;
+        controls.redraw();
+        self.offer_sub_tracks(controls);
+        let mut title_second = None;
+
+        while !sink.empty() && self.end.is_none_or(|end| sink.get_pos() < end) {
+            controls.update(self.keyboard());
+            if controls.stopping() {
+                break;
+            }
+            if controls.help {
+                self.help(w, clock, controls, Some(&sink))?;
+            }
+            if let Some(fraction) = controls.seek.take() {
+                let _ = sink.try_seek(total.mul_f64(fraction));
+            }
+
+            let task_time = clock.now();
+
+            let mut frame = Vec::new();
+            visualizer::draw(
+                &mut frame,
+                self.visualizer,
+                &samples,
+                sample_rate,
+            )?;
+            if !self.capabilities.unicode {
+                frame = to_ascii(&frame);
+            }
+            let elapsed = sink.get_pos();
+            let frame = self.subtitle(frame, elapsed, controls.subtitles());
+            let status =
+                Status { elapsed, total, frame: None, dropped: 0, stalls: 0 };
+            self.show(w, &frame, &status, controls, &mut title_second)?;
+
+            if let Some(remaining) = visualizer::FRAMETIME
+                .checked_sub(clock.now().saturating_sub(task_time))
             {
-                sleep(remaining);
+                clock.sleep(remaining);
             }
         }
 
-        show_cursor(&mut lock)?;
-        self.counter = 0;
-        SYNC_COUNTER.store(0, Ordering::Relaxed);
+        self.leave(w)?;
         Ok(())
     }
 
-    #[allow(
-        clippy::cast_possible_truncation,
-        clippy::cast_sign_loss,
-        clippy::cast_precision_loss
-    )]
-    fn get_pos(&self, sink: &Sink, total: Duration) -> usize {
-        (sink.get_pos().div_duration_f64(total) * self.length as f64).round()
-            as usize
+    /// Puts a frame on screen, along with the OSD or the TUI around it.
+    /// Puts everything together in `composed` first, so the terminal
+    /// gets the whole thing in one write and never sees half a frame.
+    #[instrument(name = "draw", skip_all)]
+    fn show<W: Write>(
+        &mut self,
+        w: &mut W,
+        frame: &[u8],
+        status: &Status,
+        controls: &mut Controls,
+        title_second: &mut Option<u64>,
+    ) -> io::Result<()> {
+        let mut composed = mem::take(&mut self.composed);
+        composed.clear();
+        self.update_title(&mut composed, status.elapsed, title_second)?;
+        if self.capabilities.sync_output {
+            begin_sync(&mut composed)?;
+        }
+        if controls.is_tui() {
+            // Anything so far has to go out before the TUI's own output
+            // does.
+            w.write_all(&composed)?;
+            w.flush()?;
+            composed.clear();
+            controls.draw_tui(&self.title, frame, status)?;
+        } else {
+            self.screen.draw(&mut composed, frame)?;
+            controls.draw(&mut composed, status)?;
+            for rows in controls.drawn_over() {
+                self.screen.damage(rows.clone());
+            }
+        }
+        if self.capabilities.sync_output {
+            end_sync(&mut composed)?;
+        }
+        w.write_all(&composed)?;
+        self.composed = composed;
+        w.flush()
+    }
+
+    /// Pauses to show the keys until one gets pressed, returning how long
+    /// that took.
+    fn help<W: Write, C: Clock>(
+        &mut self,
+        w: &mut W,
+        clock: &C,
+        controls: &mut Controls,
+        sink: Option<&Sink>,
+    ) -> io::Result<Duration> {
+        controls.help = false;
+        let Some(keyboard) = self.keyboard() else {
+            return Ok(Duration::ZERO);
+        };
+
+        let paused_at = clock.now();
+        if let Some(sink) = sink {
+            sink.pause();
+        }
+
+        draw_help(w)?;
+        w.flush()?;
+        keyboard.wait_for_key();
+        // Whatever the next frame doesn't cover.
+        clear(w)?;
+        controls.redraw();
+        self.screen.invalidate();
+
+        if let Some(sink) = sink {
+            sink.play();
+        }
+        Ok(clock.now().saturating_sub(paused_at))
+    }
+
+    /// Gets the terminal ready for drawing, undone by [`Self::leave`],
+    /// or by the guard being dropped if playback never gets there.
+    fn enter<W: Write>(
+        &mut self,
+        w: &mut W,
+        keyboard: Option<Keyboard>,
+    ) -> io::Result<()> {
+        let alt_screen = self.capabilities.alt_screen;
+        let guard = TerminalGuard::enter(w, alt_screen, keyboard)?;
+        self.terminal = Some(guard);
+        clear(w)?;
+        self.screen.invalidate();
+        Ok(())
+    }
+
+    fn leave<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        match self.terminal.take() {
+            Some(guard) => guard.leave(w),
+            None => Ok(()),
+        }
+    }
+
+    fn keyboard(&self) -> Option<&Keyboard> {
+        self.terminal.as_ref()?.keyboard.as_ref()
+    }
+
+    /// Warns about frames using more colors than the terminal has.
+    /// Without any colors, they're stripped instead.
+    fn check_colors(&mut self) -> Res<()> {
+        let colors = self.capabilities.color;
+        if self.length == 0 || colors == ColorDepth::None {
+            return Ok(());
+        }
+        let first = decompress(
+            self.frames().get(0)?,
+            &self.dictionary,
+            self.max_frame_size,
+        )?;
+        if frame_colors(&first) > colors {
+            self.warning(UNSUPPORTED_COLORS);
+        }
+        Ok(())
+    }
+
+    /// Sets up the filters, warning if they're meant to move characters
+    /// along a charset the file doesn't have.
+    fn prepare_filters(&mut self) {
+        if self.filters.remaps() && self.charset.is_none() {
+            self.warning(NO_CHARSET);
+        }
+        self.pipeline =
+            self.filters.pipeline(self.capabilities, self.charset.as_deref());
+        self.prepared = true;
+    }
+
+    /// The already open output, or a freshly opened one.
+    fn output<'a>(
+        &self,
+        output: &'a mut Option<OutputStream>,
+    ) -> Res<&'a OutputStream> {
+        if output.is_none() {
+            self.check_output_device();
+            *output = Some(self.open_output()?);
+        }
+        Ok(output.as_ref().expect("just opened"))
+    }
+
+    /// Opens the default device, at the requested sample rate if it
+    /// supports it.
+    #[instrument(skip_all)]
+    fn open_output(&self) -> Res<OutputStream> {
+        Ok(match self.resample {
+            Some(rate) => OutputStreamBuilder::from_default_device()?
+                .with_sample_rate(rate)
+                .open_stream_or_fallback()?,
+            None => OutputStreamBuilder::open_default_stream()?,
+        })
+    }
+
+    /// The decoded audio, resampled to whatever the device ended up
+    /// running at. Some backends crackle when left to do it themselves.
+    #[instrument(name = "decode_audio", skip_all)]
+    fn audio_source(
+        &self,
+        config: &OutputStreamConfig,
+    ) -> Res<impl Source + Send + use<>> {
+        // Seeking needs to know how long the data is.
+        let decoder = Decoder::builder()
+            .with_data(Cursor::new(self.audio.clone()))
+            .with_byte_len(self.audio.len() as u64)
+            .with_hint("mp3")
+            .build()?;
+        Ok(UniformSourceIterator::new(
+            Equalizer::new(decoder, self.eq),
+            config.channel_count(),
+            config.sample_rate(),
+        ))
+    }
+
+    /// Started after the output is open, so warnings about it don't end
+    /// up behind the spinner.
+    fn spinner(&self, phase: &str) -> Spinner {
+        Spinner::start(phase, self.capabilities.unicode)
+    }
+
+    /// No need to update the title more than once a second.
+    fn update_title<W: Write>(
+        &self,
+        w: &mut W,
+        elapsed: Duration,
+        last_second: &mut Option<u64>,
+    ) -> io::Result<()> {
+        if *last_second == Some(elapsed.as_secs()) {
+            return Ok(());
+        }
+        *last_second = Some(elapsed.as_secs());
+        let time = format_time(elapsed);
+        set_title(w, &format!("{} [{time}]", self.title))
+    }
+
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Where in the file the last frame drawn was.
+    pub fn position(&self) -> Duration {
+        self.timeline.start(self.counter)
+    }
+
+    /// How many frames get played.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Where in the frames `position` in the audio is, `total` being how
+    /// long the audio is.
+    fn media_position(&self, position: Duration, total: Duration) -> Duration {
+        let progress = position.div_duration_f64(total);
+        self.timeline.duration().mul_f64(progress)
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     pub fn set_frametime(&mut self, frametime: f64) {
-        self.frametime = Duration::from_micros(frametime as u64);
+        self.timeline.set_frametime(Duration::from_micros(frametime as u64));
+    }
+
+    /// Plays only part of the file, from `start` to `end` (or the end).
+    pub fn set_range(&mut self, start: Duration, end: Option<Duration>) {
+        self.start = start;
+        self.end = end;
+    }
+
+    /// Plays from frame `start` up to, but not including, `end`, instead
+    /// of between two points in time. Has to come after any change to the
+    /// frametime.
+    pub fn set_frames(&mut self, start: usize, end: Option<usize>) -> Res<()> {
+        if start >= self.length {
+            return Err(format!(
+                "Can't start at frame {start}, the last one is {}",
+                self.length.saturating_sub(1)
+            )
+            .into());
+        }
+        if end.is_some_and(|end| end > self.length) {
+            return Err(format!(
+                "Can't stop at frame {}, there are only {}",
+                end.unwrap_or_default(),
+                self.length
+            )
+            .into());
+        }
+
+        self.start = self.timeline.start(start);
+        self.end = end.map(|end| self.timeline.start(end));
+        Ok(())
+    }
+
+    /// Plays just one of the file's episodes, by its number (from 1) or
+    /// its title. Has to come after any change to the frametime.
+    pub fn set_episode(&mut self, which: &str) -> Res<()> {
+        let found = match which.parse::<usize>() {
+            Ok(number) => {
+                number.checked_sub(1).and_then(|i| self.episodes.get(i))
+            }
+            Err(_) => self
+                .episodes
+                .iter()
+                .find(|episode| episode.title.eq_ignore_ascii_case(which)),
+        };
+        let Some(episode) = found.cloned() else {
+            return Err(format!("{} has no episode {which}", self.title).into());
+        };
+        self.set_frames(episode.start, Some(episode.end))?;
+        self.title = format!("{} - {}", self.title, episode.title);
+        Ok(())
+    }
+
+    pub fn set_visualizer(&mut self, visualizer: Visualizer) {
+        self.visualizer = visualizer;
+    }
+
+    pub fn set_eq(&mut self, eq: Preset) {
+        self.eq = eq;
+    }
+
+    pub fn set_resample(&mut self, rate: Option<u32>) {
+        self.resample = rate;
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+        self.prepared = false;
+    }
+
+    pub fn set_filters(&mut self, filters: Settings) {
+        self.filters = filters;
+        self.prepared = false;
+    }
+
+    /// Adds a track from outside the file, shown unless another one
+    /// gets picked.
+    pub fn add_subtitles(&mut self, name: String, subtitles: Subtitles) {
+        self.subtitles.push_front(name.clone(), name, subtitles);
+    }
+
+    pub fn set_sub_languages(&mut self, languages: Vec<String>) {
+        self.sub_languages = languages;
+    }
+
+    /// Tells the controls which subtitle tracks there are, and picks one
+    /// by language if any were asked for.
+    fn offer_sub_tracks(&self, controls: &mut Controls) {
+        controls.sub_tracks = self.subtitles.names();
+        if let Some(track) = self.subtitles.preferred(&self.sub_languages) {
+            controls.sub_track = Some(track.to_string());
+        }
+    }
+
+    pub fn set_precise_timing(&mut self, precise: bool) {
+        self.precise_timing = precise;
+    }
+
+    pub fn set_decode_ahead(&mut self, frames: usize) {
+        self.decode_ahead = frames;
+    }
+
+    pub fn set_high_priority(&mut self, high: bool) {
+        self.high_priority = high;
+    }
+
+    pub fn set_max_frame_size(&mut self, bytes: usize) {
+        self.max_frame_size = bytes;
+    }
+
+    pub fn set_warnings(&mut self, delay: Option<Duration>) {
+        self.warn_delay = delay;
     }
 
-    pub fn backup_resync(&mut self) {
-        self.counter = SYNC_COUNTER.load(Ordering::Relaxed);
+    /// Only for the next time it's played.
+    pub fn set_start_at(&mut self, at: Option<Instant>) {
+        self.start_at = at;
     }
 
-    #[cfg(target_os = "linux")]
-    fn check_alsa_config() {
-        use crate::messages::ALSA_WARNING;
-        use std::{path::Path, thread::sleep, time::Duration};
+    /// Warns if there's no output device that would actually play
+    /// anything. Playback goes on regardless, in case the probe is wrong.
+    fn check_output_device(&self) {
+        use crate::messages::NO_OUTPUT_DEVICE;
+        use rodio::cpal::traits::{DeviceTrait, HostTrait};
+
+        let usable = rodio::cpal::default_host()
+            .default_output_device()
+            .is_some_and(|device| device.default_output_config().is_ok());
 
-        if !Path::new("/etc/alsa/conf.d").exists() {
-            eprintln!("{ALSA_WARNING}");
-            sleep(Duration::from_secs(5));
+        if !usable {
+            self.warning(NO_OUTPUT_DEVICE);
         }
     }
 
-    fn process_frames(
-        entry: Result<Entry<'_, File>, io::Error>,
-        has_audio: &mut bool,
-        audio: &mut Vec<u8>,
-        outer_frametime: &mut u64,
-    ) -> Option<Vec<u8>> {
-        let mut entry = entry.ok()?;
-        let file_stem = entry.header().path().ok()?.file_stem()?.to_os_string();
+    fn warning(&self, message: &str) {
+        if let Some(delay) = self.warn_delay {
+            eprintln!("{message}");
+            sleep(delay);
+        }
+    }
 
+    fn read_entry<R: Read>(mut entry: Entry<'_, R>) -> io::Result<Vec<u8>> {
         let mut content = Vec::new();
-        entry.read_to_end(&mut content).ok()?;
-
-        if file_stem == *"audio" {
-            *has_audio = true;
-            *audio = content;
-
-            return None;
-        } else if file_stem == *"metadata" {
-            let Metadata { frametime, fps } =
-                from_bytes(&content).unwrap_or_default();
-            if frametime != 0 {
-                *outer_frametime = frametime;
-            } else if fps != 0 {
-                // DEPRECATED
-                *outer_frametime = 1_000_000 / fps;
+        entry.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// Anything but frames, on any thread, in any order.
+    fn process_entry(name: &Path, content: Vec<u8>) -> Part {
+        if is_poster(name) {
+            Part::Skipped
+        } else if let Some(language) = subtitle_language(name) {
+            let track = Subtitles::parse(&String::from_utf8_lossy(&content));
+            if track.is_empty() {
+                Part::Skipped
+            } else {
+                Part::Subtitles(language.to_string(), track)
             }
-            // No further processing, since this can be
-            // overriden by the FPS arg
-            return None;
+        } else if is_audio(name) {
+            Part::Audio(content)
+        } else if is_metadata(name) {
+            Part::Metadata(Box::new(Metadata::parse(name, &content)))
+        } else if is_dictionary(name) {
+            Part::Dictionary(content)
+        } else {
+            Part::Skipped
         }
-
-        Some(content)
     }
 }
 
+/// What an archive entry other than a frame turned out to be.
+enum Part {
+    Audio(Vec<u8>),
+    Metadata(Box<Result<Metadata, String>>),
+    Dictionary(Vec<u8>),
+    Subtitles(String, Subtitles),
+    /// The poster, which is only needed before loading is done, and
+    /// anything else without a use.
+    Skipped,
+}
+
+/// Every entry that isn't a frame, named, with what's in it.
+type Others = Vec<(PathBuf, Vec<u8>)>;
+
 /// Asciix on cocaine
 #[derive(Parser, Debug)]
-#[command(version(crate_version!()))]
+#[allow(clippy::struct_excessive_bools)]
+#[command(
+    version(crate_version!()),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
-    /// Path to a .bapple file.
-    pub file: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Path to a .bapple file, an http:// URL of one, or a playlist (.m3u
+    /// or .txt) of them. Without one, a file browser opens.
+    pub file: Option<PathBuf>,
     /// Should be self-explanatory.
-    #[arg(default_value = "0", value_parser = validate_fps)]
+    #[arg(default_value = "0", env = "BAPPLE_FPS", value_parser = validate_fps)]
     pub frames_per_second: f64,
-    /// Enables looping
+    /// List the files played lately and exit
+    #[arg(long, conflicts_with_all = ["file", "browse"])]
+    pub history: bool,
+    /// Pick a file to play from a directory [default: the current one]
+    #[arg(
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        default_missing_value = ".",
+        conflicts_with = "file"
+    )]
+    pub browse: Option<PathBuf>,
+    /// What to play again once it's over, `r` cycles through these
+    #[arg(long, value_enum, default_value_t, env = "BAPPLE_REPEAT")]
+    pub repeat: Repeat,
+    /// Same as --repeat all
     #[arg(short, long)]
     pub r#loop: bool,
+    /// Play the playlist in random order, shuffled again on every loop
+    #[arg(long, env = "BAPPLE_SHUFFLE", value_parser = FalseyValueParser::new())]
+    pub shuffle: bool,
+    /// Seed for --shuffle, to get the same order again
+    #[arg(long, requires = "shuffle")]
+    pub seed: Option<u64>,
+    /// What to draw for files that only have audio
+    #[arg(long, value_enum, default_value_t, env = "BAPPLE_VISUALIZER")]
+    pub visualizer: Visualizer,
+    /// Equalizer preset for the audio
+    #[arg(long, value_enum, default_value_t, env = "BAPPLE_EQ")]
+    pub eq: Preset,
+    /// Output sample rate in Hz [default: the device's]
+    #[arg(
+        long,
+        value_name = "RATE",
+        env = "BAPPLE_RESAMPLE",
+        value_parser = clap::value_parser!(u32).range(8000..=384_000)
+    )]
+    pub resample: Option<u32>,
+    /// Don't print warnings before playback
+    #[arg(long, env = "BAPPLE_NO_WARN", value_parser = FalseyValueParser::new())]
+    pub no_warn: bool,
+    /// Seconds to wait after each warning, to give you time to read it
+    #[arg(
+        long,
+        default_value = "0",
+        env = "BAPPLE_WARN_DELAY",
+        value_parser = validate_delay
+    )]
+    pub warn_delay: Duration,
+    /// Spin instead of sleeping near the end of each frame, for smoother
+    /// pacing at high framerates (uses more CPU)
+    #[arg(
+        long,
+        env = "BAPPLE_PRECISE_TIMING",
+        value_parser = FalseyValueParser::new()
+    )]
+    pub precise_timing: bool,
+    /// Stream frames from the file while playing instead of loading them
+    /// all first. Frames have to be stored in order, and you can't seek
+    /// backwards
+    #[arg(long, env = "BAPPLE_LOW_MEM", value_parser = FalseyValueParser::new())]
+    pub low_mem: bool,
+    /// Ask the OS to run the threads drawing and decoding frames first
+    /// (realtime scheduling where allowed), for fewer dropped frames on a
+    /// busy system
+    #[arg(
+        long,
+        env = "BAPPLE_HIGH_PRIORITY",
+        value_parser = FalseyValueParser::new()
+    )]
+    pub high_priority: bool,
+    /// How many frames to decompress ahead of the one on screen, more
+    /// helps slow CPUs keep up at the cost of memory. 0 decompresses each
+    /// one just as it's drawn
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 4,
+        env = "BAPPLE_DECODE_AHEAD"
+    )]
+    pub decode_ahead: usize,
+    /// Print how playback went once it's over: frames shown and dropped,
+    /// how long decoding and drawing took, and how far off the audio the
+    /// frames were
+    #[arg(long)]
+    pub timings: bool,
+    /// Write the --timings report to a file as JSON instead
+    #[arg(long, value_name = "FILE")]
+    pub timings_json: Option<PathBuf>,
+    /// Record where the time goes (loading, every frame, syncing to the
+    /// audio) as a Chrome trace, to open in Perfetto
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+    /// Keep at most this many megabytes of frames in memory, the rest
+    /// goes to a temporary file
+    #[arg(
+        long,
+        value_name = "MB",
+        env = "BAPPLE_MAX_MEM",
+        conflicts_with = "low_mem",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_mem: Option<u64>,
+    /// Refuse frames that decompress to more than this many megabytes.
+    /// By default it's worked out from the terminal's size
+    #[arg(
+        long,
+        value_name = "MB",
+        env = "BAPPLE_MAX_FRAME_SIZE",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_frame_size: Option<u64>,
+    /// Refuse archives with more entries than this
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = Limits::default().entries,
+        env = "BAPPLE_MAX_ENTRIES"
+    )]
+    pub max_entries: usize,
+    /// Refuse archives holding more than this many megabytes, compressed
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = Limits::default().bytes / (1024 * 1024),
+        env = "BAPPLE_MAX_ARCHIVE_SIZE",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_archive_size: u64,
+    /// Check files against the hashes stored in them before playing, and
+    /// refuse damaged ones
+    #[arg(long, env = "BAPPLE_VERIFY", value_parser = FalseyValueParser::new())]
+    pub verify: bool,
+    /// Only play files signed by one of the --trusted-key keys, checking
+    /// them like --verify does
+    #[arg(
+        long,
+        requires = "trusted_key",
+        env = "BAPPLE_REQUIRE_SIGNED",
+        value_parser = FalseyValueParser::new()
+    )]
+    pub require_signed: bool,
+    /// A public key to trust the signatures of, in hex or as the .pub file
+    /// `bplay keygen` wrote. Can be given more than once
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    pub trusted_key: Vec<[u8; 32]>,
+    /// A file with the password to encrypted files in it, which get
+    /// decrypted to a temporary file before playing
+    #[arg(long, value_name = "FILE", env = "BAPPLE_PASSWORD_FILE")]
+    pub password_file: Option<PathBuf>,
+    /// Play inside a full-screen layout with a progress gauge, the
+    /// playlist and a status bar
+    #[arg(long, env = "BAPPLE_TUI", value_parser = FalseyValueParser::new())]
+    pub tui: bool,
+    /// Strip colors from frames, also done when `NO_COLOR` is set
+    #[arg(
+        long,
+        env = "BAPPLE_NO_COLOR",
+        value_parser = FalseyValueParser::new()
+    )]
+    pub no_color: bool,
+    /// Color frames that don't have colors of their own: green, amber,
+    /// white, red, yellow, blue, magenta, cyan, gray, #rrggbb or r,g,b
+    #[arg(
+        long,
+        value_name = "COLOR",
+        env = "BAPPLE_TINT",
+        value_parser = validate_tint
+    )]
+    pub tint: Option<Tint>,
+    /// Swap dark and bright characters, for dark-on-light terminals.
+    /// Needs the file to say which characters it's drawn with
+    #[arg(long, env = "BAPPLE_INVERT", value_parser = FalseyValueParser::new())]
+    pub invert: bool,
+    /// Brighten (up to 1) or darken (down to -1) frames. Needs the file to
+    /// say which characters it's drawn with, like --invert
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        env = "BAPPLE_BRIGHTNESS",
+        value_parser = validate_brightness
+    )]
+    pub brightness: f32,
+    /// Above 1 for more contrast, below 1 for less. Needs the file to
+    /// say which characters it's drawn with, like --invert
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        allow_negative_numbers = true,
+        env = "BAPPLE_CONTRAST",
+        value_parser = validate_contrast
+    )]
+    pub contrast: f32,
+    /// Draw with these characters instead of the file's own, from darkest
+    /// to brightest, to suit your font. Needs the file to say which
+    /// characters it's drawn with, like --invert
+    #[arg(
+        long,
+        value_name = "CHARS",
+        env = "BAPPLE_CHARSET",
+        value_parser = validate_charset
+    )]
+    pub charset: Option<String>,
+    /// Only show part of each frame, so many columns by so many rows,
+    /// like 80x24, optionally from where it starts, like 80x24+10+5
+    #[arg(long, value_name = "WxH+X+Y", value_parser = validate_crop)]
+    pub crop: Option<Crop>,
+    /// Blank rows above frames and columns to their left, either N for
+    /// both or ROWS,COLUMNS
+    #[arg(long, value_name = "N", value_parser = validate_pad)]
+    pub pad: Option<Pad>,
+    /// Subtitles to show at the bottom of the video, from an SRT file
+    #[arg(long, value_name = "FILE")]
+    pub subs: Option<PathBuf>,
+    /// Show subtitles this many milliseconds later, or earlier if it's
+    /// negative. `z` and `x` change it during playback
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0,
+        allow_negative_numbers = true,
+        env = "BAPPLE_SUB_DELAY"
+    )]
+    pub sub_delay: i64,
+    /// Which subtitle track to start with, by language, when the file has
+    /// more than one. `j` goes through them during playback
+    #[arg(long, value_name = "LANG", env = "BAPPLE_SUB_TRACK")]
+    pub sub_track: Option<String>,
+    /// Subtitle languages to look for, most wanted first, like en,ja.
+    /// Picks a track in every file that has one
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        conflicts_with = "sub_track",
+        env = "BAPPLE_SLANG"
+    )]
+    pub slang: Vec<String>,
+    /// Text to write over every frame, like a name when recording
+    #[arg(long, value_name = "TEXT", env = "BAPPLE_OVERLAY_TEXT")]
+    pub overlay_text: Option<String>,
+    /// Where --overlay-text goes
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        env = "BAPPLE_OVERLAY_POSITION",
+        requires = "overlay_text"
+    )]
+    pub overlay_position: Position,
+    /// Show the time in a corner of the video [default: top-right]
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POSITION",
+        num_args = 0..=1,
+        default_missing_value = "top-right",
+        env = "BAPPLE_CLOCK"
+    )]
+    pub clock: Option<Position>,
+    /// Swap block and braille characters for plain ASCII, for fonts
+    /// that don't have them
+    #[arg(long, env = "BAPPLE_ASCII", value_parser = FalseyValueParser::new())]
+    pub ascii: bool,
+    /// Frame to start playing at, counting from 0
+    #[arg(long, value_name = "N")]
+    pub start_frame: Option<usize>,
+    /// Only play these frames, like 300..450 (the end isn't included)
+    #[arg(
+        long,
+        value_name = "A..B",
+        value_parser = validate_frames,
+        conflicts_with = "start_frame"
+    )]
+    pub frames: Option<FrameRange>,
+    /// Only play this episode of a file that has several, by its number
+    /// (from 1) or its title
+    #[arg(
+        long,
+        value_name = "N|NAME",
+        conflicts_with_all = ["frames", "start_frame"]
+    )]
+    pub episode: Option<String>,
+    /// Once no key has been pressed for --idle, play random .bapple files
+    /// from this directory until one is
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["file", "browse", "history", "render_to_dir"]
+    )]
+    pub screensaver: Option<PathBuf>,
+    /// Seconds without a key press before --screensaver starts. With 0 it
+    /// starts right away and exits once a key is pressed, to be tmux's
+    /// lock-command
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "300",
+        requires = "screensaver",
+        value_parser = validate_delay
+    )]
+    pub idle: Duration,
+    /// Load the file, then start playing it at this time of day, like
+    /// 21:00:00, so people who started it early still start together.
+    /// Tomorrow if it's already gone by today
+    #[arg(
+        long,
+        value_name = "HH:MM[:SS]",
+        conflicts_with = "render_to_dir",
+        value_parser = validate_start_at
+    )]
+    pub start_at: Option<Duration>,
+    /// Write each frame to a numbered file in this directory instead of
+    /// playing, for debugging
+    #[arg(long, value_name = "DIR")]
+    pub render_to_dir: Option<PathBuf>,
+}
+
+/// Frames from `start` up to `end`, or the last one.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a .bapple file
+    Encode(Box<EncodeArgs>),
+    /// Join .bapple files end to end into one
+    Concat(ConcatArgs),
+    /// Cut a .bapple file into pieces that play on their own
+    Split(SplitArgs),
+    /// Keep only part of a .bapple file
+    Trim(TrimArgs),
+    /// Swap the audio of a .bapple file for another track
+    Remux(RemuxArgs),
+    /// Get at the audio of a .bapple file
+    #[command(subcommand)]
+    Audio(AudioCommand),
+    /// Compare two .bapple files frame by frame
+    Diff(DiffArgs),
+    /// Show how big the frames of a .bapple file are, and what that costs
+    Stats(StatsArgs),
+    /// Make a .bapple file smaller without changing what gets played
+    Optimize(OptimizeArgs),
+    /// Check a .bapple file against the hashes stored in it
+    Validate {
+        /// Path to a .bapple file
+        file: PathBuf,
+    },
+    /// Sign a .bapple file, for players that only play signed ones
+    Sign(SignArgs),
+    /// Make a key pair to sign .bapple files with
+    Keygen {
+        /// Where to write the secret key, the public one goes next to it
+        key: PathBuf,
+    },
+    /// Show what's inside a .bapple file
+    Info {
+        /// Path to a .bapple file
+        file: PathBuf,
+    },
+    /// Read or change the metadata of a .bapple file
+    #[command(subcommand)]
+    Meta(MetaCommand),
+    /// Play a .bapple file for browsers to watch over a WebSocket
+    Serve(ServeArgs),
+    /// Print a completion script for a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
 }
 
-fn validate_fps(s: &str) -> std::result::Result<f64, String> {
+/// A time of day, like 21:00 or 21:00:00, as how long after midnight it is.
+fn validate_start_at(s: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("invalid time {s}, expected hh:mm[:ss]");
+    let parts: Vec<_> = s.split(':').collect();
+    let [hours, minutes, seconds @ ..] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let seconds = match seconds {
+        [] => 0.0,
+        [seconds] => seconds.parse::<f64>().map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    let hours: u64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+    if hours >= 24 || minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return Err(invalid());
+    }
+    let since_midnight = Duration::from_secs(hours * 3600 + minutes * 60);
+    Ok(since_midnight + Duration::from_secs_f64(seconds))
+}
+
+pub fn validate_fps(s: &str) -> std::result::Result<f64, String> {
     let fps: f64 = s.parse().map_err(|e| format!("{e}"))?;
     if fps != 0.0 /*Value for autodetect*/ && fps < 0.01 {
         return Err("FPS value is too small.".to_string());
@@ -237,41 +1556,95 @@ fn validate_fps(s: &str) -> std::result::Result<f64, String> {
     Ok(fps)
 }
 
-#[derive(Deserialize, Default)]
-pub struct Metadata {
-    frametime: u64,
-    /// DEPRECATED
-    fps: u64,
+fn validate_frames(s: &str) -> std::result::Result<FrameRange, String> {
+    let (start, end) =
+        s.split_once("..").ok_or("Expected a range, like 300..450")?;
+    let start = if start.is_empty() {
+        0
+    } else {
+        start.parse().map_err(|e| format!("{e}"))?
+    };
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(end.parse().map_err(|e| format!("{e}"))?)
+    };
+
+    if end.is_some_and(|end| end <= start) {
+        return Err("The range is empty".to_string());
+    }
+    Ok(FrameRange { start, end })
 }
 
-#[cfg(windows)]
-fn enable_virtual_terminal_processing() {
-    use winapi::um::consoleapi::GetConsoleMode;
-    use winapi::um::consoleapi::SetConsoleMode;
-    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
-    use winapi::um::processenv::GetStdHandle;
-    use winapi::um::winbase::STD_OUTPUT_HANDLE;
-    use winapi::um::wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING;
-
-    unsafe {
-        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
-        if handle != INVALID_HANDLE_VALUE {
-            let mut mode = 0;
-            if GetConsoleMode(handle, &mut mode) != 0 {
-                if SetConsoleMode(
-                    handle,
-                    mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING,
-                ) == 0
-                {
-                    eprintln!(
-                        "Warning: Failed to enable virtual terminal processing"
-                    );
-                }
-            } else {
-                eprintln!("Warning: Failed to get console mode");
-            }
-        }
+fn validate_delay(s: &str) -> std::result::Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{e}"))
+}
+
+fn validate_brightness(s: &str) -> std::result::Result<f32, String> {
+    let brightness: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(-1.0..=1.0).contains(&brightness) {
+        return Err("Brightness goes from -1 to 1.".to_string());
     }
+    Ok(brightness)
+}
+
+fn validate_contrast(s: &str) -> std::result::Result<f32, String> {
+    let contrast: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !contrast.is_finite() || contrast < 0.0 {
+        return Err("Contrast can't be negative.".to_string());
+    }
+    Ok(contrast)
+}
+
+fn validate_charset(s: &str) -> std::result::Result<String, String> {
+    if s.chars().count() < 2 {
+        return Err("The charset needs at least two characters.".to_string());
+    }
+    Ok(s.to_string())
+}
+
+fn validate_crop(s: &str) -> std::result::Result<Crop, String> {
+    Crop::parse(s).ok_or_else(|| "Expected a size, like 80x24+10+5".to_string())
+}
+
+fn validate_pad(s: &str) -> std::result::Result<Pad, String> {
+    Pad::parse(s)
+        .ok_or_else(|| "Expected a number, or two like 2,4".to_string())
+}
+
+fn validate_tint(s: &str) -> std::result::Result<Tint, String> {
+    Tint::parse(s).ok_or_else(|| {
+        format!("Unknown color {s}, expected a name, #rrggbb or r,g,b")
+    })
+}
+
+/// Shows the file's poster, if it has one and it's no bigger than
+/// `max_size`, while the rest of it loads. It's only there to look at, so
+/// anything going wrong is ignored.
+pub fn show_poster(
+    path: &Path,
+    filters: &Settings,
+    capabilities: Capabilities,
+    max_size: usize,
+) {
+    let Ok(Some(poster)) = read_poster(path, max_size) else {
+        return;
+    };
+    #[cfg(windows)]
+    let capabilities = if enable_virtual_terminal_processing() {
+        capabilities
+    } else {
+        Capabilities { color: ColorDepth::None, ..capabilities }
+    };
+    // Without the file's charset, since that's in what's still loading.
+    let poster = filters.pipeline(capabilities, None).apply(poster);
+
+    let mut w = stdout().lock();
+    let _ = w
+        .write_all(&poster)
+        .and_then(|()| w.write_all(b"\x1b[0m\n"))
+        .and_then(|()| w.flush());
 }
 
 macro_rules! write_fn {
@@ -284,6 +1657,215 @@ macro_rules! write_fn {
 }
 
 write_fn!(clear, b"\r\x1b[2J\x1b[H");
-write_fn!(show_cursor, b"\x1b[?25h");
-write_fn!(hide_cursor, b"\x1b[?25l");
-write_fn!(return_home, b"\x1b[H");
+// Synchronized output: the terminal holds off drawing until the whole
+// frame is in, so big frames don't tear. Unknown modes get ignored.
+write_fn!(begin_sync, b"\x1b[?2026h");
+write_fn!(end_sync, b"\x1b[?2026l");
+
+/// Sleeps until the clock gets to `at`, a bit at a time so Ctrl+C still
+/// gets through.
+fn wait_until<C: Clock>(at: Duration, clock: &C) {
+    while !STOP.load(Ordering::Relaxed) {
+        let left = at.saturating_sub(clock.now());
+        if left.is_zero() {
+            return;
+        }
+        clock.sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+fn set_title<W: Write>(w: &mut W, title: &str) -> io::Result<()> {
+    // A control character in the title would end the sequence early.
+    let title = title.chars().filter(|c| !c.is_control()).collect::<String>();
+    write!(w, "\x1b]0;{title}\x07")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::ArchiveWriter, clock::FakeClock, controls::Osd, temp::TempPath,
+    };
+
+    const FRAMETIME: Duration = Duration::from_millis(40);
+
+    /// A file with `frames` frames of a line each, `frametime` apart.
+    fn file(frames: usize, frametime: Duration) -> TempPath {
+        let file = TempPath::new();
+        let mut writer = ArchiveWriter::create(&file).unwrap();
+        let frametime = u64::try_from(frametime.as_micros()).unwrap();
+        writer
+            .append_metadata(&Metadata { frametime, ..Default::default() })
+            .unwrap();
+        for i in 0..frames {
+            let frame = format!("frame {i}");
+            writer
+                .append_frame(&zstd::encode_all(frame.as_bytes(), 0).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        file
+    }
+
+    /// Captures what gets drawn, taking `lag` of the clock's time for
+    /// each frame, like a slow terminal would.
+    struct Terminal<'a> {
+        clock: &'a FakeClock,
+        lag: Duration,
+        written: Vec<u8>,
+    }
+
+    impl Write for Terminal<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.clock.advance(self.lag);
+            Ok(())
+        }
+    }
+
+    fn play(file: &Path, clock: &FakeClock, lag: Duration) -> Res<Bapple> {
+        let mut bapple = Bapple::new(file, Loading::All, Limits::default())?;
+        let mut w = Terminal { clock, lag, written: Vec::new() };
+        let mut controls = Controls::new(Repeat::None, Osd::Off);
+        bapple.play_on(&mut w, clock, None, &mut None, &mut controls)?;
+        assert!(String::from_utf8_lossy(&w.written).contains("frame 0"));
+        Ok(bapple)
+    }
+
+    #[test]
+    fn plays_every_frame_in_time() {
+        let file = file(5, FRAMETIME);
+        let clock = FakeClock::default();
+        let bapple = play(&file, &clock, Duration::ZERO).unwrap();
+
+        assert_eq!(bapple.timings().frames, 5);
+        assert_eq!(bapple.timings().dropped, 0);
+        assert_eq!(clock.now(), FRAMETIME * 5);
+    }
+
+    #[test]
+    fn drops_frames_it_cant_keep_up_with() {
+        let file = file(10, FRAMETIME);
+        let clock = FakeClock::default();
+        // Each frame takes as long to draw as two and a half are on for.
+        let bapple = play(&file, &clock, FRAMETIME * 5 / 2).unwrap();
+
+        let timings = bapple.timings();
+        assert!(timings.dropped > 0);
+        assert!(timings.frames < 10);
+        // Still done about when the last frame is.
+        assert!(clock.now() <= FRAMETIME * 10 + FRAMETIME * 5 / 2);
+    }
+
+    #[test]
+    fn starts_partway_through() {
+        let file = file(10, FRAMETIME);
+        let clock = FakeClock::default();
+        clock.advance(Duration::from_secs(60));
+        let mut bapple =
+            Bapple::new(&file, Loading::All, Limits::default()).unwrap();
+        bapple.set_range(FRAMETIME * 6, None);
+        let mut w = Terminal {
+            clock: &clock,
+            lag: Duration::ZERO,
+            written: Vec::new(),
+        };
+        let mut controls = Controls::new(Repeat::None, Osd::Off);
+        bapple.play_on(&mut w, &clock, None, &mut None, &mut controls).unwrap();
+
+        assert_eq!(bapple.timings().frames, 4);
+        assert_eq!(clock.now(), Duration::from_secs(60) + FRAMETIME * 4);
+    }
+
+    #[test]
+    fn plays_a_range_of_frames() {
+        let file = file(10, FRAMETIME);
+        let clock = FakeClock::default();
+        let mut bapple =
+            Bapple::new(&file, Loading::All, Limits::default()).unwrap();
+        bapple.set_frames(3, Some(5)).unwrap();
+        let mut w = Terminal {
+            clock: &clock,
+            lag: Duration::ZERO,
+            written: Vec::new(),
+        };
+        let mut controls = Controls::new(Repeat::None, Osd::Off);
+        bapple.play_on(&mut w, &clock, None, &mut None, &mut controls).unwrap();
+
+        // Frame 4 only redraws the number, so look for where it starts.
+        let written = String::from_utf8_lossy(&w.written);
+        assert_eq!(bapple.timings().frames, 2);
+        assert!(written.contains("frame 3"));
+        assert!(!written.contains("frame 2"));
+        assert_eq!(clock.now(), FRAMETIME * 2);
+    }
+
+    #[test]
+    fn refuses_frames_out_of_the_file() {
+        let file = file(10, FRAMETIME);
+        let mut bapple =
+            Bapple::new(&file, Loading::All, Limits::default()).unwrap();
+
+        assert!(bapple.set_frames(10, None).is_err());
+        assert!(bapple.set_frames(3, Some(11)).is_err());
+        assert!(bapple.set_frames(9, Some(10)).is_ok());
+    }
+
+    #[test]
+    fn parses_frame_ranges() {
+        let range = validate_frames("300..450").unwrap();
+        assert_eq!((range.start, range.end), (300, Some(450)));
+        let range = validate_frames("..450").unwrap();
+        assert_eq!((range.start, range.end), (0, Some(450)));
+        let range = validate_frames("300..").unwrap();
+        assert_eq!((range.start, range.end), (300, None));
+
+        assert!(validate_frames("300").is_err());
+        assert!(validate_frames("450..300").is_err());
+        assert!(validate_frames("300..300").is_err());
+    }
+
+    #[test]
+    fn waits_on_the_clock_to_start_at() {
+        let file = file(2, FRAMETIME);
+        let clock = FakeClock::default();
+        let mut bapple =
+            Bapple::new(&file, Loading::All, Limits::default()).unwrap();
+        let wait = Duration::from_secs(60);
+        bapple.set_start_at(Some(Instant::now() + wait));
+        let mut w = Terminal {
+            clock: &clock,
+            lag: Duration::ZERO,
+            written: Vec::new(),
+        };
+        let mut controls = Controls::new(Repeat::None, Osd::Off);
+        bapple.play_on(&mut w, &clock, None, &mut None, &mut controls).unwrap();
+
+        // Give or take however long it took to get there.
+        assert!(clock.now() > Duration::from_secs(59));
+        assert!(clock.now() <= wait + FRAMETIME * 2);
+    }
+
+    #[test]
+    fn refuses_archives_over_the_limits() {
+        let file = file(5, FRAMETIME);
+        let limits = Limits { entries: 3, ..Limits::default() };
+        let error = Bapple::new(&file, Loading::All, limits).err().unwrap();
+        assert!(error.to_string().contains("more than 3 entries"));
+
+        let limits = Limits { bytes: 10, ..Limits::default() };
+        let error = Bapple::new(&file, Loading::All, limits).err().unwrap();
+        assert_eq!(error.to_string(), limits.too_big());
+    }
+
+    #[test]
+    fn refuses_a_zero_frametime() {
+        let file = file(3, Duration::ZERO);
+        let clock = FakeClock::default();
+        let error = play(&file, &clock, Duration::ZERO).err().unwrap();
+        assert_eq!(error.to_string(), FRAMETIME_ZERO);
+    }
+}
@@ -0,0 +1,138 @@
+// `--high-priority`, asking the OS to run the threads that draw and decode
+// frames ahead of everything else, so they don't get held up on a busy
+// system. Realtime round-robin scheduling where that's allowed, else as
+// much of a bump as there is, else nothing, and playback goes on anyway.
+// Threads start out with whatever priority the one starting them has, so
+// it only lasts while there's drawing or decoding to do.
+
+use std::marker::PhantomData;
+
+use tracing::debug;
+
+/// How far up the nice value goes when realtime scheduling isn't allowed.
+#[cfg(unix)]
+const NICE: libc::c_int = -10;
+
+/// Puts the thread's priority back once dropped, so nothing it starts
+/// afterwards (the audio, loading the next file) is raised along with it.
+pub struct Raised {
+    before: Before,
+    /// Only the thread that got raised can be put back.
+    _thread: PhantomData<*const ()>,
+}
+
+/// What the thread had before.
+#[cfg(unix)]
+enum Before {
+    Scheduling(libc::c_int, libc::sched_param),
+    Nice(libc::c_int),
+}
+
+#[cfg(windows)]
+struct Before(i32);
+
+#[cfg(not(any(unix, windows)))]
+enum Before {}
+
+/// Raises the priority of the thread it's called on, `None` if it wasn't
+/// allowed to at all.
+#[cfg(unix)]
+pub fn raise() -> Option<Raised> {
+    unsafe {
+        let thread = libc::pthread_self();
+        let (mut policy, mut before) = (0, std::mem::zeroed());
+        // The lowest realtime priority is still above every normal thread.
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = libc::sched_get_priority_min(libc::SCHED_RR);
+        if libc::pthread_getschedparam(thread, &raw mut policy, &raw mut before)
+            == 0
+            && libc::pthread_setschedparam(
+                thread,
+                libc::SCHED_RR,
+                &raw const param,
+            ) == 0
+        {
+            debug!("priority raised to SCHED_RR");
+            return Some(Raised::new(Before::Scheduling(policy, before)));
+        }
+        // Linux takes 0 to mean just this thread, elsewhere it's all of
+        // them, which does no harm.
+        let nice = libc::getpriority(libc::PRIO_PROCESS, 0);
+        if libc::setpriority(libc::PRIO_PROCESS, 0, NICE) == 0 {
+            debug!(nice = NICE, "priority raised");
+            return Some(Raised::new(Before::Nice(nice)));
+        }
+    }
+    None
+}
+
+/// Raises the priority of the thread it's called on, `None` if it wasn't
+/// allowed to at all.
+#[cfg(windows)]
+#[allow(clippy::cast_possible_wrap)]
+pub fn raise() -> Option<Raised> {
+    use winapi::um::{
+        processthreadsapi::{
+            GetCurrentThread, GetThreadPriority, SetThreadPriority,
+        },
+        winbase::THREAD_PRIORITY_HIGHEST,
+    };
+
+    let priority = THREAD_PRIORITY_HIGHEST as i32;
+    let before = unsafe { GetThreadPriority(GetCurrentThread()) };
+    if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+        return None;
+    }
+    debug!("priority raised to THREAD_PRIORITY_HIGHEST");
+    Some(Raised::new(Before(before)))
+}
+
+/// Nothing to raise it with anywhere else.
+#[cfg(not(any(unix, windows)))]
+pub fn raise() -> Option<Raised> {
+    None
+}
+
+impl Raised {
+    #[cfg_attr(not(any(unix, windows)), allow(dead_code))]
+    fn new(before: Before) -> Self {
+        Self { before, _thread: PhantomData }
+    }
+}
+
+impl Drop for Raised {
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        unsafe {
+            match self.before {
+                Before::Scheduling(policy, param) => {
+                    let thread = libc::pthread_self();
+                    libc::pthread_setschedparam(
+                        thread,
+                        policy,
+                        &raw const param,
+                    );
+                }
+                Before::Nice(nice) => {
+                    libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+                }
+            }
+        }
+        debug!("priority put back");
+    }
+
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        use winapi::um::processthreadsapi::{
+            GetCurrentThread, SetThreadPriority,
+        };
+
+        unsafe { SetThreadPriority(GetCurrentThread(), self.before.0) };
+        debug!("priority put back");
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn drop(&mut self) {
+        match self.before {}
+    }
+}
@@ -0,0 +1,328 @@
+// Playing .bapple files straight off of a web server. Archives end with
+// `index.ron`, saying where every entry is in the file (see
+// `ArchiveWriter::finish`), so a player can fetch the end of the file,
+// then the metadata, audio and such, and start playing right away, with
+// the frames fetched a batch at a time as they're needed. Seeking fetches
+// from wherever it lands. Files from before the index, and servers that
+// don't do range requests, get read through from the start instead.
+
+use std::{
+    io::{self, Cursor, Read},
+    ops::Range,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use tar::Header;
+
+use crate::{
+    Res,
+    archive::{
+        Limits, check_inside, is_audio, is_dictionary, is_encryption, is_frame,
+        is_metadata, subtitle_language,
+    },
+    http::{Bytes, Url},
+};
+
+/// How much of the end of a file gets fetched to look for the index at
+/// first, which grows until it takes in the last entry, or gets to the
+/// most an archive gets to hold.
+const TAIL: u64 = 64 * 1024;
+/// About how much of the frames gets fetched at once.
+const BATCH: u64 = 512 * 1024;
+
+/// What's in `index.ron`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Index {
+    /// In the order they're in the archive.
+    pub entries: Vec<Indexed>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Indexed {
+    pub name: String,
+    /// Where its data starts in the archive, past the header.
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A file on a server, ready to be read.
+pub enum Opened {
+    /// The frames to fetch as they're needed, and every entry that's
+    /// needed before playing.
+    Indexed(Remote, Vec<(PathBuf, Vec<u8>)>),
+    /// All of it, from the start, to be read like a local file.
+    Whole(Box<dyn Read + Send>),
+}
+
+/// Frames fetched from a server as they're needed.
+pub struct Remote {
+    url: Url,
+    /// Where each frame is in the file.
+    frames: Vec<Range<u64>>,
+    /// What got fetched last.
+    batch: Option<Batch>,
+    /// Which stored frame each played one is, if any are repeated.
+    pub map: Option<Vec<usize>>,
+}
+
+/// A few frames in a row, as they are in the file.
+struct Batch {
+    frames: Range<usize>,
+    /// Where `data` starts in the file.
+    start: u64,
+    data: Vec<u8>,
+}
+
+pub fn open(url: &Url, limits: Limits) -> Res<Opened> {
+    let mut window = TAIL;
+    loop {
+        let response = url.get(&Bytes::Last(window))?;
+        if !response.partial {
+            return Ok(Opened::Whole(Box::new(response)));
+        }
+        let total = response.total.ok_or("the server didn't say how big")?;
+        if total > limits.bytes {
+            return Err(limits.too_big().into());
+        }
+        let mut tail = Vec::new();
+        response.take(window).read_to_end(&mut tail)?;
+        let start = total
+            .checked_sub(tail.len() as u64)
+            .ok_or("got more than there is")?;
+
+        let last = last_entry(&tail, start)?;
+        if let Some(last) =
+            last.as_ref().filter(|last| last.name == "index.ron")
+        {
+            let index =
+                &tail[offset(last.offset - start)..][..offset(last.size)];
+            let index = ron::from_str(str::from_utf8(index)?)
+                .map_err(|e| format!("invalid index: {e}"))?;
+            return indexed(url, index, total, limits);
+        }
+        if start == 0 {
+            // That's all of it already.
+            return Ok(Opened::Whole(Box::new(Cursor::new(tail))));
+        }
+        if last.is_some() {
+            // No index, so it all has to be read anyway.
+            return Ok(Opened::Whole(Box::new(url.get(&Bytes::All)?)));
+        }
+        if window >= limits.bytes {
+            return Err(limits.too_big().into());
+        }
+        window = window.saturating_mul(8).min(limits.bytes);
+    }
+}
+
+/// Fetches everything but the frames, which are left for later.
+fn indexed(url: &Url, index: Index, total: u64, limits: Limits) -> Res<Opened> {
+    let mut read = (0, 0);
+    let mut frames = Vec::new();
+    let mut others = Vec::new();
+    let mut end = 0;
+    for entry in index.entries {
+        limits.check(&mut read, entry.size)?;
+        let range = entry.offset..entry.offset.saturating_add(entry.size);
+        // In order and inside the file, or fetching them in batches
+        // wouldn't work.
+        if range.start < end || range.end > total {
+            return Err("the index is damaged".into());
+        }
+        end = range.end;
+
+        let name = PathBuf::from(entry.name);
+        check_inside(&name)?;
+        if is_encryption(&name) {
+            let why =
+                "is encrypted, download it to play it with --password-file";
+            return Err(why.into());
+        }
+        if is_frame(&name) {
+            frames.push(range);
+        } else if is_metadata(&name)
+            || is_audio(&name)
+            || is_dictionary(&name)
+            || subtitle_language(&name).is_some()
+        {
+            others.push((name, fetch(url, range)?));
+        }
+    }
+    let remote = Remote { url: url.clone(), frames, batch: None, map: None };
+    Ok(Opened::Indexed(remote, others))
+}
+
+impl Remote {
+    /// How many frames there are stored.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn get(&mut self, index: usize) -> io::Result<&[u8]> {
+        let index = self
+            .map
+            .as_ref()
+            .and_then(|map| map.get(index).copied())
+            .unwrap_or(index);
+        let span = self.frames.get(index).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("couldn't read frame {index}"),
+            )
+        })?;
+        let fetched = self
+            .batch
+            .as_ref()
+            .is_some_and(|batch| batch.frames.contains(&index));
+        if !fetched {
+            // At least this one, and as many after it as fit.
+            let last = (index + 1..self.frames.len())
+                .take_while(|&next| self.frames[next].end - span.start <= BATCH)
+                .last()
+                .unwrap_or(index);
+            let range = span.start..self.frames[last].end;
+            let data = fetch(&self.url, range)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            let frames = index..last + 1;
+            self.batch = Some(Batch { frames, start: span.start, data });
+        }
+        // Just fetched if it wasn't already.
+        let batch = self.batch.as_ref().unwrap();
+        let start = offset(span.start - batch.start);
+        Ok(&batch.data[start..start + offset(span.end - span.start)])
+    }
+}
+
+/// Exactly the bytes in `range`.
+fn fetch(url: &Url, range: Range<u64>) -> Res<Vec<u8>> {
+    let length = range.end - range.start;
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    let mut response = url.get(&Bytes::Range(range))?;
+    if !response.partial {
+        return Err("the server stopped doing ranges".into());
+    }
+    let mut data = Vec::with_capacity(offset(length));
+    response.by_ref().take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+        return Err("the connection got cut off".into());
+    }
+    Ok(data)
+}
+
+/// The last entry in `tail`, the end of an archive that starts at `start`
+/// in it, or `None` if its header isn't in there.
+fn last_entry(tail: &[u8], start: u64) -> Res<Option<Indexed>> {
+    let block = |at: u64| &tail[offset(at - start)..][..512];
+    let first = start.next_multiple_of(512);
+    // Past the blocks of zeros that mark the end.
+    let mut end = start + tail.len() as u64;
+    end -= end % 512;
+    while end >= first + 512 && block(end - 512).iter().all(|&byte| byte == 0) {
+        end -= 512;
+    }
+
+    // Going backwards, the first header that ends right where the archive
+    // does is the last one.
+    let Some(mut at) = end.checked_sub(512) else {
+        return Ok(None);
+    };
+    while at >= first {
+        let header = Header::from_byte_slice(block(at));
+        if let (true, Ok(size), Ok(name)) =
+            (checksum_matches(header), header.entry_size(), header.path())
+        {
+            // The size comes from the server, so it can be anything.
+            let entry_end = size
+                .checked_next_multiple_of(512)
+                .and_then(|size| (at + 512).checked_add(size))
+                .ok_or("the archive is damaged")?;
+            if entry_end == end {
+                let name = name.to_string_lossy().into_owned();
+                return Ok(Some(Indexed { name, offset: at + 512, size }));
+            }
+        }
+        let Some(before) = at.checked_sub(512) else {
+            return Ok(None);
+        };
+        at = before;
+    }
+    Ok(None)
+}
+
+/// Whether a block really is a header, and not just some data.
+fn checksum_matches(header: &Header) -> bool {
+    let bytes = header.as_bytes();
+    // Added up with the checksum itself counting as spaces.
+    let sum: u32 = bytes[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&bytes[156..])
+        .map(|&byte| u32::from(byte))
+        .sum();
+    header.cksum().is_ok_and(|checksum| checksum == sum)
+}
+
+/// A position in a file as one in memory, which anything that got fetched
+/// fits in.
+#[allow(clippy::cast_possible_truncation)]
+fn offset(position: u64) -> usize {
+    position as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use tar::Builder;
+
+    use super::*;
+
+    fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn finds_the_last_entry() {
+        let file = archive(&[("metadata.ron", b"()"), ("index.ron", b"[]")]);
+        // Only the end of it, not starting on a block.
+        let start = 700;
+        let last = last_entry(&file[start..], start as u64).unwrap().unwrap();
+        assert_eq!(last.name, "index.ron");
+        assert_eq!(&file[offset(last.offset)..][..2], b"[]");
+    }
+
+    #[test]
+    fn refuses_names_outside_of_it() {
+        let url = Url::parse("http://127.0.0.1:1/file.bapple").unwrap();
+        for name in ["../metadata.ron", "/etc/passwd"] {
+            let index = Index {
+                entries: vec![Indexed {
+                    name: name.to_string(),
+                    offset: 512,
+                    size: 2,
+                }],
+            };
+            let opened = indexed(&url, index, 1024, Limits::default());
+            let error = opened.err().unwrap().to_string();
+            assert!(error.contains("outside"), "{error}");
+        }
+    }
+
+    #[test]
+    fn refuses_sizes_that_overflow() {
+        let mut header = Header::new_gnu();
+        header.set_path("index.ron").unwrap();
+        header.set_size(u64::MAX);
+        header.set_cksum();
+        let mut tail = header.as_bytes().to_vec();
+        tail.extend([0; 1024]);
+        assert!(last_entry(&tail, 0).is_err());
+    }
+}
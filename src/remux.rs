@@ -0,0 +1,76 @@
+// `bplay remux`, swapping the audio of a .bapple file for another track,
+// to fix bad audio or put a dub in. The frames and everything else are
+// copied over untouched.
+
+use std::path::PathBuf;
+
+use crate::{
+    Res,
+    archive::{read_metadata, write_audio},
+    encode::audio,
+    metadata::{Metadata, TrackLabel},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RemuxArgs {
+    /// The .bapple file to change
+    pub file: PathBuf,
+    /// The new audio, from any audio or video file
+    pub audio: PathBuf,
+    /// Where to write the result, instead of changing the file in place
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// MP3 bitrate in kbps, when the audio needs transcoding
+    #[arg(
+        long,
+        default_value = "192",
+        value_parser = ["96", "128", "160", "192", "256", "320"]
+    )]
+    pub audio_bitrate: String,
+    /// Language of the new audio, like en. The old track's gets dropped
+    /// otherwise, since it's about a different track
+    #[arg(long, value_name = "LANG")]
+    pub audio_language: Option<String>,
+    /// What to call the new audio track, like English
+    #[arg(long, value_name = "NAME", requires = "audio_language")]
+    pub audio_name: Option<String>,
+}
+
+pub fn run(args: &RemuxArgs) -> Res<()> {
+    let bitrate = args.audio_bitrate.parse().unwrap_or(192);
+    let mp3 = audio::load(&args.audio, bitrate)?;
+
+    let label = args.audio_language.as_ref().map(|language| TrackLabel {
+        language: language.clone(),
+        name: args.audio_name.clone().unwrap_or_default(),
+    });
+    // Only rewritten when the label changes, copied as it is otherwise.
+    let metadata = match read_metadata(&args.file)? {
+        Some(mut metadata)
+            if label.is_some() || metadata.audio_track.is_some() =>
+        {
+            // Fields this version doesn't know would get dropped.
+            if metadata.is_newer() {
+                return Err(format!(
+                    "{}: made by a newer version of bplay, which this one \
+                     can't change without losing things",
+                    args.file.display()
+                )
+                .into());
+            }
+            metadata.audio_track = label;
+            Some(metadata)
+        }
+        None if label.is_some() => Some(Metadata {
+            version: Metadata::VERSION,
+            audio_track: label,
+            ..Default::default()
+        }),
+        _ => None,
+    };
+
+    let output = args.output.as_ref().unwrap_or(&args.file);
+    write_audio(&args.file, output, &mp3, metadata.as_ref())?;
+    println!("Put {} into {}", args.audio.display(), output.display());
+    Ok(())
+}
@@ -0,0 +1,543 @@
+// Changes made to frames right before they're drawn, for terminals
+// (or people) that can't take them as they are.
+
+use clap::ValueEnum;
+
+use crate::terminal::{ColorDepth, frame_colors};
+
+/// Drops escape sequences, leaving only the characters. CSI and OSC
+/// sequences are skipped whole, anything else is assumed to be two bytes.
+pub fn strip_escapes(frame: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(frame.len());
+    let mut bytes = frame.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        if byte != 0x1b {
+            stripped.push(byte);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'[') => {
+                // Parameters, then a final byte from '@' to '~'.
+                bytes.by_ref().find(|b| (0x40..=0x7e).contains(b));
+            }
+            Some(b']') => {
+                // Ends with BEL or ST (ESC \).
+                let mut previous = 0;
+                bytes.by_ref().find(|&b| {
+                    let end = b == 0x07 || (previous == 0x1b && b == b'\\');
+                    previous = b;
+                    end
+                });
+            }
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// A piece of a frame, either an escape sequence or a character.
+enum Token<'a> {
+    Escape(&'a str),
+    Char(char),
+}
+
+/// Splits a frame into escape sequences and characters, the sequences
+/// found the same way [`strip_escapes`] finds them.
+fn tokens(text: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        if c != '\x1b' {
+            rest = &rest[c.len_utf8()..];
+            return Some(Token::Char(c));
+        }
+
+        let mut chars = rest.char_indices().skip(1);
+        let end = match chars.next() {
+            Some((_, '[')) => chars.find(|(_, c)| ('@'..='~').contains(c)),
+            Some((_, ']')) => {
+                let mut previous = ' ';
+                chars.find(|&(_, c)| {
+                    let end = c == '\x07' || (previous == '\x1b' && c == '\\');
+                    previous = c;
+                    end
+                })
+            }
+            other => other,
+        };
+        let len = end.map_or(rest.len(), |(i, c)| i + c.len_utf8());
+        let (escape, after) = rest.split_at(len);
+        rest = after;
+        Some(Token::Escape(escape))
+    })
+}
+
+/// Runs `map` over every character that isn't part of an escape
+/// sequence.
+fn map_chars(frame: &[u8], map: impl Fn(char) -> char) -> Vec<u8> {
+    let text = String::from_utf8_lossy(frame);
+    let mut mapped = String::with_capacity(text.len());
+    for token in tokens(&text) {
+        match token {
+            Token::Escape(escape) => mapped.push_str(escape),
+            Token::Char(c) => mapped.push(map(c)),
+        }
+    }
+    mapped.into_bytes()
+}
+
+/// Part of a frame, in columns and rows.
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// `WxH`, optionally followed by `+X+Y`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (size, offset) = s.split_once('+').unwrap_or((s, "0+0"));
+        let (width, height) = size.split_once('x')?;
+        let (x, y) = offset.split_once('+')?;
+        Some(Self {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        })
+    }
+}
+
+/// Keeps only the `crop` part of a frame. Escape sequences are all kept,
+/// even on rows that aren't, so colors carry on the way they would have.
+pub fn crop(frame: &[u8], crop: Crop) -> Vec<u8> {
+    let text = String::from_utf8_lossy(frame);
+    let mut cropped = String::with_capacity(text.len());
+    let rows = crop.y..crop.y + crop.height;
+    let columns = crop.x..crop.x + crop.width;
+
+    for (row, line) in text.split_inclusive('\n').enumerate() {
+        let kept = rows.contains(&row);
+        let mut column = 0;
+        for token in tokens(line) {
+            match token {
+                Token::Escape(escape) => cropped.push_str(escape),
+                Token::Char(c @ ('\n' | '\r')) if kept => cropped.push(c),
+                Token::Char(_) if !kept => {}
+                Token::Char(c) => {
+                    if columns.contains(&column) {
+                        cropped.push(c);
+                    }
+                    column += 1;
+                }
+            }
+        }
+    }
+
+    cropped.into_bytes()
+}
+
+/// Blank space above and to the left of a frame, to move it away from
+/// the corner.
+#[derive(Clone, Copy, Debug)]
+pub struct Pad {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl Pad {
+    /// `N` for the same on both, or `ROWS,COLUMNS`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (rows, columns) = s.split_once(',').unwrap_or((s, s));
+        Some(Self {
+            rows: rows.trim().parse().ok()?,
+            columns: columns.trim().parse().ok()?,
+        })
+    }
+}
+
+pub fn pad(frame: &[u8], pad: Pad) -> Vec<u8> {
+    let indent = " ".repeat(pad.columns);
+    let mut padded = "\n".repeat(pad.rows).into_bytes();
+    for line in frame.split_inclusive(|&b| b == b'\n') {
+        padded.extend_from_slice(indent.as_bytes());
+        padded.extend_from_slice(line);
+    }
+    padded
+}
+
+/// Where text goes on top of a frame.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    #[default]
+    BottomRight,
+}
+
+/// Writes `text` over a corner of a frame, or the middle of its top or
+/// bottom row.
+pub fn overlay(frame: &[u8], text: &str, position: Position) -> Vec<u8> {
+    let row = match position {
+        Position::TopLeft | Position::Top | Position::TopRight => Row::Top(0),
+        _ => Row::Bottom(0),
+    };
+    let align = match position {
+        Position::TopLeft | Position::BottomLeft => Align::Left,
+        Position::Top | Position::Bottom => Align::Center,
+        Position::TopRight | Position::BottomRight => Align::Right,
+    };
+    composite(frame, &[(row, align, text)])
+}
+
+/// Writes subtitle lines over the bottom rows of a frame, centered.
+pub fn caption(frame: &[u8], lines: &[String]) -> Vec<u8> {
+    let texts: Vec<_> = lines
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(row, line)| (Row::Bottom(row), Align::Center, line.as_str()))
+        .collect();
+    composite(frame, &texts)
+}
+
+/// Counted from the top or the bottom of a frame.
+#[derive(Clone, Copy)]
+enum Row {
+    Top(usize),
+    Bottom(usize),
+}
+
+impl Row {
+    fn index(self, rows: usize) -> Option<usize> {
+        match self {
+            Self::Top(row) => (row < rows).then_some(row),
+            Self::Bottom(row) => rows.checked_sub(row + 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// How many columns a line takes up, escape sequences left out.
+fn columns(line: &str) -> usize {
+    tokens(line)
+        .filter(|token| {
+            matches!(token, Token::Char(c) if !matches!(c, '\n' | '\r'))
+        })
+        .count()
+}
+
+/// Writes each text over its row, in reverse video so it shows up on
+/// anything. Only turning reverse video back off leaves the frame's
+/// colors alone. Text wider than the frame gets cut off.
+fn composite(frame: &[u8], texts: &[(Row, Align, &str)]) -> Vec<u8> {
+    let frame = String::from_utf8_lossy(frame);
+    let lines: Vec<_> = frame.split_inclusive('\n').collect();
+    let width = lines.iter().map(|line| columns(line)).max().unwrap_or(0);
+
+    let mut composited = String::with_capacity(frame.len());
+    for (index, line) in lines.iter().enumerate() {
+        let text = texts
+            .iter()
+            .find(|(row, ..)| row.index(lines.len()) == Some(index));
+        match text {
+            Some(&(_, align, text)) => {
+                write_over(&mut composited, line, width, align, text);
+            }
+            None => composited.push_str(line),
+        }
+    }
+
+    composited.into_bytes()
+}
+
+fn write_over(
+    composited: &mut String,
+    line: &str,
+    width: usize,
+    align: Align,
+    text: &str,
+) {
+    let text: String = text.chars().take(width).collect();
+    let len = text.chars().count();
+    if len == 0 {
+        composited.push_str(line);
+        return;
+    }
+    let column = match align {
+        Align::Left => 0,
+        Align::Center => (width - len) / 2,
+        Align::Right => width - len,
+    };
+    let banner = format!("\x1b[7m{text}\x1b[27m");
+
+    let (line, end) = match line.strip_suffix('\n') {
+        Some(line) => (line.strip_suffix('\r').unwrap_or(line), "\n"),
+        None => (line, ""),
+    };
+    let mut current = 0;
+    for token in tokens(line) {
+        match token {
+            Token::Escape(escape) => composited.push_str(escape),
+            Token::Char(c) => {
+                if current == column {
+                    composited.push_str(&banner);
+                }
+                if !(column..column + len).contains(&current) {
+                    composited.push(c);
+                }
+                current += 1;
+            }
+        }
+    }
+    // Lines can be shorter than the widest one.
+    if current <= column {
+        composited.push_str(&" ".repeat(column - current));
+        composited.push_str(&banner);
+    }
+    composited.push_str(end);
+}
+
+/// Changes to how bright frames look, made by moving characters along
+/// the file's charset.
+#[derive(Clone, Copy, Debug)]
+pub struct Levels {
+    /// Dark becomes bright and the other way around.
+    pub invert: bool,
+    /// How far along the charset everything moves, from -1 to 1.
+    pub brightness: f32,
+    /// Above 1 spreads characters away from the middle, below 1 pulls
+    /// them towards it.
+    pub contrast: f32,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self { invert: false, brightness: 0.0, contrast: 1.0 }
+    }
+}
+
+impl Levels {
+    #[allow(clippy::float_cmp)]
+    pub fn is_identity(self) -> bool {
+        !self.invert && self.brightness == 0.0 && self.contrast == 1.0
+    }
+
+    /// How bright the character at `index` out of `len` ends up, from 0
+    /// to 1.
+    #[allow(clippy::cast_precision_loss)]
+    fn level(self, index: usize, len: usize) -> f32 {
+        let mut level =
+            if len > 1 { index as f32 / (len - 1) as f32 } else { 0.0 };
+        if self.invert {
+            level = 1.0 - level;
+        }
+        level = (level - 0.5) * self.contrast + 0.5 + self.brightness;
+        level.clamp(0.0, 1.0)
+    }
+}
+
+/// Moves every character in `from` (darkest to brightest) by `levels`,
+/// then swaps it for the one as bright in `to`, if there is one. Anything
+/// that isn't in `from` is left alone.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn remap(
+    frame: Vec<u8>,
+    from: &[char],
+    levels: Levels,
+    to: Option<&[char]>,
+) -> Vec<u8> {
+    if levels.is_identity() && to.is_none() {
+        return frame;
+    }
+    let to = to.unwrap_or(from);
+    let last = to.len().saturating_sub(1) as f32;
+
+    map_chars(&frame, |c| {
+        from.iter().position(|&d| d == c).map_or(c, |index| {
+            let level = levels.level(index, from.len());
+            to[(level * last).round() as usize]
+        })
+    })
+}
+
+/// Darkest to brightest, picked by how much of the cell a character fills.
+const RAMP: &[u8; 10] = b" .:-=+*#%@";
+
+/// How much of the cell each block element (U+2580 to U+259F) fills,
+/// in eighths.
+const BLOCK_COVERAGE: [u32; 32] = [
+    4, 1, 2, 3, 4, 5, 6, 7, 8, 7, 6, 5, 4, 3, 2, 1, //
+    4, 2, 4, 6, 1, 1, 2, 2, 2, 6, 4, 6, 6, 2, 4, 6,
+];
+
+/// Swaps block, shade, braille and box drawing characters for ASCII
+/// look-alikes, for fonts that don't have them. Anything else outside
+/// ASCII becomes '?'.
+pub fn to_ascii(frame: &[u8]) -> Vec<u8> {
+    if frame.is_ascii() {
+        return frame.to_vec();
+    }
+    String::from_utf8_lossy(frame)
+        .chars()
+        .map(transliterate)
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn transliterate(c: char) -> char {
+    let code = u32::from(c);
+    match c {
+        _ if c.is_ascii() => c,
+        '─' | '━' | '┄' | '┅' | '┈' | '┉' | '╌' | '╍' | '═' => {
+            '-'
+        }
+        '│' | '┃' | '┆' | '┇' | '┊' | '┋' | '╎' | '╏' | '║' => {
+            '|'
+        }
+        '╱' => '/',
+        '╲' => '\\',
+        '╳' => 'X',
+        '\u{2500}'..='\u{257f}' => '+',
+        '\u{2580}'..='\u{259f}' => {
+            coverage(BLOCK_COVERAGE[(code - 0x2580) as usize])
+        }
+        // Braille dots are bits, eight of them.
+        '\u{2800}'..='\u{28ff}' => coverage((code - 0x2800).count_ones()),
+        '•' | '●' | '■' => '*',
+        '·' | '∙' => '.',
+        '○' | '◯' => 'o',
+        _ => '?',
+    }
+}
+
+fn coverage(eighths: u32) -> char {
+    char::from(RAMP[(eighths * 9 / 8) as usize])
+}
+
+/// A color for frames that don't have any of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tint {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Names `--tint` knows, the first few being old monitor phosphors.
+const NAMED: [(&str, Tint); 9] = [
+    ("green", Tint { r: 0x33, g: 0xff, b: 0x33 }),
+    ("amber", Tint { r: 0xff, g: 0xb0, b: 0x00 }),
+    ("white", Tint { r: 0xf0, g: 0xf0, b: 0xf0 }),
+    ("red", Tint { r: 0xff, g: 0x33, b: 0x33 }),
+    ("yellow", Tint { r: 0xff, g: 0xff, b: 0x33 }),
+    ("blue", Tint { r: 0x55, g: 0x77, b: 0xff }),
+    ("magenta", Tint { r: 0xff, g: 0x33, b: 0xff }),
+    ("cyan", Tint { r: 0x33, g: 0xff, b: 0xff }),
+    ("gray", Tint { r: 0xa0, g: 0xa0, b: 0xa0 }),
+];
+
+/// The 16 basic colors as xterm draws them, for terminals without more.
+const BASIC: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+impl Tint {
+    /// A name, `#rrggbb`, or `r,g,b`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_lowercase();
+        if let Some((_, tint)) = NAMED.iter().find(|(name, _)| *name == s) {
+            return Some(*tint);
+        }
+
+        if let Some(hex) = s.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return None;
+            }
+            let channel = |i| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Self {
+                r: channel(0)?,
+                g: channel(2)?,
+                b: channel(4)?,
+            });
+        }
+
+        let mut channels = s.split(',').map(|c| c.trim().parse().ok());
+        let tint = Self {
+            r: channels.next()??,
+            g: channels.next()??,
+            b: channels.next()??,
+        };
+        channels.next().is_none().then_some(tint)
+    }
+
+    /// The SGR sequence for it, as close as `depth` gets.
+    fn sequence(self, depth: ColorDepth) -> String {
+        let Self { r, g, b } = self;
+        match depth {
+            ColorDepth::None => String::new(),
+            ColorDepth::Basic => {
+                let distance = |&(br, bg, bb): &(u8, u8, u8)| {
+                    [r.abs_diff(br), g.abs_diff(bg), b.abs_diff(bb)]
+                        .iter()
+                        .map(|&d| u32::from(d).pow(2))
+                        .sum::<u32>()
+                };
+                let index = (0..BASIC.len())
+                    .min_by_key(|&index| distance(&BASIC[index]))
+                    .unwrap_or(7);
+                let code = if index < 8 { 30 + index } else { 82 + index };
+                format!("\x1b[{code}m")
+            }
+            ColorDepth::Extended => {
+                let level = |c: u8| (u16::from(c) * 5 + 127) / 255;
+                let index = 16 + 36 * level(r) + 6 * level(g) + level(b);
+                format!("\x1b[38;5;{index}m")
+            }
+            ColorDepth::TrueColor => format!("\x1b[38;2;{r};{g};{b}m"),
+        }
+    }
+}
+
+/// Wraps a frame in the tint's color, unless it has colors of its own.
+pub fn tint(frame: Vec<u8>, tint: Tint, depth: ColorDepth) -> Vec<u8> {
+    if depth == ColorDepth::None || frame_colors(&frame) != ColorDepth::None {
+        return frame;
+    }
+    let mut tinted = tint.sequence(depth).into_bytes();
+    tinted.extend_from_slice(&frame);
+    tinted.extend_from_slice(b"\x1b[0m");
+    tinted
+}
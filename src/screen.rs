@@ -0,0 +1,330 @@
+// Draws frames by rewriting only the characters that changed since the
+// last one. A mostly still frame comes down to a handful of bytes, which
+// slow terminals (and ssh) are a lot happier with, and there's nothing
+// being rewritten to flicker. Once most of it changed, it's cheaper to
+// write the whole frame as it is.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write},
+    ops::Range,
+};
+
+use crossterm::terminal;
+
+/// Changed cells closer than this get written in one go, since moving the
+/// cursor over the gap would take about as many bytes.
+const MAX_GAP: usize = 4;
+
+#[derive(Default)]
+pub struct Screen {
+    /// Each line of the last frame. `None` for lines something else drew
+    /// over since.
+    lines: Vec<Option<Line>>,
+    /// The terminal's size when it was drawn, since resizing can move
+    /// things around.
+    size: Option<(u16, u16)>,
+    /// Every style seen so far, as the escape that sets it from scratch.
+    styles: Vec<Vec<u8>>,
+    ids: HashMap<Style, u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    style: u32,
+    c: char,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Line {
+    Cells(Vec<Cell>),
+    /// Has escapes that aren't colors, so it only ever gets drawn whole,
+    /// starting in `style`.
+    Raw {
+        style: u32,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Line {
+    fn width(&self) -> usize {
+        match self {
+            Self::Cells(cells) => cells.len(),
+            Self::Raw { bytes, .. } => bytes.len(),
+        }
+    }
+}
+
+impl Screen {
+    /// Makes the next frame get drawn whole.
+    pub fn invalidate(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Rows (from 0) drawn over by something else, to be drawn again.
+    pub fn damage(&mut self, rows: Range<usize>) {
+        let end = rows.end.min(self.lines.len());
+        if let Some(lines) = self.lines.get_mut(rows.start..end) {
+            lines.fill(None);
+        }
+    }
+
+    pub fn draw<W: Write>(
+        &mut self,
+        w: &mut W,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let size = terminal::size().ok();
+        if size != self.size {
+            self.size = size;
+            self.invalidate();
+        }
+
+        let lines = self.parse(frame);
+        let total: usize = lines.iter().map(Line::width).sum();
+        if self.lines.is_empty() || self.damaged(&lines) * 2 > total {
+            w.write_all(b"\x1b[H")?;
+            w.write_all(frame)?;
+            for (row, line) in lines.iter().enumerate() {
+                if let Some(Some(Line::Cells(old))) = self.lines.get(row)
+                    && let Line::Cells(cells) = line
+                    && old.len() > cells.len()
+                {
+                    clear_after(w, row, Some(cells.len()))?;
+                }
+            }
+        } else {
+            for (row, line) in lines.iter().enumerate() {
+                let old = self.lines.get(row).and_then(Option::as_ref);
+                self.update(w, row, line, old)?;
+            }
+        }
+        // A shorter frame leaves the old one's last lines behind.
+        for row in lines.len()..self.lines.len() {
+            write!(w, "\x1b[{};1H\x1b[0m\x1b[2K", row + 1)?;
+        }
+
+        self.lines = lines.into_iter().map(Some).collect();
+        Ok(())
+    }
+
+    /// How many cells have to be written again.
+    fn damaged(&self, lines: &[Line]) -> usize {
+        let mut damaged = 0;
+        for (row, line) in lines.iter().enumerate() {
+            damaged += match (line, self.lines.get(row)) {
+                (Line::Cells(cells), Some(Some(Line::Cells(old)))) => cells
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, cell)| old.get(i) != Some(cell))
+                    .count(),
+                (line, Some(Some(old))) if line == old => 0,
+                (line, _) => line.width(),
+            };
+        }
+        damaged
+    }
+
+    fn update<W: Write>(
+        &self,
+        w: &mut W,
+        row: usize,
+        line: &Line,
+        old: Option<&Line>,
+    ) -> io::Result<()> {
+        if old == Some(line) {
+            return Ok(());
+        }
+        let (cells, old) = match (line, old) {
+            (Line::Cells(cells), Some(Line::Cells(old))) => (cells, &old[..]),
+            (Line::Cells(cells), _) => (cells, &[][..]),
+            (Line::Raw { style, bytes }, _) => {
+                write!(w, "\x1b[{};1H", row + 1)?;
+                w.write_all(&self.styles[*style as usize])?;
+                w.write_all(bytes)?;
+                return clear_after(w, row, None);
+            }
+        };
+
+        // Changes close together get written as one run.
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        let changed =
+            (0..cells.len()).filter(|&i| old.get(i) != Some(&cells[i]));
+        for i in changed {
+            match runs.last_mut() {
+                Some(run) if i - run.end <= MAX_GAP => run.end = i + 1,
+                _ => runs.push(i..i + 1),
+            }
+        }
+        for run in runs {
+            self.write_cells(w, row, run.start, &cells[run])?;
+        }
+        // Whatever was there before has to go, which is everything when
+        // it was drawn over.
+        if old.len() > cells.len() || old.is_empty() {
+            clear_after(w, row, Some(cells.len()))?;
+        }
+        Ok(())
+    }
+
+    fn write_cells<W: Write>(
+        &self,
+        w: &mut W,
+        row: usize,
+        column: usize,
+        cells: &[Cell],
+    ) -> io::Result<()> {
+        write!(w, "\x1b[{};{}H", row + 1, column + 1)?;
+        let mut style = None;
+        let mut buffer = [0; 4];
+        for cell in cells {
+            if style != Some(cell.style) {
+                w.write_all(&self.styles[cell.style as usize])?;
+                style = Some(cell.style);
+            }
+            w.write_all(cell.c.encode_utf8(&mut buffer).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Splits a frame into cells, keeping track of the style each one is
+    /// in, since styles carry over from one line to the next.
+    fn parse(&mut self, frame: &[u8]) -> Vec<Line> {
+        let mut style = Style::default();
+        let mut lines = Vec::new();
+        for bytes in frame.split(|&b| b == b'\n') {
+            let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+            let start = self.id(&style);
+            let line = str::from_utf8(bytes)
+                .ok()
+                .and_then(|text| self.cells(text, &mut style));
+            lines.push(line.map_or_else(
+                || Line::Raw { style: start, bytes: bytes.to_vec() },
+                Line::Cells,
+            ));
+        }
+        lines
+    }
+
+    /// `None` if there's anything but colors and text in there.
+    fn cells(&mut self, text: &str, style: &mut Style) -> Option<Vec<Cell>> {
+        let mut cells = Vec::new();
+        let mut id = self.id(style);
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                if chars.next() != Some('[') {
+                    return None;
+                }
+                let mut parameters = String::new();
+                let command = chars.by_ref().find(|&c| {
+                    let end = ('@'..='~').contains(&c);
+                    if !end {
+                        parameters.push(c);
+                    }
+                    end
+                });
+                if command != Some('m') {
+                    return None;
+                }
+                style.apply(&parameters);
+                id = self.id(style);
+            } else if c.is_control() {
+                return None;
+            } else {
+                cells.push(Cell { style: id, c });
+            }
+        }
+        Some(cells)
+    }
+
+    fn id(&mut self, style: &Style) -> u32 {
+        if let Some(&id) = self.ids.get(style) {
+            return id;
+        }
+        let id = u32::try_from(self.styles.len()).unwrap_or(u32::MAX);
+        self.styles.push(style.escape());
+        self.ids.insert(style.clone(), id);
+        id
+    }
+}
+
+/// Clears the rest of a row from `column` (from 0) on, or from wherever
+/// the cursor is.
+fn clear_after<W: Write>(
+    w: &mut W,
+    row: usize,
+    column: Option<usize>,
+) -> io::Result<()> {
+    if let Some(column) = column {
+        write!(w, "\x1b[{};{}H", row + 1, column + 1)?;
+    }
+    w.write_all(b"\x1b[0m\x1b[K")
+}
+
+/// Whatever SGR escapes have set up so far.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+struct Style {
+    /// Bold, italic, reverse and so on, one bit each by their code.
+    attributes: u16,
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+impl Style {
+    fn apply(&mut self, parameters: &str) {
+        let mut codes = parameters.split(';');
+        while let Some(code) = codes.next() {
+            let number = if code.is_empty() { Ok(0) } else { code.parse() };
+            let Ok(number) = number else {
+                continue;
+            };
+            match number {
+                0 => *self = Self::default(),
+                1..=9 => self.attributes |= 1 << number,
+                22 => self.attributes &= !(1 << 1 | 1 << 2),
+                23..=29 => self.attributes &= !(1 << (number - 20)),
+                30..=37 | 90..=97 => self.foreground = Some(code.to_string()),
+                40..=47 | 100..=107 => self.background = Some(code.to_string()),
+                39 => self.foreground = None,
+                49 => self.background = None,
+                38 | 48 => {
+                    let color = match codes.next() {
+                        Some("5") => {
+                            codes.next().map(|i| format!("{code};5;{i}"))
+                        }
+                        Some("2") => {
+                            let rgb: Vec<_> = codes.by_ref().take(3).collect();
+                            Some(format!("{code};2;{}", rgb.join(";")))
+                        }
+                        _ => None,
+                    };
+                    if number == 38 {
+                        self.foreground = color;
+                    } else {
+                        self.background = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The escape that sets this style up from nothing.
+    fn escape(&self) -> Vec<u8> {
+        let mut escape = String::from("\x1b[0");
+        for code in 1..=9 {
+            if self.attributes & (1 << code) != 0 {
+                let _ = write!(escape, ";{code}");
+            }
+        }
+        for color in [&self.foreground, &self.background].into_iter().flatten()
+        {
+            escape.push(';');
+            escape.push_str(color);
+        }
+        escape.push('m');
+        escape.into_bytes()
+    }
+}
@@ -0,0 +1,135 @@
+// `--screensaver`, for terminals left alone: once no key has been pressed
+// for `--idle`, random .bapple files from a directory play one after
+// another until one is, and whatever was on the screen comes back. Then it
+// waits again, until quit with q.
+//
+// With `--idle 0` it starts right away and exits on the first key, which
+// is what tmux wants from a lock-command.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::stdout,
+    path::{Path, PathBuf},
+    slice,
+    sync::atomic::Ordering,
+};
+
+use crossterm::{
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+};
+use rodio::OutputStream;
+
+use crate::{
+    Res, STOP, configure,
+    controls::{Controls, Keyboard, Osd, Repeat},
+    decrypt,
+    playlist::{self, Item, Options, Shuffle},
+    preload,
+    primitives::Args,
+    terminal::Capabilities,
+    timeline::format_time,
+    verify,
+};
+
+pub fn run(dir: &Path, args: &Args, capabilities: Capabilities) -> Res<()> {
+    let files = list(dir)?;
+    if files.is_empty() {
+        return Err(format!("No .bapple files in {}", dir.display()).into());
+    }
+    // The alternate screen stays up between files, so what was there
+    // doesn't flash through.
+    let alt_screen = capabilities.alt_screen;
+    let capabilities = Capabilities { alt_screen: false, ..capabilities };
+    let mut shuffle = Shuffle::new(Shuffle::random_seed());
+    // Shared by every file, like when playing a playlist.
+    let mut output = None;
+
+    loop {
+        if !args.idle.is_zero() {
+            let keyboard =
+                Keyboard::new().ok_or("--screensaver needs a terminal")?;
+            println!(
+                "Starting after {} without a key press, q to quit",
+                format_time(args.idle)
+            );
+            if !keyboard.wait_idle(args.idle) {
+                return Ok(());
+            }
+        }
+
+        if alt_screen {
+            execute!(stdout(), EnterAlternateScreen)?;
+        }
+        let played =
+            play(&files, args, capabilities, &mut shuffle, &mut output);
+        if alt_screen {
+            execute!(stdout(), LeaveAlternateScreen)?;
+        }
+        played?;
+
+        if STOP.load(Ordering::Relaxed) || args.idle.is_zero() {
+            return Ok(());
+        }
+    }
+}
+
+/// Plays files in random order until a key gets pressed.
+fn play(
+    files: &[PathBuf],
+    args: &Args,
+    capabilities: Capabilities,
+    shuffle: &mut Shuffle,
+    output: &mut Option<OutputStream>,
+) -> Res<()> {
+    let mut controls = Controls::new(Repeat::None, Osd::Off);
+    controls.screensaver = true;
+    let mut queue = VecDeque::new();
+    let mut last = None;
+
+    loop {
+        // Everything plays once before anything plays again.
+        if queue.is_empty() {
+            queue = playlist::pass(files.len(), Some(&mut *shuffle), last);
+        }
+        let Some(index) = queue.pop_front() else {
+            return Ok(());
+        };
+        let item =
+            Item { path: files[index].clone(), options: Options::default() };
+        // Checked like anything else that gets played, and kept until
+        // it's done playing.
+        let items = slice::from_ref(&item);
+        let (paths, _decrypted) = decrypt(items, args)?;
+        if args.verify || args.require_signed {
+            verify(items, &paths, args)?;
+        }
+        let mut bapple = preload(&paths[0], args)
+            .join()
+            .expect("loading thread panicked")?;
+        configure(&mut bapple, args, &item, capabilities)?;
+        bapple.play(output, &mut controls)?;
+
+        if controls.woken || STOP.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        last = Some(index);
+    }
+}
+
+/// Every .bapple file in `dir`.
+fn list(dir: &Path) -> Res<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file()
+            && path.extension().is_some_and(|extension| extension == "bapple")
+        {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,635 @@
+// `bplay serve`, playing a file for whoever connects over a WebSocket, all
+// of them watching the same playback. Every frame goes out as a binary
+// message with what a terminal would get to draw it, after a text one
+// saying where playback is, in JSON:
+//
+//   {"frame":12,"time":0.4,"duration":219.0,"at":1760600000.25}
+//
+// `at` is when to show it, in seconds since 1970 UTC going by the clock
+// here, a little after it gets sent. Clients that go by it instead of
+// showing frames as they come in stay within a frame of each other, however
+// far apart they are. To know how far off their own clock is, they send
+//
+//   {"ping":123.4}
+//
+// every so often, with whatever number they like, and get back
+//
+//   {"pong":123.4,"server":1760600000.1}
+//
+// with the time here when it was answered. Half the round trip later than
+// that is what time it is here, and the answers that took the least time
+// to come back are the ones to go by.
+//
+// The first message a client gets says what's playing, and how big the
+// terminal to draw it in has to be:
+//
+//   {"title":"Bad Apple!!","frames":6572,"duration":219.0,
+//    "width":80,"height":30}
+//
+// Clients that can't keep up miss frames instead of falling behind. There's
+// no audio, and it starts over at the end, until stopped with Ctrl+C. Only
+// so many get to connect at once, and pages on other sites than this one
+// only with `--allow-origin`.
+// Playlists get played through in order, each item's first message going
+// out to everyone when it starts.
+//
+// With `--web`, browsers that ask for the page get one that plays it in
+// xterm.js (see `serve.html`), for people without a terminal. With
+// `--control`, playback can be driven over HTTP (see `control`), and with
+// `--metrics`, Prometheus can scrape `/metrics` (see `metrics`).
+
+use std::{
+    io::{self, BufReader, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Res, STOP,
+    archive::Limits,
+    clock::since_epoch,
+    control::{self, State},
+    frames::Loading,
+    metrics::Metrics,
+    playlist::{self, Item},
+    primitives::Bapple,
+    render::strip_escapes,
+    terminal::{Capabilities, ColorDepth},
+    websocket::{self, Message, Received, Request},
+};
+
+/// How many frames a client can fall behind before it misses some.
+const QUEUE: usize = 8;
+/// How many connections there can be at once, each taking up a thread or
+/// two.
+const MAX_CONNECTIONS: usize = 128;
+/// How long a client gets to send its request, or take a frame.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long after frames get sent they're meant to be shown, time enough
+/// for them to get anywhere.
+const LEAD: Duration = Duration::from_millis(250);
+/// How often to check whether it's been unpaused.
+const PAUSED: Duration = Duration::from_millis(50);
+/// What `--web` serves to browsers.
+const PAGE: &str = include_str!("serve.html");
+const TEXT: &str = "Content-Type: text/plain\r\n";
+const JSON: &str = "Content-Type: application/json\r\n";
+const PROMETHEUS: &str = "Content-Type: text/plain; version=0.0.4\r\n";
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Path to a .bapple file, or a playlist
+    pub file: PathBuf,
+    /// Where to listen, like 127.0.0.1:8080 (or :8080), or 0.0.0.0:8080 for
+    /// everywhere
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "127.0.0.1:8080",
+        value_parser = parse_address
+    )]
+    pub listen: SocketAddr,
+    /// Listen here instead, also serving a page to watch it in a browser
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        conflicts_with = "listen"
+    )]
+    pub web: Option<SocketAddr>,
+    /// Also take /play, /pause, /seek, /status and /playlist requests
+    #[arg(long)]
+    pub control: bool,
+    /// Only take --control requests with Authorization: Bearer TOKEN
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "BAPPLE_CONTROL_TOKEN",
+        hide_env_values = true,
+        requires = "control"
+    )]
+    pub control_token: Option<String>,
+    /// Also answer /metrics requests, for Prometheus
+    #[arg(long)]
+    pub metrics: bool,
+    /// Let pages from ORIGIN (a scheme and host) connect too, on top of the
+    /// ones served from this one
+    #[arg(long, value_name = "ORIGIN")]
+    pub allow_origin: Vec<String>,
+}
+
+/// Messages waiting to be sent to a client, in order.
+type Client = SyncSender<Arc<Vec<Message>>>;
+
+/// What the player and every connection share.
+struct Shared {
+    clients: Mutex<Vec<Client>>,
+    /// What clients get first, for whatever's playing.
+    hello: Mutex<String>,
+    state: Mutex<State>,
+    /// The names of what's in the playlist.
+    items: Vec<String>,
+    metrics: Option<Metrics>,
+    web: bool,
+    control: bool,
+    control_token: Option<String>,
+    allow_origin: Vec<String>,
+    connections: AtomicUsize,
+    /// Where it's listening.
+    address: SocketAddr,
+}
+
+pub fn run(args: &ServeArgs) -> Res<()> {
+    let items = playlist::load(&args.file)?;
+    let mut index = 0;
+    let mut bapple = load(&items[index].path)?;
+
+    let address = args.web.unwrap_or(args.listen);
+    if args.control
+        && args.control_token.is_none()
+        && !address.ip().is_loopback()
+    {
+        let why = "--control anywhere but on this machine needs a \
+                   --control-token, or anyone there could use it";
+        return Err(format!("{address}: {why}").into());
+    }
+    let listener =
+        TcpListener::bind(address).map_err(|e| format!("{address}: {e}"))?;
+    let shared = Arc::new(Shared {
+        clients: Mutex::new(Vec::new()),
+        hello: Mutex::new(hello(&mut bapple)?),
+        state: Mutex::new(State::default()),
+        items: items.iter().map(Item::name).collect(),
+        metrics: args.metrics.then(Metrics::default),
+        web: args.web.is_some(),
+        control: args.control,
+        control_token: args.control_token.clone(),
+        allow_origin: args.allow_origin.clone(),
+        connections: AtomicUsize::new(0),
+        address,
+    });
+    {
+        let shared = shared.clone();
+        thread::spawn(move || accept(&listener, &shared));
+    }
+
+    let scheme = if args.web.is_some() { "http" } else { "ws" };
+    println!("Serving {} on {scheme}://{address}", args.file.display());
+    loop {
+        begin(&mut bapple, index, &shared)?;
+        let skip = play(&mut bapple, &shared)?;
+        if STOP.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let next = skip.unwrap_or((index + 1) % items.len());
+        // Playing the same one again doesn't need it read again.
+        if next != index {
+            index = next;
+            bapple = load(&items[index].path)?;
+        }
+    }
+}
+
+fn load(path: &Path) -> Res<Bapple> {
+    let mut bapple = Bapple::new(path, Loading::All, Limits::default())?;
+    if bapple.length() == 0 {
+        let path = path.display();
+        return Err(format!("{path}: there are no frames to serve").into());
+    }
+    // Or it'd send them all as fast as it can.
+    if bapple.timeline().is_zero() {
+        let path = path.display();
+        let why = "there's no telling how long frames last";
+        return Err(format!("{path}: {why}").into());
+    }
+    // Browsers show everything.
+    bapple.set_capabilities(Capabilities {
+        color: ColorDepth::TrueColor,
+        unicode: true,
+        ..Capabilities::default()
+    });
+    Ok(bapple)
+}
+
+/// The first message clients get, saying what's playing.
+#[derive(Serialize)]
+struct Hello<'a> {
+    title: &'a str,
+    frames: usize,
+    /// In seconds, like the times in `Position`.
+    duration: f64,
+    width: usize,
+    height: usize,
+}
+
+/// What goes out before every frame.
+#[derive(Serialize)]
+struct Position {
+    frame: usize,
+    time: f64,
+    duration: f64,
+    at: f64,
+}
+
+/// What clients send to know how far off their clock is.
+#[derive(Deserialize)]
+struct Ping {
+    ping: f64,
+}
+
+/// The answer, with the time here.
+#[derive(Serialize)]
+struct Pong {
+    pong: f64,
+    server: f64,
+}
+
+fn hello(bapple: &mut Bapple) -> Res<String> {
+    let (width, height) = size(&bapple.snapshot(0)?);
+    let hello = Hello {
+        title: bapple.title(),
+        frames: bapple.length(),
+        duration: bapple.timeline().duration().as_secs_f64(),
+        width,
+        height,
+    };
+    Ok(serde_json::to_string(&hello)?)
+}
+
+/// Tells everyone, already there or yet to come, what's about to play.
+fn begin(bapple: &mut Bapple, index: usize, shared: &Shared) -> Res<()> {
+    let hello = hello(bapple)?;
+    lock(&shared.hello).clone_from(&hello);
+    broadcast(shared, &Arc::new(vec![Message::Text(hello)]));
+
+    let mut state = lock(&shared.state);
+    state.item = index;
+    state.title = bapple.title().to_string();
+    state.frame = 0;
+    state.time = Duration::ZERO;
+    state.duration = bapple.timeline().duration();
+    Ok(())
+}
+
+/// Plays it through once, sending every frame to every client. Gives
+/// back which item to play instead, if it got asked to.
+fn play(bapple: &mut Bapple, shared: &Shared) -> Res<Option<usize>> {
+    // When the first frame would have gone out.
+    let mut origin = Instant::now();
+    let mut index = 0;
+    while index < bapple.length() {
+        if STOP.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let (paused, seek) = {
+            let mut state = lock(&shared.state);
+            if let Some(item) = state.skip.take() {
+                return Ok(Some(item));
+            }
+            (state.paused, state.seek.take())
+        };
+        if let Some(to) = seek {
+            index = bapple.timeline().frame_at(to).min(bapple.length() - 1);
+        }
+        let start = bapple.timeline().start(index);
+        // Carries on from here, instead of catching up.
+        if paused || seek.is_some() {
+            origin =
+                Instant::now().checked_sub(start).unwrap_or_else(Instant::now);
+        }
+        if paused {
+            // So it's seen where it went.
+            if seek.is_some() {
+                send(bapple, index, shared)?;
+            }
+            thread::sleep(PAUSED);
+            continue;
+        }
+
+        thread::sleep(start.saturating_sub(origin.elapsed()));
+        send(bapple, index, shared)?;
+        index += 1;
+    }
+    Ok(None)
+}
+
+/// Sends a frame to every client, along with where it is.
+fn send(bapple: &mut Bapple, index: usize, shared: &Shared) -> Res<()> {
+    let start = bapple.timeline().start(index);
+    {
+        let mut state = lock(&shared.state);
+        state.frame = index;
+        state.time = start;
+    }
+    // Nobody to render it for.
+    if lock(&shared.clients).is_empty() {
+        return Ok(());
+    }
+
+    let time = serde_json::to_string(&Position {
+        frame: index,
+        time: start.as_secs_f64(),
+        duration: bapple.timeline().duration().as_secs_f64(),
+        at: (since_epoch() + LEAD).as_secs_f64(),
+    })?;
+    let decoding = Instant::now();
+    let frame = bapple.snapshot(index)?;
+    if let Some(metrics) = &shared.metrics {
+        metrics.decode.observe(decoding.elapsed());
+        metrics.frames.fetch_add(1, Ordering::Relaxed);
+    }
+    let frame = to_terminal(&frame);
+    let messages = Arc::new(vec![Message::Text(time), Message::Binary(frame)]);
+    broadcast(shared, &messages);
+    Ok(())
+}
+
+/// Ones that left get dropped, ones that are behind miss it.
+fn broadcast(shared: &Shared, messages: &Arc<Vec<Message>>) {
+    lock(&shared.clients).retain(|client| {
+        match client.try_send(messages.clone()) {
+            Err(TrySendError::Disconnected(_)) => false,
+            Err(TrySendError::Full(_)) => {
+                count(shared, |metrics| &metrics.dropped, 1);
+                true
+            }
+            Ok(()) => true,
+        }
+    });
+}
+
+/// Adds to one of the metrics, if they're being kept.
+fn count(shared: &Shared, which: impl Fn(&Metrics) -> &AtomicU64, n: u64) {
+    if let Some(metrics) = &shared.metrics {
+        which(metrics).fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+fn accept(listener: &TcpListener, shared: &Arc<Shared>) {
+    for mut stream in listener.incoming().flatten() {
+        let connections = &shared.connections;
+        if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            let why = "Too many connections, try again later\n";
+            let status = "503 Service Unavailable";
+            let _ = respond(&mut stream, status, TEXT, why);
+            continue;
+        }
+        let shared = shared.clone();
+        // Clients that go wrong just get hung up on.
+        thread::spawn(move || {
+            let _ = connect(stream, &shared);
+            shared.connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Does the handshake, then sends whatever gets played until the client
+/// leaves. With `--web`, requests for anything but a WebSocket get the
+/// page, and with `--control` and `--metrics`, ones for those get
+/// answered.
+fn connect(stream: TcpStream, shared: &Shared) -> Res<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let request = websocket::read_request(&mut reader)?;
+    if let Some(metrics) = &shared.metrics
+        && request.path == "/metrics"
+    {
+        return respond(&mut writer, "200 OK", PROMETHEUS, &metrics.render());
+    }
+    if shared.control
+        && let Some(response) = control::handle(
+            &request,
+            shared.address,
+            shared.control_token.as_deref(),
+            &shared.state,
+            &shared.items,
+        )
+    {
+        return respond(&mut writer, response.status, JSON, &response.body);
+    }
+    if request.path != "/" {
+        let why = "Nothing here\n";
+        return respond(&mut writer, "404 Not Found", TEXT, why);
+    }
+    let Some(key) = &request.key else {
+        if shared.web {
+            let html = "Content-Type: text/html; charset=utf-8\r\n";
+            return respond(&mut writer, "200 OK", html, PAGE);
+        }
+        let why = "bplay serve only speaks WebSocket, try --web\n";
+        let headers = format!("Upgrade: websocket\r\n{TEXT}");
+        return respond(&mut writer, "426 Upgrade Required", &headers, why);
+    };
+    if !allowed(&request, shared.address, &shared.allow_origin) {
+        let why = "Only for this host, and pages on other sites need \
+                   --allow-origin\n";
+        return respond(&mut writer, "403 Forbidden", TEXT, why);
+    }
+    websocket::accept(&mut writer, key)?;
+
+    let (sender, receiver) = sync_channel(QUEUE);
+    let pongs = sender.clone();
+    // Both at once, so it can't miss the start of another item.
+    {
+        let mut clients = lock(&shared.clients);
+        let hello = lock(&shared.hello).clone();
+        sender.send(Arc::new(vec![Message::Text(hello)]))?;
+        clients.push(sender);
+    }
+
+    // Clients can stay quiet for as long as they like.
+    writer.set_read_timeout(None)?;
+    let leaving = writer.try_clone()?;
+    thread::spawn(move || {
+        loop {
+            let text = match websocket::receive(&mut reader) {
+                Ok(Some(Received::Text(text))) => text,
+                // Waiting for room, since these have to be answered.
+                Ok(Some(Received::Ping(data))) => {
+                    let answer = Arc::new(vec![Message::Pong(data)]);
+                    if pongs.send(answer).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                // Saying why, then hanging up once that's gone out.
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    let close = Message::Close(websocket::PROTOCOL_ERROR);
+                    let _ = pongs.send(Arc::new(vec![close]));
+                    return;
+                }
+                Ok(None) | Err(_) => break,
+            };
+            let Some(number) = ping(&text) else {
+                continue;
+            };
+            let pong =
+                Pong { pong: number, server: since_epoch().as_secs_f64() };
+            let Ok(pong) = serde_json::to_string(&pong) else {
+                continue;
+            };
+            // One that doesn't fit can be asked for again.
+            let _ = pongs.try_send(Arc::new(vec![Message::Text(pong)]));
+        }
+        let _ = leaving.shutdown(Shutdown::Both);
+    });
+    count(shared, |metrics| &metrics.clients, 1);
+    let sent = forward(&mut writer, &receiver, shared);
+    if let Some(metrics) = &shared.metrics {
+        metrics.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+    sent
+}
+
+/// Sends the client whatever gets played, until it leaves.
+fn forward(
+    writer: &mut TcpStream,
+    receiver: &Receiver<Arc<Vec<Message>>>,
+    shared: &Shared,
+) -> Res<()> {
+    for messages in receiver {
+        for message in messages.iter() {
+            let written = websocket::send(writer, message)?;
+            count(shared, |metrics| &metrics.bytes, written as u64);
+            if let Message::Close(_) = message {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Answers a request that isn't going to become a WebSocket, `headers`
+/// each ending with a CRLF.
+fn respond(
+    w: &mut impl Write,
+    status: &str,
+    headers: &str,
+    body: &str,
+) -> Res<()> {
+    write!(
+        w,
+        "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// Whether a page on the site `request` comes from can connect, to the
+/// server listening on `address`.
+fn allowed(
+    request: &Request,
+    address: SocketAddr,
+    allow_origin: &[String],
+) -> bool {
+    let site = request.same_origin()
+        || request.origin.as_ref().is_some_and(|origin| {
+            allow_origin
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        });
+    request.to_here(address) && site
+}
+
+/// The number in `{"ping":123.4}`.
+fn ping(text: &str) -> Option<f64> {
+    serde_json::from_str::<Ping>(text).ok().map(|ping| ping.ping)
+}
+
+/// How many columns and rows a frame takes up.
+fn size(frame: &[u8]) -> (usize, usize) {
+    let text = String::from_utf8_lossy(&strip_escapes(frame)).into_owned();
+    let width = text.lines().map(|line| line.chars().count()).max();
+    (width.unwrap_or_default(), text.lines().count())
+}
+
+/// A frame as a terminal that doesn't turn `\n` into `\r\n` would need
+/// it, drawn from the top left corner.
+fn to_terminal(frame: &[u8]) -> Vec<u8> {
+    let mut output = b"\x1b[H".to_vec();
+    for &byte in frame {
+        if byte == b'\n' {
+            output.push(b'\r');
+        }
+        output.push(byte);
+    }
+    output
+}
+
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// An address to listen on, where `:8080` means on this machine only, like
+/// the default. Every interface has to be asked for with `0.0.0.0`.
+fn parse_address(s: &str) -> Result<SocketAddr, String> {
+    let address = if s.starts_with(':') {
+        format!("127.0.0.1{s}")
+    } else {
+        s.to_string()
+    };
+    address
+        .to_socket_addrs()
+        .map_err(|e| format!("{e}"))?
+        .next()
+        .ok_or_else(|| format!("{s} isn't anywhere"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listens_locally_unless_told_otherwise() {
+        let local = parse_address(":8080").unwrap();
+        assert_eq!(local, "127.0.0.1:8080".parse().unwrap());
+        let everywhere = parse_address("0.0.0.0:8080").unwrap();
+        assert!(everywhere.ip().is_unspecified());
+    }
+
+    #[test]
+    fn reads_pings() {
+        assert_eq!(ping(r#"{"ping":123.4}"#), Some(123.4));
+        assert_eq!(ping(r#" { "ping" : 5 } "#), Some(5.0));
+        assert_eq!(ping(r#"{"pong":1}"#), None);
+        assert_eq!(ping(r#"{"ping":NaN}"#), None);
+        assert_eq!(ping(r#"{"ping":"5"}"#), None);
+    }
+
+    #[test]
+    fn lets_in_pages_from_here_and_allowed_sites() {
+        let request = |host: &str, origin: Option<&str>| Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            key: Some("dGhlIHNhbXBsZSBub25jZQ==".to_string()),
+            host: Some(host.to_string()),
+            origin: origin.map(str::to_string),
+            authorization: None,
+        };
+        let here = "127.0.0.1:8080".parse().unwrap();
+        let allowed =
+            |request, allow: &[String]| allowed(&request, here, allow);
+        let allow = ["https://example.com".to_string()];
+        let page = |origin| request("localhost:8080", Some(origin));
+        assert!(allowed(request("localhost:8080", None), &[]));
+        assert!(allowed(page("http://localhost:8080"), &[]));
+        assert!(allowed(page("https://example.com"), &allow));
+        assert!(!allowed(page("https://evil.example"), &allow));
+        // Pointed here by a name they own, so it looks like the same site.
+        let origin = Some("http://evil.example:8080");
+        assert!(!allowed(request("evil.example:8080", origin), &[]));
+    }
+}
@@ -0,0 +1,245 @@
+// Signing .bapple files, so a player can be told to only play what some
+// key signed off on. The signature is an ed25519 one over the manifest
+// as it's stored, which in turn has the hashes of everything else, and
+// goes in a `manifest.sig` entry right after it. `bplay keygen` makes a
+// key pair and `bplay sign` signs a file with it; anything that changes
+// the file afterwards drops the signature.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{entry_name, is_manifest, refuse_encrypted, write_signature},
+    manifest::{hex, unhex},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct SignArgs {
+    /// The .bapple file to sign
+    pub file: PathBuf,
+    /// The secret key to sign it with, as made by `bplay keygen`
+    #[arg(short, long)]
+    pub key: PathBuf,
+    /// Where to write the result, instead of changing the file in place
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// What's in a `manifest.sig` entry.
+#[derive(Serialize, Deserialize)]
+pub struct Signature {
+    /// The public key to check it with, in hex.
+    pub key: String,
+    /// In hex too.
+    pub signature: String,
+}
+
+impl Signature {
+    /// Signs `manifest`, as it's stored, with a secret key.
+    pub fn new(secret: &[u8; 32], manifest: &[u8]) -> Self {
+        let secret = SigningKey::from_bytes(secret);
+        Self {
+            key: hex(secret.verifying_key().as_bytes()),
+            signature: hex(&secret.sign(manifest).to_bytes()),
+        }
+    }
+
+    pub fn parse(content: &[u8]) -> Res<Self> {
+        Ok(ron::from_str(str::from_utf8(content)?)?)
+    }
+
+    /// The key it was made with, if it's right for `manifest`.
+    pub fn check(&self, manifest: &[u8]) -> Option<[u8; 32]> {
+        let key = unhex(&self.key)?;
+        let signature =
+            ed25519_dalek::Signature::from_bytes(&unhex(&self.signature)?);
+        // Strict, so there's only ever one signature that passes.
+        VerifyingKey::from_bytes(&key)
+            .ok()?
+            .verify_strict(manifest, &signature)
+            .is_ok()
+            .then_some(key)
+    }
+}
+
+/// `bplay keygen`, writing a new secret key to `path` and the public key
+/// next to it, with `.pub` on the end.
+pub fn keygen(path: &Path) -> Res<()> {
+    let mut secret = [0; 32];
+    getrandom::fill(&mut secret).map_err(|e| e.to_string())?;
+    let public =
+        hex(SigningKey::from_bytes(&secret).verifying_key().as_bytes());
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // Nobody else has any business reading it.
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file =
+        options.open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    writeln!(file, "{}", hex(&secret))?;
+    let public_path = public_path(path);
+    fs::write(&public_path, format!("{public}\n"))?;
+
+    println!("Wrote the secret key to {}", path.display());
+    println!("and the public key to {}:", public_path.display());
+    println!("{public}");
+    Ok(())
+}
+
+/// `bplay sign`.
+pub fn run(args: &SignArgs) -> Res<()> {
+    let secret = read_key(&args.key)?;
+    refuse_encrypted(&args.file)?;
+    let manifest = read_manifest(&args.file)?.ok_or_else(|| {
+        format!(
+            "{}: has no hashes stored in it to sign, encode it again with \
+             a newer bplay",
+            args.file.display()
+        )
+    })?;
+
+    let signature = Signature::new(&secret, &manifest);
+    let output = args.output.as_ref().unwrap_or(&args.file);
+    let serialized = ron::to_string(&signature)?;
+    write_signature(&args.file, output, serialized.as_bytes())?;
+    println!("Signed {} with {}", output.display(), signature.key);
+    Ok(())
+}
+
+/// A public key, given right there in hex or as a file that has it, like
+/// the `.pub` one `bplay keygen` writes.
+pub fn parse_key(s: &str) -> Result<[u8; 32], String> {
+    if let Some(key) = unhex(s) {
+        return Ok(key);
+    }
+    let content = fs::read_to_string(s).map_err(|e| format!("{s}: {e}"))?;
+    unhex(content.trim()).ok_or_else(|| format!("{s}: not a public key"))
+}
+
+fn read_key(path: &Path) -> Res<[u8; 32]> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    unhex(content.trim())
+        .ok_or_else(|| format!("{}: not a secret key", path.display()).into())
+}
+
+/// The manifest as it's stored, which is what gets signed.
+pub fn read_manifest(path: &Path) -> Res<Option<Vec<u8>>> {
+    let mut archive = Archive::new(File::open(path)?);
+    let mut found = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry_name(&entry)?.is_some_and(|name| is_manifest(&name)) {
+            // Or which one got signed would depend on who's reading it.
+            if found.is_some() {
+                let path = path.display();
+                return Err(
+                    format!("{path}: has more than one manifest").into()
+                );
+            }
+            let mut manifest = Vec::new();
+            entry.read_to_end(&mut manifest)?;
+            found = Some(manifest);
+        }
+    }
+
+    Ok(found)
+}
+
+fn public_path(path: &Path) -> PathBuf {
+    let mut public = path.as_os_str().to_owned();
+    public.push(".pub");
+    public.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first three from RFC 8032, section 7.1: secret key, public key,
+    /// message and signature.
+    const VECTORS: [[&str; 4]; 3] = [
+        [
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ],
+        [
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ],
+        [
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac\
+             18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ],
+    ];
+
+    /// The order of the group, which `S` has to be below.
+    const L: [u8; 32] = [
+        0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2,
+        0xde, 0xf9, 0xde, 0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0x10,
+    ];
+
+    fn unhex_vec(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rfc_8032_vectors() {
+        for [secret, public, message, signature] in VECTORS {
+            let made =
+                Signature::new(&unhex(secret).unwrap(), &unhex_vec(message));
+            assert_eq!(made.key, public);
+            assert_eq!(made.signature, signature);
+            assert_eq!(
+                made.check(&unhex_vec(message)),
+                Some(unhex(public).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        let [secret, _, _, _] = VECTORS[2];
+        let signature = Signature::new(&unhex(secret).unwrap(), b"manifest");
+        assert!(signature.check(b"manifesT").is_none());
+    }
+
+    #[test]
+    fn rejects_non_canonical_s() {
+        let [_, public, message, signature] = VECTORS[0];
+        let mut bytes: [u8; 64] = unhex(signature).unwrap();
+        // S + L checks out as well, unless it's refused for being too big.
+        let mut carry = 0;
+        for (byte, l) in bytes[32..].iter_mut().zip(L) {
+            let sum = u16::from(*byte) + u16::from(l) + carry;
+            *byte = sum.to_le_bytes()[0];
+            carry = sum >> 8;
+        }
+        let changed =
+            Signature { key: public.to_string(), signature: hex(&bytes) };
+        assert!(changed.check(&unhex_vec(message)).is_none());
+    }
+}
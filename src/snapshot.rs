@@ -0,0 +1,126 @@
+// Frames rendered just like playback would draw them, but with no terminal
+// or clock involved, so the same archive always comes out as the same
+// bytes. For comparing output across versions, whether that's an encoder
+// checking what it wrote or a set of golden files.
+
+use std::path::Path;
+
+use crate::{
+    Res,
+    archive::Limits,
+    frames::Loading,
+    primitives::Bapple,
+    terminal::{Capabilities, ColorDepth},
+};
+
+/// Frame `index` (from 0) of the archive at `path`, as it would be drawn
+/// on a terminal with true color and Unicode, without any filters or
+/// subtitles. Frames that decompress to more than `max_size` bytes are
+/// refused, like playback would on a terminal that small.
+///
+/// # Errors
+///
+/// If the archive can't be read, or doesn't have that frame.
+pub fn render_frame(
+    path: &Path,
+    index: usize,
+    max_size: usize,
+) -> Res<Vec<u8>> {
+    let mut bapple = Bapple::new(path, Loading::All, Limits::default())?;
+    bapple.set_capabilities(Capabilities {
+        color: ColorDepth::TrueColor,
+        unicode: true,
+        ..Capabilities::default()
+    });
+    bapple.set_max_frame_size(max_size);
+    bapple.snapshot(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::ArchiveWriter,
+        filter::Settings,
+        metadata::Metadata,
+        render::{Levels, Tint},
+        temp::TempPath,
+    };
+
+    const FRAMES: [&str; 3] = [
+        "\x1b[38;2;255;0;0m##\x1b[0m  \n  \x1b[38;2;0;0;255m##\x1b[0m\n",
+        "\u{2588}\u{2588}\u{2593}\u{2592}\n\u{2591}  \u{2588}\n",
+        " .:#\n#:. \n",
+    ];
+
+    fn file() -> TempPath {
+        let file = TempPath::new();
+        let mut writer = ArchiveWriter::create(&file).unwrap();
+        let metadata = Metadata {
+            frametime: 40_000,
+            charset: Some(" .:#".to_string()),
+            ..Default::default()
+        };
+        writer.append_metadata(&metadata).unwrap();
+        for frame in FRAMES {
+            let frame = zstd::encode_all(frame.as_bytes(), 0).unwrap();
+            writer.append_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+        file
+    }
+
+    /// Frame `index` the way playback would draw it on a terminal with
+    /// `color` and `unicode`, through `filters`.
+    fn render(
+        path: &Path,
+        index: usize,
+        color: ColorDepth,
+        unicode: bool,
+        filters: Settings,
+    ) -> String {
+        let mut bapple =
+            Bapple::new(path, Loading::All, Limits::default()).unwrap();
+        bapple.set_capabilities(Capabilities {
+            color,
+            unicode,
+            ..Capabilities::default()
+        });
+        bapple.set_filters(filters);
+        String::from_utf8(bapple.snapshot(index).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_like_the_golden_output() {
+        let file = file();
+        for (index, golden) in FRAMES.iter().enumerate() {
+            let frame = render_frame(&file, index, 1024).unwrap();
+            assert_eq!(String::from_utf8(frame).unwrap(), *golden);
+        }
+
+        let inverted = Settings {
+            levels: Levels { invert: true, ..Levels::default() },
+            tint: Tint::parse("green"),
+            ..Settings::default()
+        };
+        assert_eq!(
+            render(&file, 2, ColorDepth::Extended, true, inverted),
+            include_str!("snapshot/inverted_green.ans")
+        );
+        let plain = Settings::default();
+        assert_eq!(
+            render(&file, 0, ColorDepth::None, false, plain.clone()),
+            include_str!("snapshot/no_color.txt")
+        );
+        assert_eq!(
+            render(&file, 1, ColorDepth::None, false, plain),
+            include_str!("snapshot/ascii.txt")
+        );
+    }
+
+    #[test]
+    fn refuses_frames_over_the_size() {
+        assert!(render_frame(&file(), 0, 8).is_err());
+        assert!(render_frame(&file(), FRAMES.len(), 1024).is_err());
+    }
+}
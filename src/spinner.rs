@@ -0,0 +1,58 @@
+// Something moving on stderr during the slow parts before playback, so a
+// big file doesn't look like it hung. Cleared again once it's dropped.
+
+use std::{
+    io::{IsTerminal, Write, stderr},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const ASCII_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+const INTERVAL: Duration = Duration::from_millis(80);
+
+pub struct Spinner {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Spins next to `phase` until dropped. Nothing gets drawn unless
+    /// stderr is a terminal.
+    pub fn start(phase: &str, unicode: bool) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = stderr().is_terminal().then(|| {
+            let done = done.clone();
+            let phase = phase.to_string();
+            thread::spawn(move || {
+                let frames: &[char] =
+                    if unicode { &FRAMES } else { &ASCII_FRAMES };
+                for frame in frames.iter().cycle() {
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    eprint!("\r\x1b[2K{frame} {phase}...");
+                    let _ = stderr().flush();
+                    // Woken up early when it's dropped.
+                    thread::park_timeout(INTERVAL);
+                }
+                eprint!("\r\x1b[2K");
+            })
+        });
+        Self { done, thread }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// `bplay split`, cutting a .bapple file into pieces that each play on
+// their own: into a number of equally long ones, or one every so often.
+// Cuts land on frame boundaries. The audio gets cut at the same points,
+// and subtitles, speed regions and episodes go to whichever piece they're
+// in, cut short where they run over.
+
+use std::{
+    fs,
+    io::Cursor,
+    iter::repeat,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rodio::{Decoder, Source, source::UniformSourceIterator};
+
+use crate::{
+    Res,
+    archive::{ArchiveWriter, Contents},
+    encode::audio::to_mp3,
+    metadata::{Episode, Metadata, SpeedRegion},
+    timeline::{Timeline, parse_time},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct SplitArgs {
+    /// The .bapple file to split
+    pub file: PathBuf,
+    /// How many equally long pieces to cut it into
+    #[arg(
+        long,
+        required_unless_present = "every",
+        conflicts_with = "every",
+        value_parser = clap::value_parser!(u64).range(2..)
+    )]
+    pub parts: Option<u64>,
+    /// How long each piece should be, as [[h:]mm:]ss
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub every: Option<Duration>,
+    /// Where to write the pieces, next to the file if not given
+    #[arg(short, long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+    /// MP3 bitrate in kbps for each piece's audio
+    #[arg(
+        long,
+        default_value = "192",
+        value_parser = ["96", "128", "160", "192", "256", "320"]
+    )]
+    pub audio_bitrate: String,
+}
+
+pub fn run(args: &SplitArgs) -> Res<()> {
+    let contents = Contents::read(&args.file)?;
+    let timeline = contents.timeline();
+    let pieces = cuts(args, &timeline, contents.frames);
+    if pieces.len() < 2 {
+        return Err(format!(
+            "{} is too short to split that way",
+            args.file.display()
+        )
+        .into());
+    }
+
+    let directory = match &args.output_dir {
+        Some(directory) => {
+            fs::create_dir_all(directory)?;
+            directory.clone()
+        }
+        None => args.file.parent().unwrap_or(Path::new("")).to_path_buf(),
+    };
+    let stem = args.file.file_stem().unwrap_or_default().to_string_lossy();
+    let paths: Vec<PathBuf> = (1..=pieces.len())
+        .map(|number| directory.join(format!("{stem}.{number:03}.bapple")))
+        .collect();
+
+    let titles: Vec<_> = (1..=pieces.len())
+        .map(|number| {
+            Some(format!("{} ({number}/{})", contents.title(), pieces.len()))
+        })
+        .collect();
+    let bitrate = args.audio_bitrate.parse().unwrap_or(192);
+    write_pieces(&contents, &pieces, &paths, &titles, bitrate)?;
+
+    println!("Split {} into {} files:", args.file.display(), pieces.len());
+    for path in &paths {
+        println!("  {}", path.display());
+    }
+    Ok(())
+}
+
+/// Writes each run of frames to its own file, along with its share of
+/// everything else, titled `titles`.
+pub fn write_pieces(
+    contents: &Contents,
+    pieces: &[Range<usize>],
+    paths: &[PathBuf],
+    titles: &[Option<String>],
+    bitrate: u16,
+) -> Res<()> {
+    let timeline = contents.timeline();
+    let mut writers = Vec::new();
+    for path in paths {
+        let mut writer = ArchiveWriter::create(path)?;
+        if let Some(poster) = &contents.poster {
+            writer.append_poster(poster)?;
+        }
+        if let Some(dictionary) = &contents.dictionary {
+            writer.append_dictionary(dictionary)?;
+        }
+        writers.push(writer);
+    }
+    // One pass over the frames, each going to the piece it's in, if any.
+    contents.for_each_frame(|index, frame| {
+        match pieces.iter().position(|piece| piece.contains(&index)) {
+            Some(piece) => Ok(writers[piece].append_frame(frame)?),
+            None => Ok(()),
+        }
+    })?;
+
+    let times: Vec<Range<Duration>> = pieces
+        .iter()
+        .map(|piece| timeline.start(piece.start)..timeline.start(piece.end))
+        .collect();
+    let audio = split_audio(contents, &times, bitrate)?;
+
+    for (number, mut writer) in writers.into_iter().enumerate() {
+        if let Some(mp3) = &audio {
+            writer.append_audio(&mp3[number])?;
+        }
+        let time = &times[number];
+        for (language, subtitles) in &contents.subtitles {
+            let subtitles = subtitles.between(time.start, time.end);
+            if !subtitles.is_empty() {
+                writer.append_subtitles(
+                    language,
+                    subtitles.to_srt().as_bytes(),
+                )?;
+            }
+        }
+        // Pieces of a file that had one get their own.
+        let manifest = contents.metadata.requires("manifest");
+        let metadata = piece_metadata(
+            contents,
+            pieces[number].clone(),
+            time,
+            titles[number].clone(),
+            audio.is_some(),
+        );
+        writer.append_metadata(&metadata)?;
+        if manifest {
+            writer.append_manifest()?;
+        }
+        writer.finish()?;
+    }
+
+    Ok(())
+}
+
+/// The frames of each piece, none of them empty.
+fn cuts(
+    args: &SplitArgs,
+    timeline: &Timeline,
+    frames: usize,
+) -> Vec<Range<usize>> {
+    let duration = timeline.duration();
+    let mut starts = Vec::new();
+    if let Some(every) = args.every.filter(|every| !every.is_zero()) {
+        let mut time = Duration::ZERO;
+        while time < duration {
+            starts.push(timeline.frame_at(time));
+            time += every;
+        }
+    } else if let Some(parts) = args.parts {
+        for part in 0..parts {
+            #[allow(clippy::cast_precision_loss)]
+            let time = duration.mul_f64(part as f64 / parts as f64);
+            starts.push(timeline.frame_at(time));
+        }
+    }
+    starts.push(frames);
+    starts.dedup();
+    starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+}
+
+/// Each piece's share of the audio, `None` if there's none.
+fn split_audio(
+    contents: &Contents,
+    times: &[Range<Duration>],
+    bitrate: u16,
+) -> Res<Option<Vec<Vec<u8>>>> {
+    let Some(audio) = contents.audio.clone() else {
+        return Ok(None);
+    };
+    let decoder = Decoder::try_from(Cursor::new(audio))
+        .map_err(|e| format!("{}: {e}", contents.path.display()))?;
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<f32> =
+        UniformSourceIterator::new(decoder, 2, sample_rate).collect();
+
+    // Stereo, so two samples at a time.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let sample = |time: Duration| {
+        (time.as_secs_f64() * f64::from(sample_rate)) as usize * 2
+    };
+    println!("Encoding the audio...");
+    let mut pieces = Vec::new();
+    for time in times {
+        let (start, end) = (sample(time.start), sample(time.end));
+        // Short audio gets padded, so every piece is as long as its frames.
+        let slice = samples
+            .get(start.min(samples.len())..end.min(samples.len()))
+            .unwrap_or_default();
+        let padded = slice.iter().copied().chain(repeat(0.0));
+        pieces.push(to_mp3(padded.take(end - start), sample_rate, bitrate)?);
+    }
+    Ok(Some(pieces))
+}
+
+fn piece_metadata(
+    contents: &Contents,
+    frames: Range<usize>,
+    time: &Range<Duration>,
+    title: Option<String>,
+    audio: bool,
+) -> Metadata {
+    let frametimes = &contents.frametimes[frames.clone()];
+    let frametime = frametimes[0];
+    let constant = frametimes.iter().all(|&time| time == frametime);
+
+    // Speed regions go by time at normal speed.
+    let offset: u64 = contents.frametimes[..frames.start].iter().sum();
+    let end = offset + frametimes.iter().sum::<u64>();
+    let speeds = contents
+        .metadata
+        .speeds
+        .iter()
+        .filter(|region| region.start < end && offset < region.end)
+        .map(|region| SpeedRegion {
+            start: region.start.max(offset) - offset,
+            end: region.end.min(end) - offset,
+            ..*region
+        })
+        .collect();
+    let episodes = contents
+        .metadata
+        .episodes
+        .iter()
+        .filter(|episode| {
+            episode.start < frames.end && frames.start < episode.end
+        })
+        .map(|episode| Episode {
+            title: episode.title.clone(),
+            start: episode.start.max(frames.start) - frames.start,
+            end: episode.end.min(frames.end) - frames.start,
+        })
+        .collect();
+
+    // Tracks with nothing left in this piece don't get written.
+    let subtitle_tracks = contents
+        .metadata
+        .subtitle_tracks
+        .iter()
+        .filter(|label| {
+            contents.subtitles.iter().any(|(language, subtitles)| {
+                *language == label.language
+                    && !subtitles.between(time.start, time.end).is_empty()
+            })
+        })
+        .cloned()
+        .collect();
+
+    Metadata {
+        frametime,
+        fps: 1_000_000_u64.checked_div(frametime).unwrap_or(0),
+        frametimes: if constant { Vec::new() } else { frametimes.to_vec() },
+        title,
+        speeds,
+        episodes,
+        subtitle_tracks,
+        // Every frame gets written out by itself.
+        repeats: Vec::new(),
+        required: contents
+            .metadata
+            .required
+            .iter()
+            .filter(|field| *field != "repeats")
+            .cloned()
+            .collect(),
+        audio_track: if audio {
+            contents.metadata.audio_track.clone()
+        } else {
+            None
+        },
+        ..contents.metadata.clone()
+    }
+}
@@ -0,0 +1,185 @@
+// `bplay stats`, for finding out why a file stutters: how big its frames
+// are before and after decompressing, how well they compress, which ones
+// are the biggest, and how much has to be written to the terminal every
+// second to keep up.
+
+use std::{cmp::Reverse, fs::File, io::Read, path::PathBuf, time::Duration};
+
+use tar::Archive;
+
+use crate::{
+    Res,
+    archive::{
+        Limits, entry_name, is_frame, read_dictionary, read_metadata,
+        refuse_encrypted,
+    },
+    frames::{decompress, max_frame_size},
+    info::size,
+    timeline::Timeline,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Path to a .bapple file
+    pub file: PathBuf,
+    /// Also print the sizes of every single frame
+    #[arg(long)]
+    pub per_frame: bool,
+    /// How many of the biggest frames to list
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub top: usize,
+}
+
+/// In bytes.
+#[derive(Clone, Copy)]
+struct Frame {
+    compressed: u64,
+    decompressed: u64,
+}
+
+impl Frame {
+    #[allow(clippy::cast_precision_loss)]
+    fn ratio(&self) -> f64 {
+        self.decompressed as f64 / self.compressed.max(1) as f64
+    }
+}
+
+pub fn run(args: &StatsArgs) -> Res<()> {
+    refuse_encrypted(&args.file)?;
+    let stored = read_frames(args)?;
+    if stored.is_empty() {
+        return Err(format!("{} has no frames", args.file.display()).into());
+    }
+    let metadata = read_metadata(&args.file)?.unwrap_or_default();
+    // Everything but the compressed sizes goes by what gets played.
+    let frames: Vec<Frame> =
+        match metadata.frame_map(stored.len(), Limits::default())? {
+            Some(map) => map.iter().map(|&frame| stored[frame]).collect(),
+            None => stored.clone(),
+        };
+    let timeline = Timeline::new(
+        metadata.effective_frametime(),
+        &metadata.frametimes,
+        &metadata.speeds,
+        frames.len(),
+    );
+
+    if frames.len() == stored.len() {
+        println!("Frames: {}", frames.len());
+    } else {
+        println!("Frames: {} ({} stored)", frames.len(), stored.len());
+    }
+    print_sizes("Compressed", stored.iter().map(|frame| frame.compressed));
+    print_sizes("Decompressed", frames.iter().map(|frame| frame.decompressed));
+
+    let mut ratios: Vec<f64> = frames.iter().map(Frame::ratio).collect();
+    ratios.sort_by(f64::total_cmp);
+    let percentile = |p: usize| ratios[(ratios.len() - 1) * p / 100];
+    println!(
+        "Compression ratio: {:.1}x at worst, {:.1}x at the 10th \
+         percentile, {:.1}x median, {:.1}x at the 90th, {:.1}x at best",
+        percentile(0),
+        percentile(10),
+        percentile(50),
+        percentile(90),
+        percentile(100),
+    );
+
+    let mut biggest: Vec<usize> = (0..frames.len()).collect();
+    biggest.sort_by_key(|&index| Reverse(frames[index].decompressed));
+    println!("Biggest frames:");
+    for &index in biggest.iter().take(args.top) {
+        println!(
+            "  {index}: {} ({} compressed)",
+            size(frames[index].decompressed),
+            size(frames[index].compressed)
+        );
+    }
+
+    print_bandwidth(&frames, &timeline);
+
+    if args.per_frame {
+        println!("Frame\tCompressed\tDecompressed\tRatio");
+        for (index, frame) in frames.iter().enumerate() {
+            println!(
+                "{index}\t{}\t{}\t{:.2}",
+                frame.compressed,
+                frame.decompressed,
+                frame.ratio()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn read_frames(args: &StatsArgs) -> Res<Vec<Frame>> {
+    let mut archive = Archive::new(File::open(&args.file)?);
+    let limit = max_frame_size();
+    let dictionary = read_dictionary(&args.file)?;
+    let mut frames = Vec::new();
+    let mut compressed = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry_name(&entry)?.is_some_and(|name| is_frame(&name)) {
+            compressed.clear();
+            entry.read_to_end(&mut compressed)?;
+            let decompressed = decompress(&compressed[..], &dictionary, limit)?;
+            frames.push(Frame {
+                compressed: compressed.len() as u64,
+                decompressed: decompressed.len() as u64,
+            });
+        }
+    }
+    Ok(frames)
+}
+
+fn print_sizes(what: &str, sizes: impl Iterator<Item = u64> + Clone) {
+    let total: u64 = sizes.clone().sum();
+    let count = sizes.clone().count() as u64;
+    println!(
+        "{what}: {} in all, {} on average, {} to {} per frame",
+        size(total),
+        size(total / count.max(1)),
+        size(sizes.clone().min().unwrap_or_default()),
+        size(sizes.max().unwrap_or_default()),
+    );
+}
+
+/// What the terminal has to take in every second, at most, since only
+/// the characters that changed get redrawn.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn print_bandwidth(frames: &[Frame], timeline: &Timeline) {
+    let duration = timeline.duration();
+    if duration.is_zero() {
+        println!("Bandwidth: unknown, the file doesn't say how fast it plays");
+        return;
+    }
+    let total: u64 = frames.iter().map(|frame| frame.decompressed).sum();
+    let per_second = |bytes: u64, time: Duration| {
+        size((bytes as f64 / time.as_secs_f64()) as u64)
+    };
+    // The frame needing the most, for however short it's on screen.
+    let peak = frames
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !timeline.frametime(*index).is_zero())
+        .max_by(|(a, x), (b, y)| {
+            let rate = |index: usize, frame: &Frame| {
+                frame.decompressed as f64
+                    / timeline.frametime(index).as_secs_f64()
+            };
+            rate(*a, x).total_cmp(&rate(*b, y))
+        });
+    print!("Bandwidth: {}/s on average", per_second(total, duration));
+    if let Some((index, frame)) = peak {
+        print!(
+            ", {}/s at frame {index}",
+            per_second(frame.decompressed, timeline.frametime(index))
+        );
+    }
+    println!();
+}
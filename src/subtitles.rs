@@ -0,0 +1,282 @@
+// Subtitles from SRT files, shown on the bottom rows of the video. Only
+// the text and timing are kept, formatting tags get dropped since there's
+// no way to show italics in every terminal anyway. A file can have several
+// tracks, one per language, plus the one from `--subs`.
+
+use std::{fmt::Write as _, fs, path::Path, time::Duration};
+
+use crate::Res;
+
+#[derive(Clone, Debug)]
+struct Cue {
+    start: Duration,
+    end: Duration,
+    lines: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Subtitles {
+    /// In the order they were in the file.
+    cues: Vec<Cue>,
+}
+
+impl Subtitles {
+    pub fn load(path: &Path) -> Res<Self> {
+        let content =
+            fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let subtitles = Self::parse(&String::from_utf8_lossy(&content));
+        if subtitles.cues.is_empty() {
+            return Err(
+                format!("{}: no subtitles found", path.display()).into()
+            );
+        }
+        Ok(subtitles)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    /// Blocks that don't make sense get skipped, the way most players
+    /// do it.
+    pub fn parse(content: &str) -> Self {
+        let content = content.trim_start_matches('\u{feff}');
+        let mut cues = Vec::new();
+        let mut lines = content.lines().map(str::trim_end).peekable();
+
+        while lines.peek().is_some() {
+            // A block is everything up to the next blank line.
+            let block: Vec<_> = lines
+                .by_ref()
+                .skip_while(|line| line.is_empty())
+                .take_while(|line| !line.is_empty())
+                .collect();
+
+            // The number before the timing is optional.
+            let Some(timing) =
+                block.iter().position(|line| line.contains("-->"))
+            else {
+                continue;
+            };
+            let Some((start, end)) = parse_timing(block[timing]) else {
+                continue;
+            };
+            let lines = block[timing + 1..]
+                .iter()
+                .map(|line| strip_tags(line))
+                .filter(|line| !line.is_empty())
+                .collect();
+            cues.push(Cue { start, end, lines });
+        }
+
+        Self { cues }
+    }
+
+    /// Lines to show at `position`, from every cue that's on.
+    pub fn at(&self, position: Duration) -> Vec<String> {
+        self.cues
+            .iter()
+            .filter(|cue| cue.start <= position && position < cue.end)
+            .flat_map(|cue| cue.lines.iter().cloned())
+            .collect()
+    }
+
+    /// Adds every cue from `other` after these, `by` later than it had
+    /// them.
+    pub fn append_shifted(&mut self, other: &Self, by: Duration) {
+        self.cues.extend(other.cues.iter().map(|cue| Cue {
+            start: cue.start + by,
+            end: cue.end + by,
+            lines: cue.lines.clone(),
+        }));
+    }
+
+    /// Only what's on between `start` and `end`, cut to fit and moved
+    /// back by `start`.
+    pub fn between(&self, start: Duration, end: Duration) -> Self {
+        let cues = self
+            .cues
+            .iter()
+            .filter(|cue| cue.start < end && start < cue.end)
+            .map(|cue| Cue {
+                start: cue.start.saturating_sub(start),
+                end: cue.end.min(end).saturating_sub(start),
+                lines: cue.lines.clone(),
+            })
+            .collect();
+        Self { cues }
+    }
+
+    /// Back to SRT, without whatever tags got stripped.
+    pub fn to_srt(&self) -> String {
+        let mut srt = String::new();
+        for (number, cue) in self.cues.iter().enumerate() {
+            let _ = write!(
+                srt,
+                "{}\n{} --> {}\n{}\n\n",
+                number + 1,
+                srt_time(cue.start),
+                srt_time(cue.end),
+                cue.lines.join("\n")
+            );
+        }
+        srt
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Track {
+    /// What `--sub-track` goes by, the language for embedded tracks.
+    name: String,
+    /// What gets shown when switching to it.
+    label: String,
+    subtitles: Subtitles,
+}
+
+/// Every track there is to pick from, by name.
+#[derive(Clone, Debug, Default)]
+pub struct Tracks {
+    tracks: Vec<Track>,
+}
+
+impl Tracks {
+    pub fn push(&mut self, name: String, label: String, subtitles: Subtitles) {
+        self.tracks.push(Track { name, label, subtitles });
+    }
+
+    /// Puts a track before all the others, so it's the one shown
+    /// unless another gets picked.
+    pub fn push_front(
+        &mut self,
+        name: String,
+        label: String,
+        subtitles: Subtitles,
+    ) {
+        self.tracks.insert(0, Track { name, label, subtitles });
+    }
+
+    /// Names and labels, in order.
+    pub fn names(&self) -> Vec<(String, String)> {
+        self.tracks
+            .iter()
+            .map(|track| (track.name.clone(), track.label.clone()))
+            .collect()
+    }
+
+    /// The first track in the first of `languages` there's one for.
+    pub fn preferred(&self, languages: &[String]) -> Option<&str> {
+        languages.iter().find_map(|language| {
+            self.tracks
+                .iter()
+                .find(|track| track.name.eq_ignore_ascii_case(language))
+                .map(|track| track.name.as_str())
+        })
+    }
+
+    /// The track called `name`, or the first one if there's no such
+    /// track or no name.
+    pub fn get(&self, name: Option<&str>) -> Option<&Subtitles> {
+        name.and_then(|name| self.tracks.iter().find(|t| t.name == name))
+            .or_else(|| self.tracks.first())
+            .map(|track| &track.subtitles)
+    }
+}
+
+/// Which track to show and how many milliseconds late, early if the
+/// delay is negative.
+#[derive(Clone, Copy, Debug)]
+pub struct Choice<'a> {
+    pub track: Option<&'a str>,
+    pub delay: i64,
+}
+
+/// `00:00:01,000 --> 00:00:04,000`, anything after the end time (like
+/// positioning) is ignored.
+fn parse_timing(line: &str) -> Option<(Duration, Duration)> {
+    let (start, end) = line.split_once("-->")?;
+    let end = end.split_whitespace().next()?;
+    Some((parse_time(start.trim())?, parse_time(end)?))
+}
+
+/// `HH:MM:SS,mmm`, with a dot instead of the comma too.
+fn parse_time(time: &str) -> Option<Duration> {
+    let (clock, millis) = time.split_once([',', '.']).unwrap_or((time, "0"));
+    let mut parts = clock.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) =
+        (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+    let millis: u64 = millis.parse().ok()?;
+
+    Some(
+        Duration::from_secs(hours * 3600 + minutes * 60 + seconds)
+            + Duration::from_millis(millis),
+    )
+}
+
+/// `HH:MM:SS,mmm`, the way SRT has it.
+fn srt_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        time.subsec_millis()
+    )
+}
+
+/// Drops `<i>`-style HTML tags and `{\an8}`-style ASS ones.
+fn strip_tags(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut closing = None;
+    for c in line.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => stripped.push(c),
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+        }
+    }
+    stripped.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempPath;
+
+    const SRT: &str = "\u{feff}1\r\n\
+        00:00:01,000 --> 00:00:02,500\r\n\
+        <i>Hello</i> there\r\n\
+        {\\an8}General Kenobi\r\n\
+        \r\n\
+        not a cue\r\n\
+        \r\n\
+        00:00:02.000 --> 00:00:03.000 X1:0\r\n\
+        Later\r\n";
+
+    #[test]
+    fn parses_srt() {
+        let subtitles = Subtitles::parse(SRT);
+        let at = |ms| subtitles.at(Duration::from_millis(ms));
+
+        assert!(at(999).is_empty());
+        assert_eq!(at(1000), ["Hello there", "General Kenobi"]);
+        assert_eq!(at(2000), ["Hello there", "General Kenobi", "Later"]);
+        assert_eq!(at(2500), ["Later"]);
+        assert!(at(3000).is_empty());
+    }
+
+    #[test]
+    fn refuses_files_without_subtitles() {
+        let file = TempPath::new();
+        fs::write(&file, "not\nsubtitles\n").unwrap();
+        assert!(Subtitles::load(&file).is_err());
+
+        fs::write(&file, SRT).unwrap();
+        assert!(Subtitles::load(&file).is_ok());
+    }
+}
@@ -0,0 +1,46 @@
+// Somewhere for tests to write files to, in the temporary directory, each
+// one removed once it's dropped.
+
+use std::{
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A path no other test is using, with nothing there yet, for a file or
+/// a directory.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("bplay-test-{}-{count}", process::id());
+        Self(env::temp_dir().join(name))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // Not every test gets around to writing anything, and some make
+        // a directory of it.
+        let _ =
+            fs::remove_file(&self.0).or_else(|_| fs::remove_dir_all(&self.0));
+    }
+}
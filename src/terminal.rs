@@ -0,0 +1,232 @@
+// Works out what the terminal can do, instead of assuming everything is
+// a truecolor VT100. The environment gives a first guess, then on Unix
+// the terminal gets asked directly.
+
+use std::env;
+
+/// Colors a terminal can show, or a frame uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    #[default]
+    None,
+    /// The 16 basic colors.
+    Basic,
+    /// The 256 color palette.
+    Extended,
+    TrueColor,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capabilities {
+    pub color: ColorDepth,
+    /// DEC mode 2026, which keeps big frames from tearing.
+    pub sync_output: bool,
+    /// Lets playback leave whatever was on screen untouched.
+    pub alt_screen: bool,
+    /// Whether block and braille characters show up, otherwise frames
+    /// get transliterated to ASCII.
+    pub unicode: bool,
+}
+
+/// Terminals known to do synchronized output, by `$TERM` or
+/// `$TERM_PROGRAM`. Only a guess, the terminal gets the final say.
+const SYNC_TERMINALS: [&str; 7] =
+    ["kitty", "foot", "contour", "alacritty", "wezterm", "ghostty", "iterm"];
+
+pub fn detect() -> Capabilities {
+    let term = env::var("TERM").unwrap_or_default();
+    #[allow(unused_mut)]
+    let mut capabilities = from_env(&term);
+
+    #[cfg(unix)]
+    if term != "dumb" {
+        query::run(&mut capabilities);
+    }
+
+    capabilities
+}
+
+fn from_env(term: &str) -> Capabilities {
+    // Windows consoles don't set TERM, but handle escapes fine once
+    // virtual terminal processing is on.
+    if cfg!(windows) && term.is_empty() {
+        return Capabilities {
+            color: ColorDepth::TrueColor,
+            sync_output: env::var_os("WT_SESSION").is_some(),
+            alt_screen: true,
+            unicode: true,
+        };
+    }
+
+    if term.is_empty() || term == "dumb" {
+        return Capabilities::default();
+    }
+
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    let color = if matches!(colorterm.as_str(), "truecolor" | "24bit")
+        || term.ends_with("-direct")
+    {
+        ColorDepth::TrueColor
+    } else if term.contains("256color") {
+        ColorDepth::Extended
+    } else {
+        ColorDepth::Basic
+    };
+
+    let program = env::var("TERM_PROGRAM").unwrap_or_default();
+    let sync_output = [term, &program].iter().any(|name| {
+        let name = name.to_lowercase();
+        SYNC_TERMINALS.iter().any(|known| name.contains(known))
+    });
+
+    Capabilities {
+        color,
+        sync_output,
+        alt_screen: !matches!(term, "linux" | "vt52" | "vt100" | "vt102"),
+        unicode: utf8_locale(),
+    }
+}
+
+/// Same order of precedence as the C library, unset counts as UTF-8
+/// since that's what pretty much everything is nowadays.
+fn utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .is_none_or(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// The most colorful SGR sequence in a frame.
+pub fn frame_colors(frame: &[u8]) -> ColorDepth {
+    let has = |needle: &[u8]| frame.windows(needle.len()).any(|w| w == needle);
+
+    if has(b"38;2;") || has(b"48;2;") {
+        ColorDepth::TrueColor
+    } else if has(b"38;5;") || has(b"48;5;") {
+        ColorDepth::Extended
+    } else if has(b"\x1b[3") || has(b"\x1b[4") || has(b"\x1b[9") {
+        ColorDepth::Basic
+    } else {
+        ColorDepth::None
+    }
+}
+
+#[cfg(unix)]
+mod query {
+    use std::{
+        io::{IsTerminal, Write, stdin, stdout},
+        time::{Duration, Instant},
+    };
+
+    use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+    use super::{Capabilities, ColorDepth};
+
+    /// How long to wait for an answer, slow SSH connections included.
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// Asks about synchronized output (DECRQM), then for the device
+    /// attributes (DA1). Pretty much everything answers DA1, so once
+    /// that shows up there's nothing else coming.
+    pub fn run(capabilities: &mut Capabilities) {
+        if !(stdin().is_terminal() && stdout().is_terminal()) {
+            return;
+        }
+        if enable_raw_mode().is_err() {
+            return;
+        }
+
+        let mut reply = Vec::new();
+        let mut out = stdout();
+        if out
+            .write_all(b"\x1b[?2026$p\x1b[c")
+            .and_then(|()| out.flush())
+            .is_ok()
+        {
+            let deadline = Instant::now() + TIMEOUT;
+            while !answered(&reply) {
+                let Some(remaining) =
+                    deadline.checked_duration_since(Instant::now())
+                else {
+                    break;
+                };
+                if !read(&mut reply, remaining) {
+                    break;
+                }
+            }
+        }
+
+        let _ = disable_raw_mode();
+        parse(&String::from_utf8_lossy(&reply), capabilities);
+    }
+
+    fn sequences(reply: &str) -> impl Iterator<Item = &str> {
+        reply.split('\x1b').filter_map(|s| s.strip_prefix("[?"))
+    }
+
+    fn answered(reply: &[u8]) -> bool {
+        sequences(&String::from_utf8_lossy(reply)).any(|s| s.ends_with('c'))
+    }
+
+    fn parse(reply: &str, capabilities: &mut Capabilities) {
+        let mut sync_answer = None;
+        let mut attributes = None;
+
+        for sequence in sequences(reply) {
+            if let Some(mode) = sequence
+                .strip_prefix("2026;")
+                .and_then(|s| s.strip_suffix("$y"))
+            {
+                // Set, reset or permanently set. 0 is unknown and 4 is
+                // permanently reset.
+                sync_answer = Some(matches!(mode, "1" | "2" | "3"));
+            } else if let Some(list) = sequence.strip_suffix('c') {
+                attributes = Some(list.split(';').any(|a| a == "22"));
+            }
+        }
+
+        // No answer at all says nothing, but answering DA1 while
+        // ignoring DECRQM means the mode isn't there.
+        let Some(ansi_color) = attributes else {
+            return;
+        };
+        capabilities.sync_output = sync_answer.unwrap_or(false);
+        if ansi_color {
+            capabilities.color = capabilities.color.max(ColorDepth::Basic);
+        }
+    }
+
+    /// Waits up to `timeout` for input and appends whatever came in.
+    /// Goes around `Stdin`, whose buffering would hide bytes from `poll`.
+    fn read(reply: &mut Vec<u8>, timeout: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        if unsafe { libc::poll(&raw mut fd, 1, timeout) } <= 0 {
+            return false;
+        }
+
+        let mut buffer = [0u8; 256];
+        let read = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        match usize::try_from(read) {
+            Ok(read) if read > 0 => {
+                reply.extend_from_slice(&buffer[..read]);
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Maps frames to points in time and back.
+// Most files use a constant frametime, but animations converted from
+// GIF/APNG can give every frame its own duration, and speed regions from
+// the metadata stretch or squash parts of the video.
+
+use std::{iter::repeat_n, time::Duration};
+
+use crate::metadata::SpeedRegion;
+
+pub struct Timeline {
+    frametime: Duration,
+    /// Start of each frame, plus the total duration as the last entry.
+    /// Empty when every frame lasts `frametime`.
+    starts: Vec<Duration>,
+    length: usize,
+    speeds: Vec<SpeedRegion>,
+}
+
+impl Timeline {
+    /// `frametimes` is ignored unless it has exactly one entry per frame.
+    pub fn new(
+        frametime: u64,
+        frametimes: &[u64],
+        speeds: &[SpeedRegion],
+        length: usize,
+    ) -> Self {
+        let mut timeline = Self {
+            frametime: Duration::from_micros(frametime),
+            starts: Vec::new(),
+            length,
+            // Anything else would stop time or run it backwards.
+            speeds: speeds
+                .iter()
+                .filter(|region| region.speed.is_finite() && region.speed > 0.0)
+                .copied()
+                .collect(),
+        };
+
+        if length != 0 && frametimes.len() == length {
+            timeline
+                .build(frametimes.iter().map(|&t| Duration::from_micros(t)));
+        } else {
+            timeline.build_constant();
+        }
+        timeline
+    }
+
+    /// Forces a constant frametime, dropping per-frame durations. Speed
+    /// regions still apply.
+    pub fn set_frametime(&mut self, frametime: Duration) {
+        self.frametime = frametime;
+        self.build_constant();
+    }
+
+    /// Only needs `starts` if there are speed regions to apply.
+    fn build_constant(&mut self) {
+        self.starts.clear();
+        if !self.speeds.is_empty() && !self.frametime.is_zero() {
+            self.build(repeat_n(self.frametime, self.length));
+        }
+    }
+
+    /// Fills `starts` from the duration of every frame at normal speed.
+    fn build(&mut self, frametimes: impl Iterator<Item = Duration>) {
+        let mut elapsed = Duration::ZERO;
+        // Where regions start and end.
+        let mut unscaled = Duration::ZERO;
+
+        self.starts.clear();
+        self.starts.reserve(self.length + 1);
+        self.starts.push(elapsed);
+        for frametime in frametimes {
+            elapsed += frametime.div_f64(self.speed_at(unscaled));
+            unscaled += frametime;
+            self.starts.push(elapsed);
+        }
+    }
+
+    /// Frames take on the speed of wherever they start.
+    #[allow(clippy::cast_possible_truncation)]
+    fn speed_at(&self, time: Duration) -> f64 {
+        let time = time.as_micros() as u64;
+        self.speeds
+            .iter()
+            .find(|region| (region.start..region.end).contains(&time))
+            .map_or(1.0, |region| region.speed)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.frametime.is_zero() && self.starts.is_empty()
+    }
+
+    /// How long `frame` stays on screen.
+    pub fn frametime(&self, frame: usize) -> Duration {
+        match self.starts.get(frame..=frame + 1) {
+            Some([start, end]) => end.saturating_sub(*start),
+            _ => self.frametime,
+        }
+    }
+
+    /// When `frame` shows up, counting from the start.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn start(&self, frame: usize) -> Duration {
+        match self.starts.get(frame) {
+            Some(start) => *start,
+            None => self.frametime * frame.min(self.length) as u32,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn duration(&self) -> Duration {
+        match self.starts.last() {
+            Some(total) => *total,
+            None => self.frametime * self.length as u32,
+        }
+    }
+
+    /// The frame that should be on screen at `time`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn frame_at(&self, time: Duration) -> usize {
+        if self.starts.is_empty() {
+            if self.frametime.is_zero() {
+                return 0;
+            }
+            return time.div_duration_f64(self.frametime).round() as usize;
+        }
+        self.starts.partition_point(|start| *start <= time).saturating_sub(1)
+    }
+}
+
+/// `mm:ss`, or `h:mm:ss` for the long stuff.
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// The other way around, also taking plain seconds, with or without a
+/// fraction.
+pub fn parse_time(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid time {s}, expected [[h:]mm:]ss");
+    let mut parts = s.rsplit(':');
+    let seconds: f64 =
+        parts.next().unwrap_or_default().parse().map_err(|_| invalid())?;
+
+    let mut total =
+        Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?;
+    for unit in [60, 3600] {
+        let Some(part) = parts.next() else { break };
+        let count: u64 = part.parse().map_err(|_| invalid())?;
+        total = total
+            .saturating_add(Duration::from_secs(count.saturating_mul(unit)));
+    }
+
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
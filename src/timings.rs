@@ -0,0 +1,141 @@
+// What --timings reports once playback is over: how many frames made it to
+// the screen, how long getting them there took, and how far they drifted
+// from the audio.
+
+use std::{fs, io, path::Path, time::Duration};
+
+use serde::Serialize;
+
+#[derive(Default)]
+pub struct Timings {
+    pub frames: usize,
+    /// Skipped to catch up with the audio or the clock.
+    pub dropped: usize,
+    /// Getting a frame ready: decompressing, filters and subtitles.
+    decode: Stat,
+    /// Writing it out to the terminal.
+    draw: Stat,
+    /// How far the frames were ahead of the audio every time they got
+    /// synced to it, negative when they were behind.
+    drift: Stat,
+}
+
+/// In milliseconds.
+#[derive(Default)]
+struct Stat {
+    count: u32,
+    sum: f64,
+    /// The furthest from zero.
+    max: f64,
+}
+
+impl Stat {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value.abs());
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    fn average(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum / f64::from(self.count) }
+    }
+
+    /// `None` if there was nothing to measure.
+    fn summary(&self) -> Option<Summary> {
+        (self.count > 0).then(|| Summary {
+            average: thousandths(self.average()),
+            max: thousandths(self.max),
+        })
+    }
+}
+
+/// What `--timings-json` writes.
+#[derive(Serialize)]
+struct Report {
+    frames: usize,
+    dropped: usize,
+    decode_ms: Option<Summary>,
+    draw_ms: Option<Summary>,
+    drift_ms: Option<Summary>,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    average: f64,
+    max: f64,
+}
+
+/// Anything finer than a microsecond is just noise.
+fn thousandths(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Timings {
+    pub fn frame(&mut self, decode: Duration, draw: Duration) {
+        self.frames += 1;
+        self.decode.add(millis(decode));
+        self.draw.add(millis(draw));
+    }
+
+    /// Where the frames were against where the audio was, both in the
+    /// file's own time.
+    pub fn drift(&mut self, video: Duration, audio: Duration) {
+        self.drift.add(millis(video) - millis(audio));
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.frames += other.frames;
+        self.dropped += other.dropped;
+        self.decode.merge(&other.decode);
+        self.draw.merge(&other.draw);
+        self.drift.merge(&other.drift);
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Frames shown: {} ({} dropped to keep up)",
+            self.frames, self.dropped
+        );
+        for (what, stat) in
+            [("Decoding", &self.decode), ("Drawing", &self.draw)]
+        {
+            println!(
+                "{what}: {:.2} ms on average, {:.2} ms at most",
+                stat.average(),
+                stat.max
+            );
+        }
+        if self.drift.count == 0 {
+            println!("Audio drift: no audio");
+        } else {
+            println!(
+                "Audio drift: {:+.2} ms on average, {:.2} ms at most",
+                self.drift.average(),
+                self.drift.max
+            );
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        let report = Report {
+            frames: self.frames,
+            dropped: self.dropped,
+            decode_ms: self.decode.summary(),
+            draw_ms: self.draw.summary(),
+            drift_ms: self.drift.summary(),
+        };
+        let mut json = serde_json::to_string(&report)?;
+        json.push('\n');
+        fs::write(path, json)
+    }
+}
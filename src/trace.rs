@@ -0,0 +1,22 @@
+// --trace: what's instrumented with `tracing` (loading, the frame loop,
+// syncing and audio) written out as a Chrome trace, to open in Perfetto
+// or chrome://tracing when playback is slow somewhere and it isn't clear
+// why.
+
+use std::{fs::File, path::Path};
+
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::Res;
+
+/// Records until the guard is dropped, which finishes the file.
+pub fn start(path: &Path) -> Res<FlushGuard> {
+    let file = File::create(path)?;
+    let (layer, guard) =
+        ChromeLayerBuilder::new().writer(file).include_args(true).build();
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry().with(layer),
+    )?;
+    Ok(guard)
+}
@@ -0,0 +1,75 @@
+// `bplay trim`, keeping only part of a .bapple file. The frames are copied
+// as they are, so only the audio (cut to match) gets encoded again.
+
+use std::{path::PathBuf, slice, time::Duration};
+
+use crate::{
+    Res, archive::Contents, concat::same_file, split::write_pieces,
+    timeline::parse_time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct TrimArgs {
+    /// The .bapple file to trim
+    pub file: PathBuf,
+    /// Where to start, as [[h:]mm:]ss
+    #[arg(
+        long,
+        value_name = "TIME",
+        default_value = "0",
+        value_parser = parse_time
+    )]
+    pub start: Duration,
+    /// Where to stop, the end of the file if not given
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub end: Option<Duration>,
+    /// Where to write the trimmed file
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// MP3 bitrate in kbps for the trimmed audio
+    #[arg(
+        long,
+        default_value = "192",
+        value_parser = ["96", "128", "160", "192", "256", "320"]
+    )]
+    pub audio_bitrate: String,
+}
+
+pub fn run(args: &TrimArgs) -> Res<()> {
+    if same_file(&args.file, &args.output) {
+        return Err(format!(
+            "{} is the file being trimmed",
+            args.output.display()
+        )
+        .into());
+    }
+    let contents = Contents::read(&args.file)?;
+    let timeline = contents.timeline();
+    let start = timeline.frame_at(args.start);
+    let end = args
+        .end
+        .map_or(contents.frames, |end| timeline.frame_at(end))
+        .min(contents.frames);
+    if start >= end {
+        return Err(format!(
+            "Nothing left of {} between those times",
+            args.file.display()
+        )
+        .into());
+    }
+
+    let bitrate = args.audio_bitrate.parse().unwrap_or(192);
+    write_pieces(
+        &contents,
+        slice::from_ref(&(start..end)),
+        slice::from_ref(&args.output),
+        slice::from_ref(&contents.metadata.title),
+        bitrate,
+    )?;
+    println!(
+        "Kept frames {start}..{end} of {} in {}",
+        args.file.display(),
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,255 @@
+// The `--tui` layout: the video in a box, with the playlist next to it
+// and a progress gauge and status bar underneath. Frames come in as the
+// same escape sequence soup the plain mode writes straight out, so their
+// colors get turned back into styles here.
+
+use std::io::{self, Stdout, stdout};
+
+use ratatui::{
+    Terminal,
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, Gauge, List, ListState, Paragraph},
+};
+
+use crate::{controls::Status, timeline::format_time};
+
+/// The playlist never takes more than this many columns.
+const SIDEBAR_WIDTH: u16 = 30;
+
+/// The 16 basic colors, in ANSI order.
+const BASIC: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+/// Everything that goes on screen besides the frame itself.
+pub struct View<'a> {
+    pub title: &'a str,
+    pub status: &'a Status,
+    pub playlist: &'a [String],
+    pub current: usize,
+    pub repeat: &'a str,
+    /// The measured framerate, `None` unless the stats are on.
+    pub fps: Option<f64>,
+    pub notice: Option<&'a str>,
+}
+
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    /// Set when something else drew over the screen, so everything gets
+    /// drawn again instead of just what changed.
+    stale: bool,
+}
+
+impl Tui {
+    pub fn new() -> io::Result<Self> {
+        let terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        Ok(Self { terminal, stale: true })
+    }
+
+    pub fn redraw(&mut self) {
+        self.stale = true;
+    }
+
+    /// Draws a frame with everything around it, returning where the
+    /// gauge ended up so clicks on it can seek.
+    pub fn draw(&mut self, frame: &[u8], view: &View) -> io::Result<Rect> {
+        // The screen was already cleared, ratatui only has to forget what
+        // it drew. Swapping leaves both buffers empty, since the one that
+        // was drawn gets reset. Unlike `Terminal::clear` this doesn't ask
+        // the terminal where the cursor is, whose answer would get mixed
+        // up with key presses.
+        if std::mem::take(&mut self.stale) {
+            self.terminal.swap_buffers();
+        }
+
+        let video = parse_frame(frame);
+        let mut gauge_area = Rect::default();
+        self.terminal.draw(|screen| {
+            let [main, gauge, status] = Layout::vertical([
+                Constraint::Min(3),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ])
+            .areas(screen.area());
+
+            // Not much point in a playlist of one.
+            let [video_area, sidebar] = if view.playlist.len() > 1 {
+                let width = SIDEBAR_WIDTH.min(main.width / 4);
+                Layout::horizontal([
+                    Constraint::Min(1),
+                    Constraint::Length(width),
+                ])
+                .areas(main)
+            } else {
+                [main, Rect::default()]
+            };
+
+            screen.render_widget(
+                Paragraph::new(video)
+                    .block(Block::bordered().title(view.title)),
+                video_area,
+            );
+            if !sidebar.is_empty() {
+                let list = List::new(view.playlist.iter().map(String::as_str))
+                    .block(Block::bordered().title("Playlist"))
+                    .highlight_style(Modifier::REVERSED);
+                let mut state =
+                    ListState::default().with_selected(Some(view.current));
+                screen.render_stateful_widget(list, sidebar, &mut state);
+            }
+
+            let (elapsed, total) = (view.status.elapsed, view.status.total);
+            let progress = if total.is_zero() {
+                0.0
+            } else {
+                elapsed.div_duration_f64(total).clamp(0.0, 1.0)
+            };
+            screen.render_widget(
+                Gauge::default()
+                    .ratio(progress)
+                    .label(format!(
+                        "{} / {}",
+                        format_time(elapsed),
+                        format_time(total)
+                    ))
+                    .gauge_style(Style::new().fg(Color::Cyan))
+                    .use_unicode(true),
+                gauge,
+            );
+            gauge_area = gauge;
+
+            screen.render_widget(
+                Paragraph::new(status_line(view))
+                    .style(Style::new().add_modifier(Modifier::REVERSED)),
+                status,
+            );
+        })?;
+        Ok(gauge_area)
+    }
+}
+
+fn status_line<'a>(view: &View<'a>) -> Line<'a> {
+    let mut parts = vec![format!("repeat: {}", view.repeat)];
+    if let Some(fps) = view.fps {
+        if let Some((frame, frames)) = view.status.frame {
+            parts.push(format!("frame {frame}/{frames}"));
+        }
+        parts.push(format!("{fps:.1} fps"));
+        if view.status.frame.is_some() {
+            parts.push(format!("{} dropped", view.status.dropped));
+            if view.status.stalls > 0 {
+                parts.push(format!("{} stalled", view.status.stalls));
+            }
+        }
+    }
+    parts.push("? for help".to_string());
+
+    let mut line = Line::from(format!(" {} ", parts.join(" | ")));
+    if let Some(notice) = view.notice {
+        line.push_span(Span::styled(format!("| {notice} "), Modifier::BOLD));
+    }
+    line
+}
+
+/// Turns a frame back into styled text, keeping the colors and dropping
+/// any other escape sequences.
+pub fn parse_frame(frame: &[u8]) -> Text<'static> {
+    let frame = String::from_utf8_lossy(frame);
+    let mut style = Style::new();
+    let mut lines = Vec::new();
+
+    for row in frame.lines() {
+        let mut spans = Vec::new();
+        let mut text = String::new();
+        let mut chars = row.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    if chars.next() != Some('[') {
+                        continue;
+                    }
+                    let mut parameters = String::new();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            if c == 'm' {
+                                if !text.is_empty() {
+                                    spans.push(Span::styled(
+                                        std::mem::take(&mut text),
+                                        style,
+                                    ));
+                                }
+                                style = sgr(style, &parameters);
+                            }
+                            break;
+                        }
+                        parameters.push(c);
+                    }
+                }
+                '\r' => {}
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            spans.push(Span::styled(text, style));
+        }
+        lines.push(Line::from(spans));
+    }
+    Text::from(lines)
+}
+
+fn sgr(mut style: Style, parameters: &str) -> Style {
+    let mut parameters =
+        parameters.split(';').map(|p| p.parse::<u8>().unwrap_or(0));
+
+    while let Some(parameter) = parameters.next() {
+        match parameter {
+            0 => style = Style::new(),
+            1 => style = style.add_modifier(Modifier::BOLD),
+            7 => style = style.add_modifier(Modifier::REVERSED),
+            22 => style = style.remove_modifier(Modifier::BOLD),
+            27 => style = style.remove_modifier(Modifier::REVERSED),
+            30..=37 => style = style.fg(BASIC[usize::from(parameter - 30)]),
+            90..=97 => style = style.fg(BASIC[usize::from(parameter - 82)]),
+            40..=47 => style = style.bg(BASIC[usize::from(parameter - 40)]),
+            100..=107 => style = style.bg(BASIC[usize::from(parameter - 92)]),
+            39 => style = style.fg(Color::Reset),
+            49 => style = style.bg(Color::Reset),
+            38 | 48 => {
+                let color = match parameters.next() {
+                    Some(5) => Color::Indexed(parameters.next().unwrap_or(0)),
+                    Some(2) => {
+                        let mut channel = || parameters.next().unwrap_or(0);
+                        Color::Rgb(channel(), channel(), channel())
+                    }
+                    _ => continue,
+                };
+                style = if parameter == 38 {
+                    style.fg(color)
+                } else {
+                    style.bg(color)
+                };
+            }
+            _ => {}
+        }
+    }
+    style
+}
@@ -0,0 +1,241 @@
+// For archives that have audio but no frames: instead of exiting,
+// draw something driven by the samples being played.
+
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    io::Write,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use rodio::{Source, source::SeekError};
+
+/// Samples kept around for drawing, per channel.
+const WINDOW: usize = 2048;
+/// Samples collected before taking the lock.
+const BATCH: usize = 256;
+
+pub const FRAMETIME: Duration = Duration::from_micros(33_333);
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Visualizer {
+    /// Frequency spectrum
+    #[default]
+    Bars,
+    /// Raw waveform
+    Scope,
+}
+
+pub type Samples = Arc<Mutex<VecDeque<f32>>>;
+
+/// Passes samples through untouched, keeping a copy of the most recent
+/// ones (first channel only) for the visualizer.
+pub struct Tap<S> {
+    inner: S,
+    samples: Samples,
+    batch: Vec<f32>,
+    position: usize,
+}
+
+impl<S: Source> Tap<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(WINDOW))),
+            batch: Vec::with_capacity(BATCH),
+            position: 0,
+        }
+    }
+
+    pub fn samples(&self) -> Samples {
+        Arc::clone(&self.samples)
+    }
+
+    fn flush(&mut self) {
+        let mut samples =
+            self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        samples.extend(self.batch.drain(..));
+        let excess = samples.len().saturating_sub(WINDOW);
+        samples.drain(..excess);
+    }
+}
+
+impl<S: Source> Iterator for Tap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+
+        if self
+            .position
+            .is_multiple_of(usize::from(self.inner.channels().max(1)))
+        {
+            self.batch.push(sample);
+            if self.batch.len() == BATCH {
+                self.flush();
+            }
+        }
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl<S: Source> Source for Tap<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+/// Draws one frame of the visualizer, sized to the terminal.
+pub fn draw<W: Write>(
+    w: &mut W,
+    visualizer: Visualizer,
+    samples: &Samples,
+    sample_rate: u32,
+) -> std::io::Result<()> {
+    let (columns, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    let (columns, rows) = (usize::from(columns), rows.max(1));
+
+    let samples = samples
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .copied()
+        .collect::<Vec<_>>();
+
+    let heights = match visualizer {
+        Visualizer::Bars => spectrum(&samples, columns, sample_rate),
+        Visualizer::Scope => waveform(&samples, columns),
+    };
+
+    let mut frame = String::with_capacity(columns * usize::from(rows) * 3);
+    for row in 0..rows {
+        if row != 0 {
+            frame.push('\n');
+        }
+        // How far up from the bottom this row is, from 0 to 1.
+        let level = f32::from(rows - row) / f32::from(rows);
+        let next_level = f32::from(rows - row - 1) / f32::from(rows);
+
+        for &height in &heights {
+            frame.push(match visualizer {
+                Visualizer::Bars if height >= level => '█',
+                Visualizer::Bars if height > next_level => '▄',
+                Visualizer::Scope if height <= level && height > next_level => {
+                    '•'
+                }
+                _ => ' ',
+            });
+        }
+    }
+
+    w.write_all(frame.as_bytes())
+}
+
+/// Loudness of log-spaced frequency bands, from 0 to 1, one per column.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn spectrum(samples: &[f32], columns: usize, sample_rate: u32) -> Vec<f32> {
+    // Hann window, to keep loud bands from bleeding into their neighbours.
+    let mut bins = vec![(0.0, 0.0); WINDOW];
+    for (i, (bin, sample)) in bins.iter_mut().zip(samples).enumerate() {
+        let hann = 0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos();
+        *bin = (sample * hann, 0.0);
+    }
+    fft(&mut bins);
+
+    let sample_rate = sample_rate as f32;
+    let (low, high) = (40.0_f32, (sample_rate / 2.0).min(16_000.0));
+    let bin_of = |frequency: f32| {
+        ((frequency * WINDOW as f32 / sample_rate) as usize).min(WINDOW / 2)
+    };
+
+    (0..columns)
+        .map(|column| {
+            let from = column as f32 / columns as f32;
+            let to = (column + 1) as f32 / columns as f32;
+            let first = bin_of(low * (high / low).powf(from));
+            let last = bin_of(low * (high / low).powf(to)).max(first + 1);
+
+            // A full scale sine peaks at a quarter of the window
+            // once it goes through Hann.
+            let amplitude = bins[first..last]
+                .iter()
+                .map(|(re, im)| re.hypot(*im) * 4.0 / WINDOW as f32)
+                .fold(0.0, f32::max);
+            let decibels = 20.0 * amplitude.log10();
+            ((decibels + 60.0) / 60.0).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// In-place radix-2 FFT over `(real, imaginary)` pairs.
+/// The length has to be a power of two.
+#[allow(clippy::cast_precision_loss)]
+fn fft(buffer: &mut [(f32, f32)]) {
+    let n = buffer.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j ^= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (ar, ai) = buffer[start + k];
+                let (br, bi) = buffer[start + k + len / 2];
+                let (tr, ti) = (br * cos - bi * sin, br * sin + bi * cos);
+                buffer[start + k] = (ar + tr, ai + ti);
+                buffer[start + k + len / 2] = (ar - tr, ai - ti);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Sample values mapped to 0 (bottom) to 1 (top), one per column.
+fn waveform(samples: &[f32], columns: usize) -> Vec<f32> {
+    (0..columns)
+        .map(|column| {
+            let sample = samples
+                .get(column * samples.len() / columns.max(1))
+                .copied()
+                .unwrap_or(0.0);
+            f32::midpoint(sample.clamp(-1.0, 1.0), 1.0)
+        })
+        .collect()
+}
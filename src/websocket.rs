@@ -0,0 +1,336 @@
+// The server side of WebSockets (RFC 6455), as far as `bplay serve` needs
+// it: the handshake, sending whole messages, and reading the small text
+// ones clients send, along with their pings. Clients that break the
+// protocol get closed on with 1002.
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    net::{IpAddr, SocketAddr},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1::{Digest, Sha1};
+
+use crate::Res;
+
+/// What every key gets hashed along with in the handshake.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The most a request's headers get to take up.
+const MAX_HEADERS: u64 = 16 * 1024;
+/// Anything bigger a client sends gets skipped.
+const MAX_RECEIVED: u64 = 16 * 1024;
+/// The most pings and other control frames get to carry.
+const MAX_CONTROL: u64 = 125;
+/// What this machine goes by, whatever it's listening on.
+const LOOPBACK: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+/// The status to close with when a client breaks the protocol.
+pub const PROTOCOL_ERROR: u16 = 1002;
+
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The answer to a ping, with what came with it.
+    Pong(Vec<u8>),
+    /// Hangs up, saying why with a status like `PROTOCOL_ERROR`.
+    Close(u16),
+}
+
+/// What clients send that needs something done about it.
+pub enum Received {
+    Text(String),
+    /// To be answered with a pong.
+    Ping(Vec<u8>),
+}
+
+/// The start of an HTTP request.
+pub struct Request {
+    pub method: String,
+    /// With the query, if there is one.
+    pub path: String,
+    /// `Sec-WebSocket-Key`, if it asks to be a WebSocket.
+    pub key: Option<String>,
+    pub host: Option<String>,
+    /// Where the page asking is from, if it's a browser asking.
+    pub origin: Option<String>,
+    pub authorization: Option<String>,
+}
+
+impl Request {
+    /// Whether `Host` says it's for the server listening on `address`: one
+    /// of the names this machine goes by, or an address it's listening on.
+    /// Pages can have browsers send requests for names they own to
+    /// anywhere they like, by pointing them there (DNS rebinding), but not
+    /// with these.
+    pub fn to_here(&self, address: SocketAddr) -> bool {
+        let Some(host) = self.host.as_deref() else {
+            return false;
+        };
+        // Browsers leave out the port when it's 80.
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.ends_with(']') => (name, port),
+            _ => (host, "80"),
+        };
+        if port.parse() != Ok(address.port()) {
+            return false;
+        }
+        let ip = name.trim_start_matches('[').trim_end_matches(']');
+        LOOPBACK.iter().any(|here| here.eq_ignore_ascii_case(name))
+            || ip.parse::<IpAddr>().is_ok_and(|ip| {
+                ip == address.ip() || address.ip().is_unspecified()
+            })
+    }
+
+    /// Whether it's from a page served from here, or not from a page at
+    /// all. Browsers let any page send requests anywhere, localhost
+    /// included, but say where it's from.
+    pub fn same_origin(&self) -> bool {
+        let Some(origin) = &self.origin else {
+            return true;
+        };
+        let authority = origin.split_once("://").map(|(_, rest)| rest);
+        authority
+            .zip(self.host.as_deref())
+            .is_some_and(|(origin, host)| origin.eq_ignore_ascii_case(host))
+    }
+}
+
+/// Reads the request line and the headers.
+pub fn read_request(reader: &mut impl BufRead) -> Res<Request> {
+    let mut reader = reader.take(MAX_HEADERS);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(path)) = (words.next(), words.next()) else {
+        return Err("not an HTTP request".into());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut key = None;
+    let (mut host, mut origin, mut authorization) = (None, None, None);
+    let mut upgrade = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(value.to_string()),
+            "host" => host = Some(value.to_string()),
+            "origin" => origin = Some(value.to_string()),
+            "authorization" => authorization = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let key = key.filter(|_| upgrade);
+    Ok(Request { method, path, key, host, origin, authorization })
+}
+
+/// Agrees to a request with `key` to be a WebSocket.
+pub fn accept(w: &mut impl Write, key: &str) -> io::Result<()> {
+    let accept = STANDARD.encode(Sha1::digest(format!("{key}{GUID}")));
+    write!(
+        w,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+    w.flush()
+}
+
+/// Sends a whole message as one frame, unmasked since it's from the
+/// server. Gives back how many bytes that took.
+#[allow(clippy::cast_possible_truncation)]
+pub fn send(w: &mut impl Write, message: &Message) -> io::Result<usize> {
+    let status;
+    let (opcode, payload) = match message {
+        Message::Text(text) => (0x1, text.as_bytes()),
+        Message::Binary(data) => (0x2, data.as_slice()),
+        Message::Pong(data) => (0xa, data.as_slice()),
+        Message::Close(code) => {
+            status = code.to_be_bytes();
+            (0x8, &status[..])
+        }
+    };
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => header.push(length as u8),
+        length @ 126..=0xffff => {
+            header.push(126);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            header.push(127);
+            header.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    w.write_all(&header)?;
+    w.write_all(payload)?;
+    w.flush()?;
+    Ok(header.len() + payload.len())
+}
+
+/// Reads the next text message or ping a client sends, `None` once it says
+/// it's leaving. Anything else it sends gets skipped, as do messages in more
+/// than one frame. Frames that break the protocol, like unmasked ones or
+/// pings too big or in pieces, are an `InvalidData` error.
+#[allow(clippy::cast_possible_truncation)]
+pub fn receive(reader: &mut impl Read) -> io::Result<Option<Received>> {
+    loop {
+        let mut header = [0; 2];
+        reader.read_exact(&mut header)?;
+        let length = match header[1] & 0x7f {
+            126 => {
+                let mut length = [0; 2];
+                reader.read_exact(&mut length)?;
+                u64::from(u16::from_be_bytes(length))
+            }
+            127 => {
+                let mut length = [0; 8];
+                reader.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => u64::from(length),
+        };
+        // Clients mask everything they send.
+        if header[1] & 0x80 == 0 {
+            return Err(invalid("the client sent an unmasked frame"));
+        }
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        let (fin, opcode) = (header[0] & 0x80 != 0, header[0] & 0x0f);
+        // Control frames, which come whole and small.
+        if opcode & 0x8 != 0 && (!fin || length > MAX_CONTROL) {
+            return Err(invalid("the client sent a broken control frame"));
+        }
+        let wanted = matches!(opcode, 0x1 | 0x9);
+        if !wanted || !fin || length > MAX_RECEIVED {
+            io::copy(&mut reader.take(length), &mut io::sink())?;
+            // Close.
+            if opcode == 0x8 {
+                return Ok(None);
+            }
+            continue;
+        }
+
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        return Ok(Some(if opcode == 0x9 {
+            Received::Ping(payload)
+        } else {
+            Received::Text(String::from_utf8_lossy(&payload).into_owned())
+        }));
+    }
+}
+
+fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn accepts_the_rfc_example() {
+        let mut response = Vec::new();
+        accept(&mut response, "dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains(
+            "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+        ));
+    }
+
+    /// A frame the way clients send them, masked.
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![0x80 | opcode];
+        if let Ok(length @ 0..=125) = u8::try_from(payload.len()) {
+            frame.push(0x80 | length);
+        } else {
+            let length = u16::try_from(payload.len()).unwrap();
+            frame.push(0xfe);
+            frame.extend(length.to_be_bytes());
+        }
+        frame.extend(mask);
+        frame.extend(
+            payload.iter().zip(mask.iter().cycle()).map(|(a, b)| a ^ b),
+        );
+        frame
+    }
+
+    #[test]
+    fn reads_texts_and_pings() {
+        let mut stream = masked(0x2, b"skipped");
+        stream.extend(masked(0x1, b"Hello"));
+        stream.extend(masked(0x9, b"are you there"));
+        stream.extend(masked(0x8, b""));
+        let mut reader = Cursor::new(stream);
+        let Some(Received::Text(text)) = receive(&mut reader).unwrap() else {
+            panic!("expected a text message");
+        };
+        assert_eq!(text, "Hello");
+        let Some(Received::Ping(ping)) = receive(&mut reader).unwrap() else {
+            panic!("expected a ping");
+        };
+        assert_eq!(ping, b"are you there");
+        assert!(receive(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn knows_where_requests_are_for() {
+        let request = |host: &str| Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            key: None,
+            host: Some(host.to_string()),
+            origin: None,
+            authorization: None,
+        };
+        let local = "127.0.0.1:8080".parse().unwrap();
+        for host in ["localhost:8080", "127.0.0.1:8080", "[::1]:8080"] {
+            assert!(request(host).to_here(local), "{host}");
+        }
+        for host in ["evil.example:8080", "localhost:80", "localhost"] {
+            assert!(!request(host).to_here(local), "{host}");
+        }
+        let everywhere = "0.0.0.0:80".parse().unwrap();
+        assert!(request("192.168.1.5").to_here(everywhere));
+        assert!(!request("evil.example").to_here(everywhere));
+    }
+
+    #[test]
+    fn refuses_broken_frames() {
+        let mut unmasked = masked(0x1, b"Hello");
+        unmasked[1] &= 0x7f;
+        let mut fragmented = masked(0x9, b"are you");
+        fragmented[0] &= 0x7f;
+        let oversized = masked(0x9, &[0; 126]);
+        for frame in [unmasked, fragmented, oversized] {
+            let error = receive(&mut Cursor::new(frame)).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn sends_pongs() {
+        let mut sent = Vec::new();
+        send(&mut sent, &Message::Pong(b"hi".to_vec())).unwrap();
+        assert_eq!(sent, b"\x8a\x02hi");
+    }
+
+    #[test]
+    fn closes_with_a_status() {
+        let mut sent = Vec::new();
+        send(&mut sent, &Message::Close(PROTOCOL_ERROR)).unwrap();
+        assert_eq!(sent, b"\x88\x02\x03\xea");
+    }
+}